@@ -0,0 +1,33 @@
+#!/bin/sh
+# 実際のサーバーから API の応答を取り、テスト用の fixture にする。
+#
+#   MI_TOKEN=... fixtures/capture.sh <software> <host> <note_id> <file_id>
+#
+# ノートは `notes/show` 、その作者は `users/show` 、ファイルは `drive/files/show` で取る。
+# ファイルはトークンのアカウントのドライブにあるものを指定する。
+# 手を加えるのはホスト名だけで、`<software>.example` に置き換える。
+# 書き出した後に、公開したくない本文や名前が残っていないか目で確かめること。
+set -eu
+
+software=$1
+host=$2
+note_id=$3
+file_id=$4
+dir=$(dirname "$0")/$software
+
+# v12 はヘッダーのトークンを読まないので、本文の `i` で渡す。
+api() {
+    curl -sSf "https://$host/api/$1" \
+        -H 'Content-Type: application/json' \
+        -d "$(printf '%s' "$2" | jq -c --arg i "$MI_TOKEN" '. + {i: $i}')"
+}
+
+anonymize() {
+    sed "s/$(printf '%s' "$host" | sed 's/\./\\./g')/$software.example/g"
+}
+
+mkdir -p "$dir"
+api notes/show "{\"noteId\":\"$note_id\"}" | jq . | anonymize > "$dir/note.json"
+user_id=$(jq -r .userId "$dir/note.json")
+api users/show "{\"userId\":\"$user_id\"}" | jq . | anonymize > "$dir/user.json"
+api drive/files/show "{\"fileId\":\"$file_id\"}" | jq . | anonymize > "$dir/drive_file.json"
@@ -1,258 +1,818 @@
-use std::{collections::HashSet, error::Error, fs::File, io::BufReader, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
 
-use fancy_regex::Regex;
+use chrono::{DateTime, Utc};
 use itertools::Itertools;
-use serde_json::json;
-use tokio::sync::{mpsc::UnboundedReceiver, RwLock};
-use tracing::error;
+use tokio::sync::{
+    mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    RwLock,
+};
+use tracing::{debug, info, warn};
 
 use crate::{
+    branch_rules::BranchRules,
+    clock::SystemClock,
     common_types::{
-        BranchKey, ChannelChannel, Connection, Credential, DynNoteModel, Host, NoteModel,
+        BranchKey, ChannelChannel, ColumnSettings, Connection, Credential, DynNoteModel, Host,
+        MediaVisibility, MiMergeError, NoteModel, RetentionPolicy, SensitiveMediaMode,
     },
-    merged_timeline::MergedTimeline,
-    mi_models::Note,
-    server_cxn::ServerCxn,
+    config::Config,
+    connection_status::{account_key, ConnectionStatus, ConnectionStatuses},
+    emoji_service::refresh_catalogue,
+    global_state::{get_decomposer, get_emoji_service, get_note_store, get_search_index},
+    merged_timeline::{MergedTimeline, DEFAULT_REORDER_WINDOW},
+    note_filter::NoteFilter,
+    note_store::RESTORED_NOTES,
+    pager::{NoteSource, Pager},
+    server_capabilities::ServerCapabilities,
     server_note_repo::ServerNoteRepo,
+    server_source::{new_source, ServerSource, ServerStream},
     ws_msg_router::WsMsgRouter,
     ws_poller::WsPoller,
 };
 
+// 保存済みのノートまで遡るときに、たどるページの上限。
+const BACKFILL_MAX_PAGES: usize = 5;
+
 #[derive(Debug)]
 pub struct AppModel {
     pub credentials: Vec<Credential>,
     pub merged_timeline: Arc<RwLock<MergedTimeline>>,
+    pub note_filter: Arc<NoteFilter>,
+    pub branch_rules: Arc<BranchRules>,
+    pub statuses: ConnectionStatuses,
 
     branches: Vec<BranchKey>,
     branches_set: HashSet<BranchKey>,
+    branch_slots: HashMap<BranchKey, usize>,
+    branch_senders: Vec<UnboundedSender<()>>,
+    columns: Vec<ColumnSettings>,
+    sensitive_media: SensitiveMediaMode,
+    media_visibilities: HashMap<Host, MediaVisibility>,
+    sources: Vec<NoteSource>,
+    connections: Vec<LiveConnection>,
+
+    // キーは "user@host" 。ロールによってアカウントごとに違う。
+    capabilities: HashMap<String, ServerCapabilities>,
+}
+
+// 設定の再読み込みで、チャンネルを足し引きするために覚えておく。
+struct LiveConnection {
+    settings: Connection,
+    host: Host,
+    api_key: String,
+    source: Arc<dyn ServerSource>,
+    cxn: Arc<RwLock<Box<dyn ServerStream>>>,
+    repo: Arc<RwLock<ServerNoteRepo>>,
+    router: Arc<RwLock<WsMsgRouter>>,
+    channel_ids: HashMap<ChannelChannel, String>,
+}
+
+impl std::fmt::Debug for LiveConnection {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("LiveConnection")
+            .field("settings", &self.settings)
+            .field("host", &self.host)
+            .field("channel_ids", &self.channel_ids)
+            .finish_non_exhaustive()
+    }
+}
+
+// 繋ぐ間は `AppModel` をロックしないので、使うものを先に借りておく。
+#[derive(Debug)]
+struct ConnectContext {
+    credential: Credential,
+    sensitive_media: SensitiveMediaMode,
+    merged_timeline: Arc<RwLock<MergedTimeline>>,
+    note_filter: Arc<NoteFilter>,
+    branch_rules: Arc<BranchRules>,
+    statuses: ConnectionStatuses,
+}
+
+// 繋ぎ終えた接続。`AppModel::attach` で取り込む。
+#[derive(Debug)]
+struct OpenedConnection {
+    live: LiveConnection,
+    sources: Vec<NoteSource>,
+    capabilities: ServerCapabilities,
+    media_visibility: MediaVisibility,
 }
 
 #[derive(Debug)]
 pub struct TimelineMerger {
     merged_timeline: Arc<RwLock<MergedTimeline>>,
+    note_filter: Arc<NoteFilter>,
+    branch_rules: Arc<BranchRules>,
+    me_id: Option<String>,
     host: Host,
+    account: String,
+    statuses: ConnectionStatuses,
     receiver: UnboundedReceiver<DynNoteModel>,
+
+    // ミュートしたノートは表示しないので、すぐに捨ててよい。
+    evicted: UnboundedSender<Vec<String>>,
+}
+
+// `MergedTimeline` から捨てられたノートを、サーバーごとの `ServerNoteRepo` からも捨てる。
+#[derive(Debug)]
+pub struct NoteEvictor {
+    repo: Arc<RwLock<ServerNoteRepo>>,
+    cxn: Arc<RwLock<Box<dyn ServerStream>>>,
+    host: Host,
+    receiver: UnboundedReceiver<Vec<String>>,
 }
 
 impl AppModel {
     pub fn new() -> Self {
+        let mut merged_timeline =
+            MergedTimeline::new(RetentionPolicy::default(), Arc::new(SystemClock));
+        merged_timeline.set_store_writer(get_note_store().spawn_writer());
+        merged_timeline.set_index_writer(get_search_index().spawn_writer());
+
         Self {
             credentials: Default::default(),
-            merged_timeline: Arc::new(RwLock::new(MergedTimeline::new())),
+            merged_timeline: Arc::new(RwLock::new(merged_timeline)),
+            note_filter: Default::default(),
+            branch_rules: Default::default(),
+            statuses: ConnectionStatuses::new(),
             branches: Vec::new(),
             branches_set: HashSet::new(),
+            branch_slots: HashMap::new(),
+            branch_senders: Vec::new(),
+            columns: Vec::new(),
+            sensitive_media: Default::default(),
+            media_visibilities: HashMap::new(),
+            sources: Vec::new(),
+            connections: Vec::new(),
+            capabilities: HashMap::new(),
         }
     }
 
-    pub async fn connect_all(&mut self) -> Result<(), Box<dyn Error>> {
-        self.credentials = serde_json::from_reader(BufReader::new(
-            File::open("credentials.json").expect("TODO: handle error"),
-        ))
-        .map_err(|e| {
-            error!("failed to parse credentials.json");
-            e
-        })?;
-        let column: Vec<Connection> = serde_json::from_reader(BufReader::new(
-            File::open("connections.json").expect("TODO: handle error"),
-        ))
-        .map_err(|e| {
-            error!("failed to parse connections.json");
-            e
-        })?;
-
-        for c in column.into_iter().filter(|x| !x.disable) {
-            self.connect(c).await
+    // 設定を読み込み、保存済みのノートを戻す。繋ぐ接続を返す。
+    async fn prepare(&mut self, config: Config) -> Result<Vec<Connection>, MiMergeError> {
+        self.credentials = config.credentials;
+        self.note_filter = Arc::new(NoteFilter::new(config.mute)?);
+        self.branch_rules = Arc::new(BranchRules::new(config.branch_rules)?);
+        self.columns = config.columns;
+        self.sensitive_media = config.media.sensitive_media;
+        self.merged_timeline
+            .write()
+            .await
+            .set_retention(config.timeline.retention_policy());
+
+        self.restore_notes().await;
+        self.refresh_branches();
+
+        Ok(self.enabled_connections(config.connections))
+    }
+
+    // 変わった接続を切断し、繋いだままの接続のチャンネルを足し引きする。新しく繋ぐ接続を返す。
+    async fn reload_live(&mut self, config: Config) -> Vec<Connection> {
+        self.credentials = config.credentials;
+        self.sensitive_media = config.media.sensitive_media;
+        self.merged_timeline
+            .write()
+            .await
+            .set_retention(config.timeline.retention_policy());
+        let wanted = self.enabled_connections(config.connections);
+
+        // API キーやサーバーの種類が変わった接続は、繋ぎ直す。
+        let removed: Vec<_> = self
+            .connections
+            .iter()
+            .filter(|x| {
+                !wanted.iter().any(|y| {
+                    y.host == x.settings.host
+                        && y.user == x.settings.user
+                        && y.backend == x.settings.backend
+                }) || self.api_key(&x.settings) != Some(&x.api_key)
+            })
+            .map(|x| (x.settings.host.clone(), x.settings.user.clone()))
+            .collect();
+        for (host, user) in removed {
+            self.disconnect(&host, &user).await;
+        }
+        // 繋げなかった接続は `self.connections` に無いので、状態だけを消す。
+        let accounts: HashSet<_> = wanted
+            .iter()
+            .map(|x| account_key(&x.host, &x.user))
+            .collect();
+        self.statuses.retain(|x| accounts.contains(x));
+
+        let mut added = Vec::new();
+        for c in wanted {
+            let live = self
+                .connections
+                .iter()
+                .position(|x| x.settings.host == c.host && x.settings.user == c.user);
+            match live {
+                Some(i) => self.update_channels(i, c).await,
+                None => added.push(c),
+            }
         }
-        self.merged_timeline.write().await.implicit_sort().await;
+        self.refresh_branches();
+        added
+    }
 
-        Ok(())
+    // 無効にした接続と、無効にしたアカウントの接続を除く。
+    fn enabled_connections(&self, connections: Vec<Connection>) -> Vec<Connection> {
+        connections
+            .into_iter()
+            .filter(|x| !x.disable)
+            .filter(|c| {
+                let credential_disabled = self
+                    .credentials
+                    .iter()
+                    .any(|x| x.host == c.host && x.user == c.user && x.disable);
+                if credential_disabled {
+                    info!("skipped {}@{}: the credential is disabled", c.user, c.host);
+                }
+                !credential_disabled
+            })
+            .collect()
     }
 
-    pub async fn connect(&mut self, cxn_settings: Connection) {
-        let (host, api_key) = {
-            let credential = self
-                .credentials
-                .iter()
-                .filter(|x| x.host == cxn_settings.host && x.user == cxn_settings.user)
-                .next();
-            let credential = credential.expect("missing credential");
+    fn api_key(&self, cxn_settings: &Connection) -> Option<&String> {
+        self.credentials
+            .iter()
+            .find(|x| x.host == cxn_settings.host && x.user == cxn_settings.user)
+            .map(|x| &x.api_key)
+    }
 
-            let host = Host::from(cxn_settings.host.clone());
-            let api_key = credential.api_key.clone();
-            (host, api_key)
+    // ネットワークに繋ぐ前に、前回の起動時までのノートをタイムラインに戻す。
+    async fn restore_notes(&mut self) {
+        let notes = match get_note_store().load_recent(RESTORED_NOTES) {
+            Ok(notes) => notes,
+            Err(e) => {
+                warn!("failed to load stored notes: {e}");
+                return;
+            }
         };
 
-        let mut server_cxn = ServerCxn::new(host.clone(), api_key.clone());
-
-        let channel_branches = cxn_settings
-            .channels
-            .clone()
+        let mut notes: Vec<_> = notes
             .into_iter()
-            .map(|x| (x.channel, x.branches))
-            .into_grouping_map()
-            .fold(HashSet::new(), |mut acc, _k, v| {
-                acc.extend(v.into_iter().map(BranchKey));
-                acc
-            });
-
-        for b in cxn_settings.channels.iter().flat_map(|x| &x.branches) {
-            self.insert_branch(BranchKey(b.clone()));
+            .filter(|x| !self.note_filter.is_hidden(x))
+            .collect();
+
+        // 前回から規則が変わっているかもしれないので、付け直す。
+        for x in &mut notes {
+            let channel = &x.branches - &x.rule_branches;
+            let rule_branches = self.branch_rules.solve_restored(x, &x.rule_branches);
+            x.rule_branches = &rule_branches - &channel;
+            x.branches = &channel | &rule_branches;
         }
-
-        let mut router = WsMsgRouter::new();
-        for (channel, branches) in &channel_branches {
-            let id = match channel {
-                ChannelChannel::HomeTimeline => server_cxn.connect_to_home(),
-                ChannelChannel::LocalTimeline => server_cxn.connect_to_local(),
-                ChannelChannel::Channel { channel_id } => {
-                    server_cxn.connect_to_channel(&channel_id)
-                }
-            };
-            router.extend(id, branches.iter().cloned());
+        for x in &notes {
+            get_emoji_service().insert_from_note(&x.mi_note, &x.source_host);
         }
+        info!("restored {} notes", notes.len());
 
-        server_cxn.spawn().await.expect("TODO: handle error");
+        self.merged_timeline.write().await.restore(notes);
+    }
 
-        let mut repo = ServerNoteRepo::new();
-        let receiver = repo.make_updated_note_receiver();
+    fn connect_context(&self, cxn_settings: &Connection) -> Result<ConnectContext, MiMergeError> {
+        let credential = self
+            .credentials
+            .iter()
+            .find(|x| x.host == cxn_settings.host && x.user == cxn_settings.user)
+            .ok_or_else(|| MiMergeError::MissingCredential {
+                host: cxn_settings.host.clone(),
+                user: cxn_settings.user.clone(),
+            })?;
+
+        Ok(ConnectContext {
+            credential: credential.clone(),
+            sensitive_media: credential.sensitive_media.unwrap_or(self.sensitive_media),
+            merged_timeline: self.merged_timeline.clone(),
+            note_filter: self.note_filter.clone(),
+            branch_rules: self.branch_rules.clone(),
+            statuses: self.statuses.clone(),
+        })
+    }
 
-        let cxn = Arc::new(RwLock::new(server_cxn));
-        let repo = Arc::new(RwLock::new(repo));
+    fn attach(&mut self, opened: OpenedConnection) {
+        let account = account_key(&opened.live.settings.host, &opened.live.settings.user);
+        self.media_visibilities
+            .insert(opened.live.host.clone(), opened.media_visibility);
+        self.capabilities.insert(account, opened.capabilities);
+        self.sources.extend(opened.sources);
+        self.connections.push(opened.live);
+        self.refresh_branches();
+    }
 
-        let poller = WsPoller {
-            repo: repo.clone(),
-            cxn: cxn.clone(),
-            router,
-            host: host.clone(),
+    // WebSocket を閉じる。タイムラインに入っているノートはそのまま残す。
+    async fn disconnect(&mut self, host: &str, user: &str) {
+        let Some(i) = self
+            .connections
+            .iter()
+            .position(|x| x.settings.host == host && x.settings.user == user)
+        else {
+            return;
         };
-        tokio::spawn(poller.poll());
-
-        for (channel, branches) in &channel_branches {
-            let notes = match channel {
-                ChannelChannel::HomeTimeline => fetch_home_notes(&host, &api_key)
-                    .await
-                    .expect("TODO: handle error"),
-                ChannelChannel::LocalTimeline => fetch_local_notes(&host, &api_key)
-                    .await
-                    .expect("TODO: handle error"),
-                ChannelChannel::Channel { channel_id } => {
-                    fetch_channel_notes(&host, &api_key, &channel_id)
-                        .await
-                        .expect("TODO: handle error")
-                }
+        let live = self.connections.remove(i);
+        info!("removed the connection {user}@{host}");
+        self.statuses.remove(&account_key(host, user));
+        self.capabilities.remove(&account_key(host, user));
+
+        live.cxn.write().await.close();
+        self.sources.retain(|x| !Arc::ptr_eq(&x.repo, &live.repo));
+    }
+
+    // 足されたチャンネルに繋ぎ、消されたチャンネルを切断する。
+    async fn update_channels(&mut self, i: usize, cxn_settings: Connection) {
+        let live = &self.connections[i];
+        let account = account_key(&live.settings.host, &live.settings.user);
+        let host = live.host.clone();
+        let source = live.source.clone();
+        let cxn = live.cxn.clone();
+        let repo = live.repo.clone();
+        let router = live.router.clone();
+
+        let old = channel_branches(&live.settings);
+        let new = channel_branches(&cxn_settings);
+        let mut channel_ids = live.channel_ids.clone();
+
+        for channel in old.keys().filter(|x| !new.contains_key(x)) {
+            let Some(id) = channel_ids.remove(channel) else {
+                continue;
             };
+            info!("disconnecting {channel:?} of {host}");
+            cxn.write().await.disconnect(&id);
+            router.write().await.remove(&id);
+            self.sources
+                .retain(|x| !(Arc::ptr_eq(&x.repo, &repo) && x.channel == *channel));
+        }
 
-            let mut repo = repo.write().await;
-            for note in notes {
-                repo.upsert(
-                    NoteModel::from_mi_model(note, host.clone()),
-                    branches.clone(),
-                )
-                .expect("TODO: handle error");
+        for (channel, branches) in &new {
+            match old.get(channel) {
+                Some(old_branches) if old_branches == branches => {}
+                Some(_) => {
+                    if let Some(id) = channel_ids.get(channel) {
+                        router.write().await.replace(id.clone(), branches.clone());
+                    }
+                    for x in &mut self.sources {
+                        if Arc::ptr_eq(&x.repo, &repo) && x.channel == *channel {
+                            x.branches = branches.clone();
+                        }
+                    }
+                }
+                None if self
+                    .capabilities
+                    .get(&account)
+                    .is_some_and(|x| !x.supports(channel)) =>
+                {
+                    self.statuses
+                        .degrade(&account, &unsupported_channel(&host, channel));
+                }
+                None => {
+                    info!("connecting {channel:?} of {host}");
+                    let id = cxn.write().await.connect_to(channel);
+                    router
+                        .write()
+                        .await
+                        .extend(id.clone(), branches.iter().cloned());
+                    channel_ids.insert(channel.clone(), id);
+                    match load_channel(&source, channel, branches, &repo, None).await {
+                        Ok(x) => self.sources.push(x),
+                        Err(e) => self.statuses.degrade(&account, &e),
+                    }
+                }
             }
         }
 
-        let merger = TimelineMerger {
-            merged_timeline: self.merged_timeline.clone(),
-            host: host.clone(),
-            receiver,
-        };
-        tokio::spawn(merger.merge());
+        if cxn_settings.reorder_window_ms != self.connections[i].settings.reorder_window_ms {
+            let window = cxn_settings
+                .reorder_window_ms
+                .map(Duration::from_millis)
+                .unwrap_or(DEFAULT_REORDER_WINDOW);
+            self.merged_timeline
+                .write()
+                .await
+                .set_reorder_window(host, window);
+        }
+
+        let live = &mut self.connections[i];
+        live.settings = cxn_settings;
+        live.channel_ids = channel_ids;
     }
 
     pub fn branches(&self) -> Vec<BranchKey> {
         self.branches.clone()
     }
 
-    pub fn insert_branch(&mut self, branch: BranchKey) {
-        if self.branches_set.insert(branch.clone()) {
-            self.branches.push(branch);
+    // 列の設定が無ければ、すべてのブランチを1つの列に表示する。
+    pub fn columns(&self) -> Vec<ColumnSettings> {
+        if self.columns.is_empty() {
+            vec![ColumnSettings {
+                name: "タイムライン".to_owned(),
+                branches: Vec::new(),
+            }]
+        } else {
+            self.columns.clone()
+        }
+    }
+
+    // `filter` に当てはまるノートを流し込みうるチャンネルから、古いノートを読み込む。
+    pub fn pager(&self, filter: Option<&HashSet<BranchKey>>) -> Pager {
+        Pager::new(self.merged_timeline.clone(), self.pager_sources(filter))
+    }
+
+    pub fn pager_sources(&self, filter: Option<&HashSet<BranchKey>>) -> Vec<NoteSource> {
+        let rule_branches: HashSet<_> = self.branch_rules.branches().into_iter().collect();
+        self.sources
+            .iter()
+            .filter(|x| match filter {
+                // 規則で決まるブランチは、どのチャンネルのノートにも付きうる。
+                Some(filter) => {
+                    !filter.is_disjoint(&x.branches) || !filter.is_disjoint(&rule_branches)
+                }
+                None => true,
+            })
+            .cloned()
+            .collect()
+    }
+
+    pub fn media_visibilities(&self) -> HashMap<Host, MediaVisibility> {
+        self.media_visibilities.clone()
+    }
+
+    pub fn capabilities(&self, account: &str) -> Option<ServerCapabilities> {
+        self.capabilities.get(account).cloned()
+    }
+
+    // 色の番号。ブランチが消えても番号は詰めないので、残ったブランチの色は変わらない。
+    pub fn branch_slot(&self, branch: &BranchKey) -> usize {
+        self.branch_slots.get(branch).copied().unwrap_or_default()
+    }
+
+    // ブランチが増減するたびに通知する。
+    pub fn make_branches_receiver(&mut self) -> UnboundedReceiver<()> {
+        let (tx, rx) = unbounded_channel();
+        self.branch_senders.push(tx);
+        rx
+    }
+
+    fn insert_branch(&mut self, branch: BranchKey) -> bool {
+        if !self.branches_set.insert(branch.clone()) {
+            return false;
+        }
+        let slot = self.branch_slots.len();
+        self.branch_slots.entry(branch.clone()).or_insert(slot);
+        self.branches.push(branch);
+        true
+    }
+
+    // 繋いでいるチャンネルと規則から、ブランチを数え直す。
+    fn refresh_branches(&mut self) {
+        let wanted: Vec<BranchKey> = self
+            .connections
+            .iter()
+            .flat_map(|x| {
+                x.settings
+                    .channels
+                    .iter()
+                    .filter(|y| !y.disable)
+                    .flat_map(|y| y.branches.iter().cloned().map(BranchKey))
+            })
+            .chain(self.branch_rules.branches())
+            .collect();
+        let wanted_set: HashSet<_> = wanted.iter().cloned().collect();
+
+        let before = self.branches.len();
+        self.branches.retain(|x| wanted_set.contains(x));
+        self.branches_set.retain(|x| wanted_set.contains(x));
+        let mut changed = self.branches.len() != before;
+
+        for b in wanted {
+            changed |= self.insert_branch(b);
+        }
+
+        if changed {
+            self.branch_senders.retain(|x| x.send(()).is_ok());
         }
     }
 }
 
+fn unsupported_channel(host: &Host, channel: &ChannelChannel) -> MiMergeError {
+    MiMergeError::UnsupportedChannel {
+        host: host.to_string(),
+        channel: format!("{channel:?}"),
+    }
+}
+
+// `config` は `Config::validate` で検証済みであること。
+// 保存済みのノートを戻してからロックを手放し、列がすぐに購読できるようにする。
+// 繋ぐ間は `AppModel` をロックしない。接続の失敗は `statuses` に残し、他の接続は続ける。
+pub async fn connect_all(app_model: &RwLock<AppModel>, config: Config) -> Result<(), MiMergeError> {
+    let connections = app_model.write().await.prepare(config).await?;
+
+    for c in connections {
+        connect(app_model, c).await;
+    }
+
+    let merged_timeline = app_model.read().await.merged_timeline.clone();
+    merged_timeline.write().await.implicit_sort().await;
+    Ok(())
+}
+
+// 接続とアカウントの変更だけを反映する。変わらなかった接続には触れない。
+// ミュート、ブランチの規則、列の変更は、再起動するまで反映しない。
+pub async fn reload(app_model: &RwLock<AppModel>, config: Config) {
+    let added = app_model.write().await.reload_live(config).await;
+    for c in added {
+        connect(app_model, c).await;
+    }
+}
+
+// 失敗しても、他の接続には影響しない。
+async fn connect(app_model: &RwLock<AppModel>, cxn_settings: Connection) {
+    let account = account_key(&cxn_settings.host, &cxn_settings.user);
+    let (context, statuses) = {
+        let app_model = app_model.read().await;
+        app_model
+            .statuses
+            .set(&account, ConnectionStatus::Connecting);
+        (
+            app_model.connect_context(&cxn_settings),
+            app_model.statuses.clone(),
+        )
+    };
+
+    let opened = match context {
+        Ok(context) => open_connection(context, cxn_settings, &account).await,
+        Err(e) => Err(e),
+    };
+    match opened {
+        Ok(opened) => app_model.write().await.attach(opened),
+        Err(e) => statuses.fail(&account, &e),
+    }
+}
+
+async fn open_connection(
+    context: ConnectContext,
+    cxn_settings: Connection,
+    account: &str,
+) -> Result<OpenedConnection, MiMergeError> {
+    let host = Host::from(cxn_settings.host.clone());
+    let api_key = context.credential.api_key.clone();
+    let sensitive_media = context.sensitive_media;
+
+    let source = new_source(cxn_settings.backend, host.clone(), api_key.clone());
+    let mut capabilities = source.probe().await;
+
+    let me = source
+        .fetch_me()
+        .await
+        .map_err(|e| warn!("failed to fetch account of {host}: {e}"))
+        .ok();
+    let always_mark_nsfw = me.as_ref().is_some_and(|x| x.always_mark_nsfw);
+    let media_visibility = sensitive_media.resolve(always_mark_nsfw);
+    if let Some(me) = &me {
+        capabilities.apply_policies(&me.policies);
+    }
+
+    if capabilities.emoji_endpoint {
+        tokio::spawn(refresh_catalogue(get_emoji_service(), host.clone()));
+    } else {
+        // ノートに同梱された絵文字だけで描画する。
+        get_emoji_service().disable_lookup(host.clone());
+    }
+
+    let stored_until = get_note_store()
+        .newest_created_at(&host)
+        .map_err(|e| warn!("failed to read the note store: {e}"))
+        .ok()
+        .flatten();
+
+    if let Some(ms) = cxn_settings.reorder_window_ms {
+        context
+            .merged_timeline
+            .write()
+            .await
+            .set_reorder_window(host.clone(), Duration::from_millis(ms));
+    }
+
+    let mut server_cxn = source.stream();
+
+    // 使えないタイムラインには繋がず、接続の状態に残す。
+    let (channel_branches, unsupported): (HashMap<_, _>, HashMap<_, _>) =
+        channel_branches(&cxn_settings)
+            .into_iter()
+            .partition(|(x, _)| capabilities.supports(x));
+
+    let mut router = WsMsgRouter::new();
+    let mut channel_ids = HashMap::new();
+    for (channel, branches) in &channel_branches {
+        let id = server_cxn.connect_to(channel);
+        router.extend(id.clone(), branches.iter().cloned());
+        channel_ids.insert(channel.clone(), id);
+    }
+
+    server_cxn.spawn().await?;
+    context.statuses.set(account, ConnectionStatus::Connected);
+    for channel in unsupported.keys() {
+        context
+            .statuses
+            .degrade(account, &unsupported_channel(&host, channel));
+    }
+
+    let mut repo = ServerNoteRepo::new();
+    let receiver = repo.make_updated_note_receiver();
+
+    let cxn = Arc::new(RwLock::new(server_cxn));
+    let repo = Arc::new(RwLock::new(repo));
+    let router = Arc::new(RwLock::new(router));
+
+    let poller = WsPoller {
+        repo: repo.clone(),
+        cxn: cxn.clone(),
+        router: router.clone(),
+        host: host.clone(),
+        account: account.to_owned(),
+        statuses: context.statuses.clone(),
+    };
+    tokio::spawn(poller.poll());
+
+    let mut sources = Vec::new();
+    for (channel, branches) in &channel_branches {
+        match load_channel(&source, channel, branches, &repo, stored_until).await {
+            Ok(x) => sources.push(x),
+            Err(e) => context.statuses.degrade(account, &e),
+        }
+    }
+
+    let (evicted_tx, evicted_rx) = unbounded_channel();
+    context
+        .merged_timeline
+        .write()
+        .await
+        .add_eviction_sender(evicted_tx.clone());
+
+    let evictor = NoteEvictor {
+        repo: repo.clone(),
+        cxn: cxn.clone(),
+        host: host.clone(),
+        receiver: evicted_rx,
+    };
+    tokio::spawn(evictor.evict());
+
+    let merger = TimelineMerger {
+        merged_timeline: context.merged_timeline.clone(),
+        note_filter: context.note_filter.clone(),
+        branch_rules: context.branch_rules.clone(),
+        me_id: me.map(|x| x.id),
+        host: host.clone(),
+        account: account.to_owned(),
+        statuses: context.statuses.clone(),
+        receiver,
+        evicted: evicted_tx,
+    };
+    tokio::spawn(merger.merge());
+
+    Ok(OpenedConnection {
+        live: LiveConnection {
+            settings: cxn_settings,
+            host,
+            api_key,
+            source,
+            cxn,
+            repo,
+            router,
+            channel_ids,
+        },
+        sources,
+        capabilities,
+        media_visibility,
+    })
+}
+
+// 最初のページを読み込み、古いノートを読み込めるようにする。
+// `stored_until` があれば、前回の起動時に保存したノートまで遡って埋める。
+async fn load_channel(
+    source: &Arc<dyn ServerSource>,
+    channel: &ChannelChannel,
+    branches: &HashSet<BranchKey>,
+    repo: &Arc<RwLock<ServerNoteRepo>>,
+    stored_until: Option<DateTime<Utc>>,
+) -> Result<NoteSource, MiMergeError> {
+    let host = source.host();
+    let mut notes = source.fetch_page(channel, None).await?;
+
+    if let Some(stored_until) = stored_until {
+        for _ in 0..BACKFILL_MAX_PAGES {
+            let Some(oldest) = notes.iter().min_by_key(|x| x.created_at) else {
+                break;
+            };
+            if oldest.created_at <= stored_until {
+                break;
+            }
+            match source.fetch_page(channel, Some(&oldest.id)).await {
+                Ok(page) if !page.is_empty() => notes.extend(page),
+                Ok(_) => break,
+                Err(e) => {
+                    warn!("failed to backfill notes of {host}: {e}");
+                    break;
+                }
+            }
+        }
+    }
+
+    let note_source = NoteSource {
+        host: host.clone(),
+        source: source.clone(),
+        channel: channel.clone(),
+        branches: branches.clone(),
+        repo: repo.clone(),
+        oldest: notes
+            .iter()
+            .min_by_key(|x| x.created_at)
+            .map(|x| (x.id.clone(), x.created_at)),
+    };
+
+    let mut repo = repo.write().await;
+    for note in notes {
+        repo.upsert(
+            NoteModel::from_mi_model(note, host.clone()),
+            branches.clone(),
+        )?;
+    }
+    Ok(note_source)
+}
+
+fn channel_branches(cxn_settings: &Connection) -> HashMap<ChannelChannel, HashSet<BranchKey>> {
+    cxn_settings
+        .channels
+        .clone()
+        .into_iter()
+        .filter(|x| !x.disable)
+        .map(|x| (x.channel, x.branches))
+        .into_grouping_map()
+        .fold(HashSet::new(), |mut acc, _k, v| {
+            acc.extend(v.into_iter().map(BranchKey));
+            acc
+        })
+}
+
 impl TimelineMerger {
     async fn merge(mut self) {
         while let Some(mut note) = self.receiver.recv().await {
+            if self.note_filter.is_hidden(&note) {
+                let _ = self.evicted.send(vec![note.uri]);
+                continue;
+            }
+
+            // チャンネルで付いているものは、ルールに合わなくなっても外さない。
+            let rule_branches = self
+                .branch_rules
+                .solve_branches(&note, self.me_id.as_deref());
+            note.rule_branches = &rule_branches - &note.branches;
+            note.branches.extend(rule_branches);
+
+            get_emoji_service().insert_from_note(&note.mi_note, &note.source_host);
+
             for (r, _) in &mut note.reactions {
                 if let Some(qualified) = self.qualify_reaction(r) {
                     *r = qualified;
                 }
             }
 
-            self.merged_timeline
-                .write()
-                .await
-                .upsert(note)
-                .await
-                .expect("TODO: handle error");
+            let upserted = self.merged_timeline.write().await.upsert(note).await;
+            if let Err(e) = upserted {
+                self.statuses.degrade(&self.account, &e);
+            }
         }
     }
 
     fn qualify_reaction(&self, reaction_name: &str) -> Option<String> {
-        let re = Regex::new("^:(.*)@(.*):$").unwrap();
-        match re.captures(&reaction_name).expect("regex error") {
-            Some(captures) => {
-                if captures.get(2).unwrap().as_str() == "." {
-                    return Some(format!(
-                        ":{}@{}:",
-                        captures.get(1).unwrap().as_str(),
-                        self.host
-                    ));
-                } else {
-                    return None;
-                }
-            }
-            None => None,
+        let emoji = get_decomposer().parse_emoji(reaction_name)?;
+        if emoji.host.is_some_and(|x| x != ".") {
+            return None;
         }
+        Some(emoji.qualified(&self.host, &self.host))
     }
 }
 
-async fn fetch_home_notes(host: &Host, api_key: &str) -> Result<Vec<Note>, Box<dyn Error>> {
-    let client = reqwest::Client::new();
-    let res = client
-        .post(format!("https://{}/api/notes/timeline", host.to_string()))
-        .json(&json!({ "i": api_key }))
-        .send()
-        .await?
-        .error_for_status()?;
-
-    let res = res.json().await?;
-    Ok(res)
-}
+impl NoteEvictor {
+    async fn evict(mut self) {
+        while let Some(uris) = self.receiver.recv().await {
+            // 接続を消したら、受け取るのをやめる。
+            if self.cxn.read().await.is_closed() {
+                break;
+            }
 
-async fn fetch_local_notes(host: &Host, api_key: &str) -> Result<Vec<Note>, Box<dyn Error>> {
-    let client = reqwest::Client::new();
-    let res = client
-        .post(format!(
-            "https://{}/api/notes/local-timeline",
-            host.to_string()
-        ))
-        .json(&json!({ "i": api_key }))
-        .send()
-        .await?
-        .error_for_status()?;
-
-    let res = res.json().await?;
-    Ok(res)
-}
+            let uris = HashSet::from_iter(uris);
+            let note_ids = self.repo.write().await.evict(&uris);
+            if note_ids.is_empty() {
+                continue;
+            }
+            debug!("evicted {} notes from {}", note_ids.len(), self.host);
 
-async fn fetch_channel_notes(
-    host: &Host,
-    api_key: &str,
-    channel_id: &str,
-) -> Result<Vec<Note>, Box<dyn Error>> {
-    let client = reqwest::Client::new();
-    let res = client
-        .post(format!(
-            "https://{}/api/channels/timeline",
-            host.to_string()
-        ))
-        .json(&json!({ "channelId": channel_id, "i": api_key }))
-        .send()
-        .await?
-        .error_for_status()?;
-
-    let res = res.json().await?;
-    Ok(res)
+            let mut cxn = self.cxn.write().await;
+            for id in &note_ids {
+                cxn.unsubscribe_note(id);
+            }
+        }
+    }
 }
@@ -0,0 +1,61 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use base64::prelude::*;
+use tracing::debug;
+
+const PLACEHOLDER_SIZE: u32 = 32;
+const MAX_CACHED: usize = 10000;
+
+// blurhash を小さな PNG の data URI にする。
+// 列は更新のたびに作り直されるので、デコード結果を覚えておく。
+#[derive(Debug, Default)]
+pub struct BlurhashDecoder {
+    cache: Mutex<HashMap<String, Option<String>>>,
+}
+
+impl BlurhashDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn data_uri(&self, blurhash: &str) -> Option<String> {
+        if let Some(cached) = self.cache.lock().unwrap().get(blurhash) {
+            return cached.clone();
+        }
+
+        let data_uri = decode_to_data_uri(blurhash);
+
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= MAX_CACHED {
+            cache.clear();
+        }
+        cache.insert(blurhash.to_owned(), data_uri.clone());
+        data_uri
+    }
+}
+
+fn decode_to_data_uri(blurhash: &str) -> Option<String> {
+    let pixels = blurhash::decode(blurhash, PLACEHOLDER_SIZE, PLACEHOLDER_SIZE, 1.0)
+        .map_err(|e| debug!("invalid blurhash {blurhash}: {e:?}"))
+        .ok()?;
+
+    let mut png = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut png, PLACEHOLDER_SIZE, PLACEHOLDER_SIZE);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder
+            .write_header()
+            .map_err(|e| debug!("failed to encode placeholder: {e:?}"))
+            .ok()?;
+        writer
+            .write_image_data(&pixels)
+            .map_err(|e| debug!("failed to encode placeholder: {e:?}"))
+            .ok()?;
+    }
+
+    Some(format!(
+        "data:image/png;base64,{}",
+        BASE64_STANDARD.encode(png)
+    ))
+}
@@ -0,0 +1,133 @@
+use std::collections::HashSet;
+
+use fancy_regex::Regex;
+
+use crate::{
+    common_types::{BranchKey, BranchRule, BranchRuleSettings, DynNoteModel, Host},
+    mi_models::{Note, Visibility},
+};
+
+#[derive(Debug, Default)]
+pub struct BranchRules {
+    rules: Vec<(BranchKey, CompiledRule)>,
+}
+
+#[derive(Debug)]
+enum CompiledRule {
+    HasFiles,
+    OriginalHost(Host),
+    MentionsMe,
+    Visibility(Visibility),
+    TextMatches(Regex),
+    RenoteOf(String),
+    And(Vec<CompiledRule>),
+    Or(Vec<CompiledRule>),
+    Not(Box<CompiledRule>),
+}
+
+impl BranchRules {
+    pub fn new(settings: Vec<BranchRuleSettings>) -> Result<Self, fancy_regex::Error> {
+        let rules = settings
+            .into_iter()
+            .map(|x| Ok((BranchKey(x.branch), CompiledRule::new(x.rule)?)))
+            .collect::<Result<_, fancy_regex::Error>>()?;
+        Ok(Self { rules })
+    }
+
+    pub fn branches(&self) -> Vec<BranchKey> {
+        self.rules.iter().map(|(b, _)| b.clone()).collect()
+    }
+
+    // `me_id` はノートを受け取ったアカウントの ID 。
+    pub fn solve_branches(&self, note: &DynNoteModel, me_id: Option<&str>) -> HashSet<BranchKey> {
+        self.rules
+            .iter()
+            .filter(|(_, rule)| rule.matches(note, me_id))
+            .map(|(b, _)| b.clone())
+            .collect()
+    }
+
+    // 保存しておいたノートを戻すときに使う。受け取ったアカウントの ID はまだ分からないので、
+    // それに頼る規則は前回の結果 `stored` を引き継ぐ。
+    pub fn solve_restored(
+        &self,
+        note: &DynNoteModel,
+        stored: &HashSet<BranchKey>,
+    ) -> HashSet<BranchKey> {
+        self.rules
+            .iter()
+            .filter(|(b, rule)| {
+                if rule.uses_me() {
+                    stored.contains(b)
+                } else {
+                    rule.matches(note, None)
+                }
+            })
+            .map(|(b, _)| b.clone())
+            .collect()
+    }
+}
+
+impl CompiledRule {
+    fn new(rule: BranchRule) -> Result<Self, fancy_regex::Error> {
+        Ok(match rule {
+            BranchRule::HasFiles => Self::HasFiles,
+            BranchRule::OriginalHost { host } => Self::OriginalHost(Host::from(host)),
+            BranchRule::MentionsMe => Self::MentionsMe,
+            BranchRule::Visibility { visibility } => Self::Visibility(visibility),
+            BranchRule::TextMatches { regex } => Self::TextMatches(Regex::new(&regex)?),
+            BranchRule::RenoteOf { user } => {
+                Self::RenoteOf(user.trim_start_matches('@').to_lowercase())
+            }
+            BranchRule::And { rules } => {
+                Self::And(rules.into_iter().map(Self::new).collect::<Result<_, _>>()?)
+            }
+            BranchRule::Or { rules } => {
+                Self::Or(rules.into_iter().map(Self::new).collect::<Result<_, _>>()?)
+            }
+            BranchRule::Not { rule } => Self::Not(Box::new(Self::new(*rule)?)),
+        })
+    }
+
+    fn uses_me(&self) -> bool {
+        match self {
+            Self::MentionsMe => true,
+            Self::And(rules) | Self::Or(rules) => rules.iter().any(Self::uses_me),
+            Self::Not(rule) => rule.uses_me(),
+            _ => false,
+        }
+    }
+
+    fn matches(&self, note: &DynNoteModel, me_id: Option<&str>) -> bool {
+        let mi_note = &note.mi_note;
+
+        match self {
+            Self::HasFiles => notes(mi_note).any(|x| !x.files.is_empty()),
+            Self::OriginalHost(host) => &note.original_host == host,
+            Self::MentionsMe => me_id.is_some_and(|me_id| {
+                notes(mi_note)
+                    .filter_map(|x| x.mentions.as_ref())
+                    .any(|xs| xs.iter().any(|x| x == me_id))
+            }),
+            Self::Visibility(visibility) => &mi_note.visibility == visibility,
+            Self::TextMatches(re) => notes(mi_note)
+                .filter_map(|x| x.text.as_deref())
+                .any(|x| re.is_match(x).unwrap_or(false)),
+            Self::RenoteOf(user) => mi_note.renote.as_ref().is_some_and(|renote| {
+                if user.contains('@') {
+                    &renote.user.acct(&note.source_host) == user
+                } else {
+                    &renote.user.username.to_lowercase() == user
+                }
+            }),
+            Self::And(rules) => rules.iter().all(|x| x.matches(note, me_id)),
+            Self::Or(rules) => rules.iter().any(|x| x.matches(note, me_id)),
+            Self::Not(rule) => !rule.matches(note, me_id),
+        }
+    }
+}
+
+// リノートされたノートも含めて見る。
+fn notes(note: &Note) -> impl Iterator<Item = &Note> {
+    std::iter::once(note).chain(note.renote.as_deref())
+}
@@ -0,0 +1,58 @@
+use std::{fmt::Debug, time::Instant};
+
+#[cfg(test)]
+use std::{sync::Mutex, time::Duration};
+
+use chrono::{DateTime, Utc};
+
+// 時刻の取得を差し替えられるようにする。
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> Instant;
+    fn utc_now(&self) -> DateTime<Utc>;
+}
+
+#[derive(Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn utc_now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+// テストで、時刻を好きなだけ進める。
+#[cfg(test)]
+#[derive(Debug)]
+pub struct FakeClock {
+    now: Mutex<(Instant, DateTime<Utc>)>,
+}
+
+#[cfg(test)]
+impl FakeClock {
+    pub fn new(utc_now: DateTime<Utc>) -> Self {
+        Self {
+            now: Mutex::new((Instant::now(), utc_now)),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self.now.lock().unwrap();
+        now.0 += duration;
+        now.1 += duration;
+    }
+}
+
+#[cfg(test)]
+impl Clock for FakeClock {
+    fn now(&self) -> Instant {
+        self.now.lock().unwrap().0
+    }
+
+    fn utc_now(&self) -> DateTime<Utc> {
+        self.now.lock().unwrap().1
+    }
+}
@@ -0,0 +1,44 @@
+use serde::{Deserialize, Serialize};
+
+use crate::mi_models::Visibility;
+
+// チャンネルではなく、条件に一致するノートを集めるブランチ。
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct BranchRuleSettings {
+    pub branch: String,
+    pub rule: BranchRule,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+#[serde(deny_unknown_fields)]
+pub enum BranchRule {
+    #[serde(rename = "hasFiles")]
+    HasFiles,
+
+    #[serde(rename = "originalHost")]
+    OriginalHost { host: String },
+
+    #[serde(rename = "mentionsMe")]
+    MentionsMe,
+
+    #[serde(rename = "visibility")]
+    Visibility { visibility: Visibility },
+
+    #[serde(rename = "textMatches")]
+    TextMatches { regex: String },
+
+    // "username@host" 。ホストを省略するとすべてのサーバーの同名のユーザーに一致する。
+    #[serde(rename = "renoteOf")]
+    RenoteOf { user: String },
+
+    #[serde(rename = "and")]
+    And { rules: Vec<BranchRule> },
+
+    #[serde(rename = "or")]
+    Or { rules: Vec<BranchRule> },
+
+    #[serde(rename = "not")]
+    Not { rule: Box<BranchRule> },
+}
@@ -0,0 +1,11 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ColumnSettings {
+    pub name: String,
+
+    // 空ならすべてのブランチを表示する。
+    #[serde(default)]
+    pub branches: Vec<String>,
+}
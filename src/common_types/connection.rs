@@ -2,18 +2,39 @@ use std::collections::HashSet;
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Connection {
     pub host: String,
     pub user: String,
 
+    // 省略すると Misskey 。
+    #[serde(default)]
+    pub backend: Backend,
+
     pub channels: Vec<Channel>,
 
     #[serde(default)]
     pub disable: bool,
+
+    // 遅れて届いたノートを作成日時順に並べ直す時間の幅。省略すると 500ms 。
+    #[serde(default)]
+    pub reorder_window_ms: Option<u64>,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    #[default]
+    #[serde(rename = "misskey")]
+    Misskey,
+
+    // GoToSocial など、Mastodon の API を持つサーバーも含む。
+    #[serde(rename = "mastodon")]
+    Mastodon,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Channel {
     pub channel: ChannelChannel,
 
@@ -24,8 +45,9 @@ pub struct Channel {
     pub disable: bool,
 }
 
-#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 #[serde(tag = "channel")]
+#[serde(deny_unknown_fields)]
 pub enum ChannelChannel {
     #[serde(rename = "homeTimeline")]
     HomeTimeline,
@@ -35,4 +57,8 @@ pub enum ChannelChannel {
 
     #[serde(rename = "channel")]
     Channel { channel_id: String },
+
+    // 先頭の `#` を除いたハッシュタグ。
+    #[serde(rename = "hashtag")]
+    Hashtag { tag: String },
 }
@@ -1,6 +1,9 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+use super::SensitiveMediaMode;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
 pub struct Credential {
     pub host: String,
     pub user: String,
@@ -8,4 +11,22 @@ pub struct Credential {
 
     #[serde(default)]
     pub disable: bool,
+
+    // 未指定なら `[media]` の `sensitive_media` に従う。
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sensitive_media: Option<SensitiveMediaMode>,
+}
+
+// `Config` や接続の途中の状態と一緒にログへ出ても、API キーは見せない。
+impl std::fmt::Debug for Credential {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Credential")
+            .field("host", &self.host)
+            .field("user", &self.user)
+            .field("api_key", &"<redacted>")
+            .field("disable", &self.disable)
+            .field("sensitive_media", &self.sensitive_media)
+            .finish()
+    }
 }
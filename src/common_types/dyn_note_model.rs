@@ -15,6 +15,10 @@ pub struct DynNoteModel {
 
     pub reactions: Vec<(String, i64)>,
     pub branches: HashSet<BranchKey>,
+
+    // `branches` のうち、チャンネルではなくブランチのルールで付いたもの。
+    // 内容が変わったら、足し合わせずに求め直す。
+    pub rule_branches: HashSet<BranchKey>,
 }
 
 impl DynNoteModel {
@@ -26,6 +30,7 @@ impl DynNoteModel {
             mi_note: global_note.mi_note,
             reactions: Default::default(),
             branches: Default::default(),
+            rule_branches: Default::default(),
         }
     }
     pub fn from_mi_model(mi_note: Note, source_host: Host) -> Self {
@@ -1,8 +1,22 @@
 use std::error::Error;
 
+use crate::{
+    credential_store::CredentialStoreError, emoji_service::EmojiServiceError,
+    note_store::NoteStoreError, server_cxn::ServerCxnError,
+};
+
+// プロジェクト全体のエラー。各モジュールのエラーはこれに包んで、接続の状態とログに流す。
 #[derive(Debug)]
 pub enum MiMergeError {
     InvalidNote,
+    InvalidSettings(String),
+    MissingCredential { host: String, user: String },
+    UnsupportedChannel { host: String, channel: String },
+    HttpRequestError(String),
+    ServerCxnError(ServerCxnError),
+    EmojiServiceError(EmojiServiceError),
+    NoteStoreError(NoteStoreError),
+    CredentialStoreError(CredentialStoreError),
 }
 
 impl std::fmt::Display for MiMergeError {
@@ -11,8 +25,78 @@ impl std::fmt::Display for MiMergeError {
             MiMergeError::InvalidNote => {
                 write!(f, "invalid note")
             }
+            MiMergeError::InvalidSettings(e) => {
+                write!(f, "invalid settings: {e}")
+            }
+            MiMergeError::MissingCredential { host, user } => {
+                write!(f, "missing credential for {user}@{host}")
+            }
+            MiMergeError::UnsupportedChannel { host, channel } => {
+                write!(f, "{channel} is not available on {host}")
+            }
+            MiMergeError::HttpRequestError(e) => {
+                write!(f, "http request error: {e}")
+            }
+            MiMergeError::ServerCxnError(e) => {
+                write!(f, "{e}")
+            }
+            MiMergeError::EmojiServiceError(e) => {
+                write!(f, "{e}")
+            }
+            MiMergeError::NoteStoreError(e) => {
+                write!(f, "{e}")
+            }
+            MiMergeError::CredentialStoreError(e) => {
+                write!(f, "{e}")
+            }
+        }
+    }
+}
+
+impl Error for MiMergeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MiMergeError::ServerCxnError(e) => Some(e),
+            MiMergeError::EmojiServiceError(e) => Some(e),
+            MiMergeError::NoteStoreError(e) => Some(e),
+            MiMergeError::CredentialStoreError(e) => Some(e),
+            _ => None,
         }
     }
 }
 
-impl Error for MiMergeError {}
+impl From<ServerCxnError> for MiMergeError {
+    fn from(value: ServerCxnError) -> Self {
+        Self::ServerCxnError(value)
+    }
+}
+
+impl From<EmojiServiceError> for MiMergeError {
+    fn from(value: EmojiServiceError) -> Self {
+        Self::EmojiServiceError(value)
+    }
+}
+
+impl From<NoteStoreError> for MiMergeError {
+    fn from(value: NoteStoreError) -> Self {
+        Self::NoteStoreError(value)
+    }
+}
+
+impl From<CredentialStoreError> for MiMergeError {
+    fn from(value: CredentialStoreError) -> Self {
+        Self::CredentialStoreError(value)
+    }
+}
+
+impl From<reqwest::Error> for MiMergeError {
+    fn from(value: reqwest::Error) -> Self {
+        Self::HttpRequestError(value.to_string())
+    }
+}
+
+impl From<fancy_regex::Error> for MiMergeError {
+    fn from(value: fancy_regex::Error) -> Self {
+        Self::InvalidSettings(value.to_string())
+    }
+}
@@ -0,0 +1,11 @@
+use serde::{Deserialize, Serialize};
+
+use super::SensitiveMediaMode;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct MediaSettings {
+    // アカウントごとの `sensitive_media` を省略したときに使う。
+    #[serde(default)]
+    pub sensitive_media: SensitiveMediaMode,
+}
@@ -1,15 +1,31 @@
 mod branch_key;
+mod branch_rule;
+mod column_settings;
 mod connection;
 mod credential;
 mod dyn_note_model;
 mod error;
 mod host;
+mod media_settings;
+mod mute_settings;
 mod note_model;
+mod retention_policy;
+mod sensitive_media;
+mod storage_settings;
+mod timeline_settings;
 
 pub use branch_key::BranchKey;
-pub use connection::{ChannelChannel, Connection};
+pub use branch_rule::{BranchRule, BranchRuleSettings};
+pub use column_settings::ColumnSettings;
+pub use connection::{Backend, Channel, ChannelChannel, Connection};
 pub use credential::Credential;
 pub use dyn_note_model::DynNoteModel;
 pub use error::MiMergeError;
 pub use host::Host;
+pub use media_settings::MediaSettings;
+pub use mute_settings::MuteSettings;
 pub use note_model::NoteModel;
+pub use retention_policy::RetentionPolicy;
+pub use sensitive_media::{MediaVisibility, SensitiveMediaMode};
+pub use storage_settings::StorageSettings;
+pub use timeline_settings::TimelineSettings;
@@ -0,0 +1,23 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct MuteSettings {
+    // 本文と CW に含まれていたら隠す。大文字小文字は区別しない。
+    #[serde(default)]
+    pub words: Vec<String>,
+
+    #[serde(default)]
+    pub regexes: Vec<String>,
+
+    // "username@host" 。ホストを省略するとすべてのサーバーの同名のユーザーに一致する。
+    #[serde(default)]
+    pub users: Vec<String>,
+
+    #[serde(default)]
+    pub instances: Vec<String>,
+
+    // このユーザーによるリノートを隠す。書式は `users` と同じ。
+    #[serde(default)]
+    pub hide_renotes_from: Vec<String>,
+}
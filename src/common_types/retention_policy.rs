@@ -0,0 +1,21 @@
+use std::time::Duration;
+
+pub const DEFAULT_MAX_NOTES: usize = 2000;
+
+// タイムラインにいくつまで、どれだけ古いものまでノートを保持するか。
+// 設定ファイルの `[timeline]` で決める。
+// 溢れたノートは古いものから捨てる。捨てたノートは `untilId` で取り直せる。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    pub max_notes: Option<usize>,
+    pub max_age: Option<Duration>,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            max_notes: Some(DEFAULT_MAX_NOTES),
+            max_age: None,
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+
+use crate::mi_models::DriveFile;
+
+// 閲覧注意のメディアをどう表示するか。
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SensitiveMediaMode {
+    // 常に表示する。
+    #[serde(rename = "show")]
+    Show,
+
+    // 常に隠す。
+    #[serde(rename = "hide")]
+    Hide,
+
+    // 閲覧注意のものを隠す。アカウントの `alwaysMarkNsfw` が有効なら全て隠す。
+    #[default]
+    #[serde(rename = "server")]
+    Server,
+}
+
+impl SensitiveMediaMode {
+    pub fn resolve(self, always_mark_nsfw: bool) -> MediaVisibility {
+        match self {
+            Self::Show => MediaVisibility::ShowAll,
+            Self::Hide => MediaVisibility::HideAll,
+            Self::Server if always_mark_nsfw => MediaVisibility::HideAll,
+            Self::Server => MediaVisibility::HideSensitive,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MediaVisibility {
+    ShowAll,
+    #[default]
+    HideSensitive,
+    HideAll,
+}
+
+impl MediaVisibility {
+    pub fn hides(self, file: &DriveFile) -> bool {
+        match self {
+            Self::ShowAll => false,
+            Self::HideSensitive => file.is_sensitive,
+            Self::HideAll => true,
+        }
+    }
+}
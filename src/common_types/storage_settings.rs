@@ -0,0 +1,79 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config, note_store::NOTE_STORE_FILE_NAME, search_index::SEARCH_INDEX_FILE_NAME};
+
+pub const DEFAULT_MEDIA_CACHE_MAX_MB: u64 = 512;
+
+// 変更は再起動するまで反映しない。
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct StorageSettings {
+    // 省略すると XDG のデータディレクトリの下に置く。
+    #[serde(default = "default_note_store")]
+    pub note_store: PathBuf,
+
+    #[serde(default = "default_search_index")]
+    pub search_index: PathBuf,
+
+    // 省略すると XDG のキャッシュディレクトリの下に置く。
+    #[serde(default = "default_media_cache_dir")]
+    pub media_cache_dir: PathBuf,
+
+    #[serde(default = "default_media_cache_max_mb")]
+    pub media_cache_max_mb: u64,
+}
+
+impl Default for StorageSettings {
+    fn default() -> Self {
+        Self {
+            note_store: default_note_store(),
+            search_index: default_search_index(),
+            media_cache_dir: default_media_cache_dir(),
+            media_cache_max_mb: default_media_cache_max_mb(),
+        }
+    }
+}
+
+impl StorageSettings {
+    pub fn media_cache_max_bytes(&self) -> u64 {
+        self.media_cache_max_mb.saturating_mul(1024 * 1024)
+    }
+
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for (key, path) in [
+            ("note_store", &self.note_store),
+            ("search_index", &self.search_index),
+            ("media_cache_dir", &self.media_cache_dir),
+        ] {
+            if path.as_os_str().is_empty() {
+                problems.push(format!("storage.{key}: empty path"));
+            }
+        }
+        if self.note_store == self.search_index {
+            problems.push("storage.search_index: the same file as storage.note_store".to_owned());
+        }
+        if self.media_cache_max_mb == 0 {
+            problems.push("storage.media_cache_max_mb: must be at least 1".to_owned());
+        }
+        problems
+    }
+}
+
+fn default_note_store() -> PathBuf {
+    config::data_dir().join(NOTE_STORE_FILE_NAME)
+}
+
+fn default_search_index() -> PathBuf {
+    config::data_dir().join(SEARCH_INDEX_FILE_NAME)
+}
+
+fn default_media_cache_dir() -> PathBuf {
+    config::cache_dir().join("media")
+}
+
+fn default_media_cache_max_mb() -> u64 {
+    DEFAULT_MEDIA_CACHE_MAX_MB
+}
@@ -0,0 +1,50 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::{retention_policy::DEFAULT_MAX_NOTES, RetentionPolicy};
+
+// `0` を指定するとその制限を外す。
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct TimelineSettings {
+    #[serde(default = "default_max_notes")]
+    pub max_notes: usize,
+
+    #[serde(default)]
+    pub max_age_minutes: u64,
+}
+
+impl Default for TimelineSettings {
+    fn default() -> Self {
+        Self {
+            max_notes: default_max_notes(),
+            max_age_minutes: 0,
+        }
+    }
+}
+
+impl TimelineSettings {
+    pub fn retention_policy(&self) -> RetentionPolicy {
+        RetentionPolicy {
+            max_notes: (self.max_notes > 0).then_some(self.max_notes),
+            max_age: (self.max_age_minutes > 0)
+                .then(|| Duration::from_secs(self.max_age_minutes * 60)),
+        }
+    }
+
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.max_age_minutes.checked_mul(60).is_none() {
+            problems.push(format!(
+                "timeline.max_age_minutes: too large ({})",
+                self.max_age_minutes
+            ));
+        }
+        problems
+    }
+}
+
+fn default_max_notes() -> usize {
+    DEFAULT_MAX_NOTES
+}
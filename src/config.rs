@@ -0,0 +1,573 @@
+use std::{
+    collections::HashSet,
+    error::Error,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use regex::Regex;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use tracing::{info, warn};
+
+use crate::{
+    branch_rules::BranchRules,
+    common_types::{
+        Backend, BranchRuleSettings, Channel, ChannelChannel, ColumnSettings, Connection,
+        Credential, MediaSettings, MuteSettings, StorageSettings, TimelineSettings,
+    },
+    global_state::get_credential_store,
+    note_filter::NoteFilter,
+};
+
+pub const CONFIG_VERSION: u32 = 1;
+pub const CONFIG_FILE_NAME: &str = "config.toml";
+
+// 以前の設定ファイル。カレントディレクトリから読む。
+pub const LEGACY_CREDENTIALS: &str = "credentials.json";
+const LEGACY_CONNECTIONS: &str = "connections.json";
+const LEGACY_FILTERS: &str = "filters.json";
+const LEGACY_BRANCH_RULES: &str = "branch_rules.json";
+const LEGACY_COLUMNS: &str = "columns.json";
+
+// 設定ファイルの更新日時を見に行く間隔。
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+// 書式は `config.example.toml` を参照。
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub version: u32,
+
+    #[serde(default)]
+    pub credentials: Vec<Credential>,
+
+    #[serde(default)]
+    pub connections: Vec<Connection>,
+
+    #[serde(default)]
+    pub mute: MuteSettings,
+
+    #[serde(default)]
+    pub branch_rules: Vec<BranchRuleSettings>,
+
+    #[serde(default)]
+    pub columns: Vec<ColumnSettings>,
+
+    #[serde(default)]
+    pub timeline: TimelineSettings,
+
+    #[serde(default)]
+    pub media: MediaSettings,
+
+    #[serde(default)]
+    pub storage: StorageSettings,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    NotFound(PathBuf),
+    ReadError(PathBuf, String),
+    ParseError(PathBuf, String),
+    UnsupportedVersion(PathBuf, u32),
+    MigrationError(String),
+    Invalid(PathBuf, Vec<String>),
+    WriteError(PathBuf, String),
+    CredentialStoreError(String),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ConfigError::NotFound(path) => {
+                write!(f, "config file not found: {}", path.display())
+            }
+            ConfigError::ReadError(path, e) => {
+                write!(f, "failed to read {}: {e}", path.display())
+            }
+            ConfigError::ParseError(path, e) => {
+                write!(f, "failed to parse {}: {e}", path.display())
+            }
+            ConfigError::UnsupportedVersion(path, v) => {
+                write!(
+                    f,
+                    "unsupported config version {v} in {} (expected {CONFIG_VERSION})",
+                    path.display()
+                )
+            }
+            ConfigError::MigrationError(e) => {
+                write!(f, "failed to migrate the JSON config files: {e}")
+            }
+            ConfigError::Invalid(path, problems) => {
+                write!(
+                    f,
+                    "invalid config {}: {}",
+                    path.display(),
+                    problems.join("; ")
+                )
+            }
+            ConfigError::WriteError(path, e) => {
+                write!(f, "failed to write {}: {e}", path.display())
+            }
+            ConfigError::CredentialStoreError(e) => {
+                write!(f, "credential store error: {e}")
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl ConfigError {
+    // 起動時のエラー画面に、1行ずつ表示する。
+    pub fn messages(&self) -> Vec<String> {
+        match self {
+            ConfigError::Invalid(path, problems) => {
+                let mut messages = vec![format!("invalid config {}", path.display())];
+                messages.extend(problems.iter().cloned());
+                messages
+            }
+            e => vec![e.to_string()],
+        }
+    }
+}
+
+// `.env` などで `MI_MERGE_CONFIG` を指定できる。
+// 無ければ XDG の設定ディレクトリ (`~/.config/mi-merge/config.toml` など) を使う。
+pub fn config_path() -> PathBuf {
+    if let Ok(path) = std::env::var("MI_MERGE_CONFIG") {
+        return PathBuf::from(path);
+    }
+    dirs::config_dir()
+        .map(|x| x.join("mi-merge").join(CONFIG_FILE_NAME))
+        .unwrap_or(PathBuf::from(CONFIG_FILE_NAME))
+}
+
+// 取り直せないものを置く。XDG のデータディレクトリ (`~/.local/share/mi-merge` など) を使う。
+pub fn data_dir() -> PathBuf {
+    dirs::data_dir()
+        .map(|x| x.join("mi-merge"))
+        .unwrap_or(PathBuf::from("data"))
+}
+
+// 取り直せるものを置く。XDG のキャッシュディレクトリ (`~/.cache/mi-merge` など) を使う。
+pub fn cache_dir() -> PathBuf {
+    dirs::cache_dir()
+        .map(|x| x.join("mi-merge"))
+        .unwrap_or(PathBuf::from("cache"))
+}
+
+// 設定ファイルが無く、以前の JSON ファイルがあれば、それを変換して書き出す。
+pub fn load() -> Result<Config, ConfigError> {
+    let path = config_path();
+    migrate_legacy(&path)?;
+
+    let config = read(&path)?;
+    if !get_credential_store().exists() && !config.credentials.is_empty() {
+        warn!(
+            "API keys are stored in plaintext in {}. run `mi-merge migrate-credentials` to encrypt them",
+            path.display()
+        );
+    }
+    Ok(config)
+}
+
+pub fn migrate_legacy(path: &Path) -> Result<(), ConfigError> {
+    if !path.exists()
+        && Path::new(LEGACY_CREDENTIALS).exists()
+        && Path::new(LEGACY_CONNECTIONS).exists()
+    {
+        let config = migrate_from_json().map_err(|e| ConfigError::MigrationError(e.to_string()))?;
+        save(path, &config).map_err(|e| ConfigError::MigrationError(e.to_string()))?;
+        info!(
+            "migrated the JSON config files to {}. they are no longer read",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+// 暗号化したファイルがあれば、`credentials` はそこから読み込む。
+// 先に `CredentialStore::unlock` で開いておくこと。
+pub fn read(path: &Path) -> Result<Config, ConfigError> {
+    let mut config = read_raw(path)?;
+
+    let store = get_credential_store();
+    if store.exists() {
+        if !config.credentials.is_empty() {
+            return Err(ConfigError::Invalid(
+                path.to_owned(),
+                vec![format!(
+                    "credentials: plaintext API keys remain while {} exists. run `mi-merge migrate-credentials`",
+                    store.path().display()
+                )],
+            ));
+        }
+        config.credentials = store
+            .credentials()
+            .ok_or_else(|| ConfigError::CredentialStoreError("locked".to_owned()))?;
+    }
+
+    let problems = config.validate();
+    if !problems.is_empty() {
+        return Err(ConfigError::Invalid(path.to_owned(), problems));
+    }
+    Ok(config)
+}
+
+// 設定ファイルに書かれたとおりに読む。検証はしない。
+pub fn read_raw(path: &Path) -> Result<Config, ConfigError> {
+    if !path.exists() {
+        return Err(ConfigError::NotFound(path.to_owned()));
+    }
+
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| ConfigError::ReadError(path.to_owned(), e.to_string()))?;
+    let version = toml::from_str::<VersionOnly>(&text)
+        .map_err(|e| ConfigError::ParseError(path.to_owned(), e.to_string()))?
+        .version;
+    if version != CONFIG_VERSION {
+        return Err(ConfigError::UnsupportedVersion(path.to_owned(), version));
+    }
+    toml::from_str::<Config>(&text)
+        .map_err(|e| ConfigError::ParseError(path.to_owned(), e.to_string()))
+}
+
+// 設定ファイルが書き換えられるたびに、読み直した結果を送る。
+// 消されたときは何も送らない。
+pub fn watch() -> UnboundedReceiver<Result<Config, ConfigError>> {
+    let path = config_path();
+    let (tx, rx) = unbounded_channel();
+
+    tokio::spawn(async move {
+        let mut modified = modified_at(&path);
+        loop {
+            tokio::time::sleep(WATCH_INTERVAL).await;
+
+            let t = modified_at(&path);
+            if t == modified {
+                continue;
+            }
+            modified = t;
+            if t.is_none() {
+                continue;
+            }
+
+            info!("{} has changed", path.display());
+            if tx.send(read(&path)).is_err() {
+                break;
+            }
+        }
+    });
+
+    rx
+}
+
+fn modified_at(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|x| x.modified()).ok()
+}
+
+// 設定ファイルを読み直して書き換える。検証を通らなければ書き込まない。
+// 書き込むと `watch` が拾って、接続に反映される。
+pub fn update(f: impl FnOnce(&mut Config)) -> Result<Config, ConfigError> {
+    let path = config_path();
+    let mut config = read(&path)?;
+    f(&mut config);
+
+    let problems = config.validate();
+    if !problems.is_empty() {
+        return Err(ConfigError::Invalid(path, problems));
+    }
+
+    // 暗号化したファイルがあれば、アカウントはそちらに書く。
+    let store = get_credential_store();
+    if store.exists() {
+        store
+            .save(config.credentials.clone())
+            .map_err(|e| ConfigError::CredentialStoreError(e.to_string()))?;
+        let plain = Config {
+            credentials: Vec::new(),
+            ..config.clone()
+        };
+        save(&path, &plain).map_err(|e| ConfigError::WriteError(path.clone(), e.to_string()))?;
+    } else {
+        save(&path, &config).map_err(|e| ConfigError::WriteError(path.clone(), e.to_string()))?;
+    }
+    Ok(config)
+}
+
+pub fn save(path: &Path, config: &Config) -> Result<(), Box<dyn Error>> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, toml::to_string_pretty(config)?)?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct VersionOnly {
+    version: u32,
+}
+
+fn migrate_from_json() -> Result<Config, Box<dyn Error>> {
+    Ok(Config {
+        version: CONFIG_VERSION,
+        credentials: read_json(LEGACY_CREDENTIALS)?,
+        connections: read_json(LEGACY_CONNECTIONS)?,
+        mute: read_json_if_exists(LEGACY_FILTERS)?.unwrap_or_default(),
+        branch_rules: read_json_if_exists(LEGACY_BRANCH_RULES)?.unwrap_or_default(),
+        columns: read_json_if_exists(LEGACY_COLUMNS)?.unwrap_or_default(),
+        timeline: Default::default(),
+        media: Default::default(),
+        storage: Default::default(),
+    })
+}
+
+fn read_json<T: DeserializeOwned>(path: &str) -> Result<T, Box<dyn Error>> {
+    serde_json::from_reader(BufReader::new(File::open(path)?))
+        .map_err(|e| format!("{path}: {e}").into())
+}
+
+fn read_json_if_exists<T: DeserializeOwned>(path: &str) -> Result<Option<T>, Box<dyn Error>> {
+    if Path::new(path).exists() {
+        read_json(path).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+impl Config {
+    // 同じアカウントがあれば、トークンを差し替えて有効にする。
+    // そのアカウントの接続が無ければ、`backend` の API でホームタイムラインに繋ぐ。
+    pub fn add_account(&mut self, credential: Credential, backend: Backend) {
+        let same = |x: &Credential| x.host == credential.host && x.user == credential.user;
+        match self.credentials.iter_mut().find(|x| same(x)) {
+            Some(x) => {
+                x.api_key = credential.api_key.clone();
+                x.disable = false;
+            }
+            None => self.credentials.push(credential.clone()),
+        }
+
+        let connected = self
+            .connections
+            .iter()
+            .any(|x| x.host == credential.host && x.user == credential.user);
+        if !connected {
+            self.connections.push(Connection {
+                host: credential.host.clone(),
+                user: credential.user.clone(),
+                backend,
+                channels: vec![Channel {
+                    channel: ChannelChannel::HomeTimeline,
+                    branches: HashSet::from([format!("{}@{}", credential.user, credential.host)]),
+                    disable: false,
+                }],
+                disable: false,
+                reorder_window_ms: None,
+            });
+        }
+    }
+
+    pub fn set_account_disabled(&mut self, host: &str, user: &str, disable: bool) {
+        for x in &mut self.credentials {
+            if x.host == host && x.user == user {
+                x.disable = disable;
+            }
+        }
+    }
+
+    // アカウントと、その接続を消す。
+    // 列が消えたブランチを表示していれば、検証で止まる。
+    pub fn remove_account(&mut self, host: &str, user: &str) {
+        self.credentials
+            .retain(|x| !(x.host == host && x.user == user));
+        self.connections
+            .retain(|x| !(x.host == host && x.user == user));
+    }
+
+    // 見つかった問題をすべて返す。
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        let host_re = Regex::new(
+            r"^[a-z0-9]([a-z0-9\-]*[a-z0-9])?(\.[a-z0-9]([a-z0-9\-]*[a-z0-9])?)*(:\d+)?$",
+        )
+        .unwrap();
+        let channel_id_re = Regex::new(r"^[0-9a-z]+$").unwrap();
+
+        let mut accounts = HashSet::new();
+        for (i, x) in self.credentials.iter().enumerate() {
+            if !host_re.is_match(&x.host) {
+                problems.push(format!("credentials[{i}]: invalid host \"{}\"", x.host));
+            }
+            if x.api_key.is_empty() {
+                problems.push(format!("credentials[{i}]: empty api_key"));
+            }
+            if !accounts.insert((&x.host, &x.user)) {
+                problems.push(format!(
+                    "credentials[{i}]: duplicate credential for {}@{}",
+                    x.user, x.host
+                ));
+            }
+        }
+
+        let mut channel_branches = HashSet::new();
+        for (i, x) in self.connections.iter().enumerate() {
+            if !self.credentials.iter().any(|y| y.host == x.host) {
+                problems.push(format!(
+                    "connections[{i}]: unknown host \"{}\" (no credential for it)",
+                    x.host
+                ));
+            } else if !accounts.contains(&(&x.host, &x.user)) {
+                problems.push(format!(
+                    "connections[{i}]: missing credential for {}@{}",
+                    x.user, x.host
+                ));
+            }
+
+            for (j, y) in x.channels.iter().enumerate() {
+                match &y.channel {
+                    ChannelChannel::Channel { channel_id } => {
+                        if !channel_id_re.is_match(channel_id) {
+                            problems.push(format!(
+                                "connections[{i}].channels[{j}]: bad channel id \"{channel_id}\""
+                            ));
+                        }
+                        if x.backend == Backend::Mastodon {
+                            problems.push(format!(
+                                "connections[{i}].channels[{j}]: mastodon has no channels"
+                            ));
+                        }
+                    }
+                    ChannelChannel::Hashtag { tag }
+                        if tag.is_empty()
+                            || tag.starts_with('#')
+                            || tag.contains(char::is_whitespace) =>
+                    {
+                        problems.push(format!(
+                            "connections[{i}].channels[{j}]: bad hashtag \"{tag}\" (write it without '#')"
+                        ));
+                    }
+                    _ => {}
+                }
+                channel_branches.extend(y.branches.iter().cloned());
+            }
+        }
+
+        let mut rule_branches = HashSet::new();
+        for (i, x) in self.branch_rules.iter().enumerate() {
+            if !rule_branches.insert(x.branch.clone()) {
+                problems.push(format!(
+                    "branch_rules[{i}]: duplicate branch name \"{}\"",
+                    x.branch
+                ));
+            }
+            if channel_branches.contains(&x.branch) {
+                problems.push(format!(
+                    "branch_rules[{i}]: branch name \"{}\" is already used by a channel",
+                    x.branch
+                ));
+            }
+        }
+        if let Err(e) = BranchRules::new(self.branch_rules.clone()) {
+            problems.push(format!("branch_rules: {e}"));
+        }
+
+        if let Err(e) = NoteFilter::new(self.mute.clone()) {
+            problems.push(format!("mute: {e}"));
+        }
+
+        let mut column_names = HashSet::new();
+        for (i, x) in self.columns.iter().enumerate() {
+            if !column_names.insert(&x.name) {
+                problems.push(format!(
+                    "columns[{i}]: duplicate column name \"{}\"",
+                    x.name
+                ));
+            }
+            for b in &x.branches {
+                if !channel_branches.contains(b) && !rule_branches.contains(b) {
+                    problems.push(format!("columns[{i}]: unknown branch \"{b}\""));
+                }
+            }
+        }
+
+        problems.extend(self.timeline.validate());
+        problems.extend(self.storage.validate());
+
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = include_str!("../config.example.toml");
+
+    #[test]
+    fn example_is_valid() {
+        let config: Config = toml::from_str(EXAMPLE).unwrap();
+        assert_eq!(config.validate(), Vec::<String>::new());
+        assert_eq!(config.storage.media_cache_max_mb, 512);
+        assert_eq!(config.timeline.retention_policy(), Default::default());
+    }
+
+    #[test]
+    fn rejects_unknown_keys_in_sections() {
+        for (section, key) in [
+            ("[storage]", "cache_max_mb = 1"),
+            ("[timeline]", "max_note = 1"),
+            ("[media]", "sensitive = \"show\""),
+            ("[mute]", "word = []"),
+            ("[[columns]]", "name = \"x\"\nbranch = []"),
+        ] {
+            let text = format!("version = 1\n{section}\n{key}\n");
+            assert!(toml::from_str::<Config>(&text).is_err(), "{section} {key}");
+        }
+
+        let text = r#"
+            version = 1
+            [[connections]]
+            host = "misskey.io"
+            user = "alice"
+            [[connections.channels]]
+            channel = { channel = "hashtag", tag = "misskey", channel_id = "x" }
+        "#;
+        assert!(toml::from_str::<Config>(text).is_err());
+    }
+
+    #[test]
+    fn validates_storage() {
+        let text = r#"
+            version = 1
+            [storage]
+            note_store = "data/x.sqlite3"
+            search_index = "data/x.sqlite3"
+            media_cache_max_mb = 0
+        "#;
+        let config: Config = toml::from_str(text).unwrap();
+        assert_eq!(config.validate().len(), 2);
+    }
+
+    #[test]
+    fn add_account_keeps_the_backend() {
+        let mut config: Config = toml::from_str("version = 1").unwrap();
+        let credential = Credential {
+            host: "mastodon.example".to_owned(),
+            user: "alice".to_owned(),
+            api_key: "token".to_owned(),
+            disable: false,
+            sensitive_media: None,
+        };
+        config.add_account(credential.clone(), Backend::Mastodon);
+        config.add_account(credential, Backend::Mastodon);
+
+        assert_eq!(config.credentials.len(), 1);
+        assert_eq!(config.connections.len(), 1);
+        assert_eq!(config.connections[0].backend, Backend::Mastodon);
+    }
+}
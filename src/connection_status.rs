@@ -0,0 +1,75 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use tracing::warn;
+
+use crate::common_types::MiMergeError;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionStatus {
+    Connecting,
+    Connected,
+
+    // 動き続けているが、一部が失敗した。
+    Degraded(String),
+    Failed(String),
+}
+
+impl std::fmt::Display for ConnectionStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ConnectionStatus::Connecting => write!(f, "接続中"),
+            ConnectionStatus::Connected => write!(f, "接続済み"),
+            ConnectionStatus::Degraded(e) => write!(f, "一部失敗: {e}"),
+            ConnectionStatus::Failed(e) => write!(f, "失敗: {e}"),
+        }
+    }
+}
+
+pub fn account_key(host: &str, user: &str) -> String {
+    format!("{user}@{host}")
+}
+
+// 接続ごとの状態。接続の各タスクが書き込み、画面が読む。
+// キーは "user@host" 。
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionStatuses(Arc<Mutex<BTreeMap<String, ConnectionStatus>>>);
+
+impl ConnectionStatuses {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&self, account: &str, status: ConnectionStatus) {
+        self.0.lock().unwrap().insert(account.to_owned(), status);
+    }
+
+    // 失敗をログに残し、接続の状態にも反映する。
+    pub fn fail(&self, account: &str, e: &MiMergeError) {
+        warn!("{account}: {e}");
+        self.set(account, ConnectionStatus::Failed(e.to_string()));
+    }
+
+    // 失敗をログに残す。接続が生きていれば、一部失敗として表示する。
+    pub fn degrade(&self, account: &str, e: &MiMergeError) {
+        warn!("{account}: {e}");
+        let mut statuses = self.0.lock().unwrap();
+        if let Some(status @ ConnectionStatus::Connected) = statuses.get_mut(account) {
+            *status = ConnectionStatus::Degraded(e.to_string());
+        }
+    }
+
+    pub fn get(&self, account: &str) -> Option<ConnectionStatus> {
+        self.0.lock().unwrap().get(account).cloned()
+    }
+
+    pub fn retain(&self, mut f: impl FnMut(&str) -> bool) {
+        self.0.lock().unwrap().retain(|k, _| f(k));
+    }
+
+    pub fn remove(&self, account: &str) {
+        self.0.lock().unwrap().remove(account);
+    }
+}
@@ -0,0 +1,458 @@
+use std::{
+    error::Error,
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{prelude::BASE64_STANDARD, Engine};
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    Key, XChaCha20Poly1305, XNonce,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use zeroize::Zeroizing;
+
+use crate::{
+    common_types::Credential,
+    config::{self, config_path},
+};
+
+pub const CREDENTIAL_STORE_FILE_NAME: &str = "credentials.enc";
+const STORE_VERSION: u32 = 1;
+const SALT_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+
+#[derive(Debug)]
+pub enum CredentialStoreError {
+    IoError(String),
+    InvalidFormat,
+    UnsupportedVersion(u32),
+    KeyDerivationError(String),
+    WrongPassphrase,
+    Locked,
+}
+
+impl std::fmt::Display for CredentialStoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CredentialStoreError::IoError(e) => {
+                write!(f, "io error: {e}")
+            }
+            CredentialStoreError::InvalidFormat => {
+                write!(f, "the credential store is broken")
+            }
+            CredentialStoreError::UnsupportedVersion(v) => {
+                write!(f, "unsupported credential store version {v}")
+            }
+            CredentialStoreError::KeyDerivationError(e) => {
+                write!(f, "failed to derive the key: {e}")
+            }
+            CredentialStoreError::WrongPassphrase => {
+                write!(f, "wrong passphrase")
+            }
+            CredentialStoreError::Locked => {
+                write!(f, "the credential store is locked")
+            }
+        }
+    }
+}
+
+impl Error for CredentialStoreError {}
+
+impl From<std::io::Error> for CredentialStoreError {
+    fn from(value: std::io::Error) -> Self {
+        Self::IoError(value.to_string())
+    }
+}
+
+impl From<serde_json::Error> for CredentialStoreError {
+    fn from(_value: serde_json::Error) -> Self {
+        Self::InvalidFormat
+    }
+}
+
+impl From<base64::DecodeError> for CredentialStoreError {
+    fn from(_value: base64::DecodeError) -> Self {
+        Self::InvalidFormat
+    }
+}
+
+impl From<argon2::Error> for CredentialStoreError {
+    fn from(value: argon2::Error) -> Self {
+        Self::KeyDerivationError(value.to_string())
+    }
+}
+
+// ファイルの中身。鍵はパスフレーズから Argon2id で導き、XChaCha20-Poly1305 で暗号化する。
+#[derive(Serialize, Deserialize, Debug)]
+struct Envelope {
+    version: u32,
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+struct Unlocked {
+    key: Zeroizing<[u8; KEY_LEN]>,
+    salt: Vec<u8>,
+    params: Params,
+    credentials: Vec<Credential>,
+}
+
+// ログに鍵や API キーを出さない。
+impl std::fmt::Debug for Unlocked {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Unlocked")
+            .field("key", &"<redacted>")
+            .field("params", &self.params)
+            .field("credentials", &self.credentials.len())
+            .finish_non_exhaustive()
+    }
+}
+
+// API キーを平文で置かないための、パスフレーズで守ったファイル。
+// 起動時に1度だけ開き、`Config` の `credentials` はここから読み込む。
+pub struct CredentialStore {
+    path: PathBuf,
+    unlocked: Mutex<Option<Unlocked>>,
+}
+
+impl std::fmt::Debug for CredentialStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("CredentialStore")
+            .field("path", &self.path)
+            .field("unlocked", &self.unlocked.lock().unwrap().is_some())
+            .finish()
+    }
+}
+
+impl CredentialStore {
+    // `.env` などで `MI_MERGE_CREDENTIALS` を指定できる。
+    // 無ければ設定ファイルと同じディレクトリに置く。
+    pub fn from_env() -> Self {
+        let path = std::env::var("MI_MERGE_CREDENTIALS")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| config_path().with_file_name(CREDENTIAL_STORE_FILE_NAME));
+        Self::new(path)
+    }
+
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            unlocked: Mutex::new(None),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.exists() && self.unlocked.lock().unwrap().is_none()
+    }
+
+    pub fn unlock(&self, passphrase: &str) -> Result<(), CredentialStoreError> {
+        let text = std::fs::read_to_string(&self.path)?;
+        let envelope: Envelope = serde_json::from_str(&text)?;
+        if envelope.version != STORE_VERSION {
+            return Err(CredentialStoreError::UnsupportedVersion(envelope.version));
+        }
+
+        let salt = BASE64_STANDARD.decode(&envelope.salt)?;
+        let nonce = BASE64_STANDARD.decode(&envelope.nonce)?;
+        let ciphertext = BASE64_STANDARD.decode(&envelope.ciphertext)?;
+        if nonce.len() != XNonce::default().len() {
+            return Err(CredentialStoreError::InvalidFormat);
+        }
+
+        let params = Params::new(
+            envelope.m_cost,
+            envelope.t_cost,
+            envelope.p_cost,
+            Some(KEY_LEN),
+        )?;
+        let key = derive_key(passphrase, &salt, params.clone())?;
+
+        let plaintext = Zeroizing::new(
+            XChaCha20Poly1305::new(Key::from_slice(key.as_ref()))
+                .decrypt(XNonce::from_slice(&nonce), ciphertext.as_ref())
+                .map_err(|_| CredentialStoreError::WrongPassphrase)?,
+        );
+        let credentials = serde_json::from_slice(&plaintext)?;
+
+        info!("unlocked the credential store {}", self.path.display());
+        *self.unlocked.lock().unwrap() = Some(Unlocked {
+            key,
+            salt,
+            params,
+            credentials,
+        });
+        Ok(())
+    }
+
+    // 開いていなければ `None` 。
+    pub fn credentials(&self) -> Option<Vec<Credential>> {
+        self.unlocked
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|x| x.credentials.clone())
+    }
+
+    // 新しいパスフレーズで作り直す。
+    pub fn create(
+        &self,
+        passphrase: &str,
+        credentials: Vec<Credential>,
+    ) -> Result<(), CredentialStoreError> {
+        let mut salt = vec![0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let params = Params::default();
+        let key = derive_key(passphrase, &salt, params.clone())?;
+
+        *self.unlocked.lock().unwrap() = Some(Unlocked {
+            key,
+            salt,
+            params,
+            credentials: Vec::new(),
+        });
+        self.save(credentials)
+    }
+
+    // 開いたときの鍵で書き直す。
+    pub fn save(&self, credentials: Vec<Credential>) -> Result<(), CredentialStoreError> {
+        let mut unlocked = self.unlocked.lock().unwrap();
+        let unlocked = unlocked.as_mut().ok_or(CredentialStoreError::Locked)?;
+
+        let plaintext = Zeroizing::new(serde_json::to_vec(&credentials)?);
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = XChaCha20Poly1305::new(Key::from_slice(unlocked.key.as_ref()))
+            .encrypt(&nonce, plaintext.as_ref())
+            .map_err(|_| CredentialStoreError::InvalidFormat)?;
+
+        let envelope = Envelope {
+            version: STORE_VERSION,
+            m_cost: unlocked.params.m_cost(),
+            t_cost: unlocked.params.t_cost(),
+            p_cost: unlocked.params.p_cost(),
+            salt: BASE64_STANDARD.encode(&unlocked.salt),
+            nonce: BASE64_STANDARD.encode(nonce),
+            ciphertext: BASE64_STANDARD.encode(ciphertext),
+        };
+
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let tmp = self.path.with_extension("enc.tmp");
+        write_private(&tmp, serde_json::to_string_pretty(&envelope)?.as_bytes())?;
+        std::fs::rename(&tmp, &self.path)?;
+
+        unlocked.credentials = credentials;
+        Ok(())
+    }
+}
+
+fn derive_key(
+    passphrase: &str,
+    salt: &[u8],
+    params: Params,
+) -> Result<Zeroizing<[u8; KEY_LEN]>, CredentialStoreError> {
+    let mut key = Zeroizing::new([0u8; KEY_LEN]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params).hash_password_into(
+        passphrase.as_bytes(),
+        salt,
+        key.as_mut(),
+    )?;
+    Ok(key)
+}
+
+// 持ち主だけが読めるように書く。
+fn write_private(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    file.write_all(bytes)?;
+    file.sync_all()
+}
+
+// 中身を上書きしてから消す。
+fn wipe(path: &Path) -> std::io::Result<()> {
+    let len = std::fs::metadata(path)?.len();
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    file.write_all(&vec![0u8; len as usize])?;
+    file.sync_all()?;
+    drop(file);
+    std::fs::remove_file(path)
+}
+
+// `mi-merge migrate-credentials` 。
+// 設定ファイル (無ければ以前の `credentials.json`) の平文の API キーを暗号化したファイルへ移し、平文を消す。
+pub fn migrate_credentials() -> Result<(), Box<dyn Error>> {
+    let store = CredentialStore::from_env();
+    let path = config_path();
+
+    config::migrate_legacy(&path)?;
+    let mut config = config::read_raw(&path)?;
+    let plaintext = std::mem::take(&mut config.credentials);
+    let legacy = Path::new(config::LEGACY_CREDENTIALS);
+    let legacy_credentials: Vec<Credential> = if legacy.exists() {
+        serde_json::from_str(&std::fs::read_to_string(legacy)?)?
+    } else {
+        Vec::new()
+    };
+    if plaintext.is_empty() && legacy_credentials.is_empty() {
+        println!("no plaintext credentials found in {}", path.display());
+        return Ok(());
+    }
+
+    // 設定ファイルにあるものを優先する。
+    let mut imported = plaintext;
+    for x in legacy_credentials {
+        if !imported
+            .iter()
+            .any(|y| y.host == x.host && y.user == x.user)
+        {
+            imported.push(x);
+        }
+    }
+
+    if store.exists() {
+        let passphrase = Zeroizing::new(rpassword::prompt_password(format!(
+            "passphrase for {}: ",
+            store.path().display()
+        ))?);
+        store.unlock(&passphrase)?;
+
+        let mut credentials = store.credentials().unwrap_or_default();
+        for x in imported {
+            credentials.retain(|y| !(y.host == x.host && y.user == x.user));
+            credentials.push(x);
+        }
+        store.save(credentials)?;
+    } else {
+        let passphrase = Zeroizing::new(rpassword::prompt_password("new passphrase: ")?);
+        let confirmation = Zeroizing::new(rpassword::prompt_password("confirm passphrase: ")?);
+        if passphrase.is_empty() {
+            return Err("the passphrase must not be empty".into());
+        }
+        if passphrase != confirmation {
+            return Err("the passphrases do not match".into());
+        }
+        store.create(&passphrase, imported)?;
+    }
+    println!("saved the credentials to {}", store.path().display());
+
+    // 暗号化したファイルを書けてから、平文を消す。
+    config::save(&path, &config)?;
+    println!("removed the credentials from {}", path.display());
+    if legacy.exists() {
+        wipe(legacy)?;
+        println!("wiped {}", legacy.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credentials() -> Vec<Credential> {
+        vec![Credential {
+            host: "misskey.io".to_owned(),
+            user: "alice".to_owned(),
+            api_key: "very-secret-key".to_owned(),
+            disable: false,
+            sensitive_media: None,
+        }]
+    }
+
+    fn created(dir: &Path) -> PathBuf {
+        let path = dir.join(CREDENTIAL_STORE_FILE_NAME);
+        CredentialStore::new(path.clone())
+            .create("correct horse", credentials())
+            .unwrap();
+        path
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = CredentialStore::new(created(dir.path()));
+        assert!(store.is_locked());
+        assert_eq!(store.credentials(), None);
+
+        store.unlock("correct horse").unwrap();
+        assert!(!store.is_locked());
+        assert_eq!(store.credentials(), Some(credentials()));
+
+        // 開いたときの鍵で書き直したものも、同じパスフレーズで開ける。
+        let mut more = credentials();
+        more[0].disable = true;
+        store.save(more.clone()).unwrap();
+        let reopened = CredentialStore::new(store.path().to_owned());
+        reopened.unlock("correct horse").unwrap();
+        assert_eq!(reopened.credentials(), Some(more));
+    }
+
+    #[test]
+    fn wrong_passphrase() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = CredentialStore::new(created(dir.path()));
+
+        let result = store.unlock("wrong horse");
+        assert!(matches!(result, Err(CredentialStoreError::WrongPassphrase)));
+        assert!(store.is_locked());
+        assert!(matches!(
+            store.save(credentials()),
+            Err(CredentialStoreError::Locked)
+        ));
+    }
+
+    #[test]
+    fn tampered_ciphertext() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = created(dir.path());
+
+        let mut envelope: Envelope =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        let mut ciphertext = BASE64_STANDARD.decode(&envelope.ciphertext).unwrap();
+        ciphertext[0] ^= 1;
+        envelope.ciphertext = BASE64_STANDARD.encode(ciphertext);
+        std::fs::write(&path, serde_json::to_string(&envelope).unwrap()).unwrap();
+
+        let store = CredentialStore::new(path);
+        let result = store.unlock("correct horse");
+        assert!(matches!(result, Err(CredentialStoreError::WrongPassphrase)));
+        assert!(store.is_locked());
+    }
+
+    #[test]
+    fn debug_hides_secrets() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = CredentialStore::new(created(dir.path()));
+        store.unlock("correct horse").unwrap();
+
+        let debug = format!("{store:?}");
+        assert!(debug.contains("unlocked: true"), "{debug}");
+        let debug = format!("{:?}", store.unlocked.lock().unwrap());
+        assert!(!debug.contains("very-secret-key"), "{debug}");
+        let debug = format!("{:?}", credentials());
+        assert!(!debug.contains("very-secret-key"), "{debug}");
+    }
+}
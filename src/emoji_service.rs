@@ -1,19 +1,96 @@
-use std::{collections::HashMap, error::Error};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::PathBuf,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
 
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
-use tracing::info;
+use tokio::sync::OnceCell;
+use tracing::{info, warn};
 
-use crate::{common_types::Host, mi_models::EmojiSimple};
+use crate::{
+    common_types::Host,
+    config,
+    mi_models::{EmojiSimple, EmojisResponse, Note, User},
+};
 
-#[derive(Debug, Clone, Default)]
+const CATALOGUE_CACHE_DIR: &str = "emojis";
+const CATALOGUE_CACHE_VERSION: u32 = 1;
+const CATALOGUE_TTL: Duration = Duration::from_secs(60 * 60);
+const MISS_TTL: Duration = Duration::from_secs(10 * 60);
+
+// 通信の失敗は、一覧に無いこととは限らないので、早めに問い合わせ直す。
+const FAILURE_RETRY: Duration = Duration::from_secs(30);
+
+type EmojiKey = (Host, String);
+type InFlight = Arc<OnceCell<Result<EmojiSimple, EmojiServiceError>>>;
+
+// ロックはネットワーク I/O を挟まずにすぐ手放す。
+// 同じ絵文字への同時の問い合わせは、1つのリクエストを共有する。
+#[derive(Debug, Default)]
 pub struct EmojiService {
-    cache: HashMap<(Host, String), EmojiSimple>,
+    client: reqwest::Client,
+
+    // ローカルの代役のサーバーで試せるように、URL の前半を差し替えられる。
+    base_url: Option<String>,
+
+    state: RwLock<EmojiServiceState>,
+    in_flight: Mutex<HashMap<EmojiKey, InFlight>>,
+}
+
+#[derive(Debug, Default)]
+struct EmojiServiceState {
+    cache: HashMap<EmojiKey, EmojiSimple>,
+
+    // 新しい一覧を取得済みのホストでは、一覧に無い絵文字を問い合わせない。
+    catalogues: HashMap<Host, Catalogue>,
+    misses: HashMap<EmojiKey, Miss>,
+
+    // `/api/emoji` が無いホスト。
+    no_lookup: HashSet<Host>,
+}
+
+#[derive(Debug)]
+struct Catalogue {
+    emojis: Vec<EmojiSimple>,
+
+    // ディスクから読んだものは `None` 。取り直すまでは古いものとして扱う。
+    fetched_at: Option<Instant>,
 }
 
 #[derive(Debug)]
+struct Miss {
+    at: Instant,
+    error: EmojiServiceError,
+}
+
+impl Miss {
+    fn is_fresh(&self) -> bool {
+        let ttl = match self.error {
+            EmojiServiceError::NotFound => MISS_TTL,
+            _ => FAILURE_RETRY,
+        };
+        self.at.elapsed() < ttl
+    }
+}
+
+impl Catalogue {
+    fn is_fresh(&self) -> bool {
+        self.fetched_at.is_some_and(|x| x.elapsed() < CATALOGUE_TTL)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum EmojiServiceError {
     HttpRequestError,
     InvalidFormatResponse,
+    NotFound,
 }
 
 impl std::fmt::Display for EmojiServiceError {
@@ -25,39 +102,202 @@ impl std::fmt::Display for EmojiServiceError {
             EmojiServiceError::InvalidFormatResponse => {
                 write!(f, "invalid format response")
             }
+            EmojiServiceError::NotFound => {
+                write!(f, "emoji not found")
+            }
         }
     }
 }
 
 impl Error for EmojiServiceError {}
 
+#[derive(Serialize, Deserialize)]
+struct CatalogueCacheFile {
+    version: u32,
+    host: String,
+    emojis: Vec<EmojiSimple>,
+}
+
 impl EmojiService {
     pub fn new() -> Self {
         Self::default()
     }
 
-    pub async fn fetch(
-        &mut self,
-        host: &Host,
-        name: &str,
-    ) -> Result<EmojiSimple, EmojiServiceError> {
-        use std::collections::hash_map::Entry;
+    pub fn with_base_url(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: Some(base_url.into()),
+            ..Self::default()
+        }
+    }
+
+    fn api_url(&self, host: &Host, endpoint: &str) -> String {
+        match &self.base_url {
+            Some(base_url) => format!("{base_url}/api/{endpoint}"),
+            None => format!("https://{host}/api/{endpoint}"),
+        }
+    }
 
+    pub async fn fetch(&self, host: &Host, name: &str) -> Result<EmojiSimple, EmojiServiceError> {
         let key = (host.clone(), name.to_owned());
-        let entry = self.cache.entry(key);
-        let entry = match entry {
-            Entry::Occupied(cached) => return Ok(cached.get().clone()),
-            Entry::Vacant(entry) => entry,
+        if let Some(cached) = self.state.read().unwrap().lookup(&key) {
+            return cached;
+        }
+
+        let cell = self
+            .in_flight
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .clone();
+
+        let res = cell
+            .get_or_init(|| async {
+                info!("fetching emoji info of :{}@{}:", name, host);
+                let res = self.fetch_emoji(host, name).await;
+
+                let mut state = self.state.write().unwrap();
+                match &res {
+                    Ok(emoji) => {
+                        state.misses.remove(&key);
+                        state.cache.insert(key.clone(), emoji.clone());
+                    }
+                    Err(error) => {
+                        let miss = Miss {
+                            at: Instant::now(),
+                            error: error.clone(),
+                        };
+                        state.misses.insert(key.clone(), miss);
+                    }
+                }
+                res
+            })
+            .await
+            .clone();
+
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if in_flight.get(&key).is_some_and(|x| Arc::ptr_eq(x, &cell)) {
+            in_flight.remove(&key);
+        }
+
+        res
+    }
+
+    pub fn catalogue(&self, host: &Host) -> Option<Vec<EmojiSimple>> {
+        self.state
+            .read()
+            .unwrap()
+            .catalogues
+            .get(host)
+            .map(|x| x.emojis.clone())
+    }
+
+    // `fetched_at` が `None` なら、一覧に無い絵文字もこれまでどおり問い合わせる。
+    pub fn install_catalogue(
+        &self,
+        host: Host,
+        emojis: Vec<EmojiSimple>,
+        fetched_at: Option<Instant>,
+    ) {
+        let mut state = self.state.write().unwrap();
+        for emoji in &emojis {
+            state
+                .cache
+                .insert((host.clone(), emoji.name.clone()), emoji.clone());
+        }
+        state.misses.retain(|(h, _), _| h != &host);
+        state
+            .catalogues
+            .insert(host, Catalogue { emojis, fetched_at });
+    }
+
+    // 古いサーバーには問い合わせる先が無いので、キャッシュに無ければ見つからないものとする。
+    pub fn disable_lookup(&self, host: Host) {
+        self.state.write().unwrap().no_lookup.insert(host);
+    }
+
+    pub fn insert(&self, host: Host, name: String, url: String) {
+        let emoji = EmojiSimple {
+            name: name.clone(),
+            url,
+            ..Default::default()
         };
+        self.state
+            .write()
+            .unwrap()
+            .cache
+            .insert((host, name), emoji);
+    }
+
+    // ノートに同梱されている絵文字を、問い合わせずに済むようキャッシュに入れておく。
+    pub fn insert_from_note(&self, note: &Note, source_host: &Host) {
+        let host = note
+            .user
+            .host
+            .clone()
+            .map(Host::from)
+            .unwrap_or(source_host.clone());
+
+        for (name, url) in &note.emojis {
+            self.insert(host.clone(), name.clone(), url.clone());
+        }
+        for (name, url) in &note.reaction_emojis {
+            self.insert_qualified(name, url, source_host);
+        }
+
+        self.insert_from_user(&note.user, source_host);
+
+        if let Some(renote) = &note.renote {
+            self.insert_from_note(renote, source_host);
+        }
+        if let Some(reply) = &note.reply {
+            self.insert_from_note(reply, source_host);
+        }
+    }
+
+    pub fn insert_from_user(&self, user: &User, source_host: &Host) {
+        let host = user
+            .host
+            .clone()
+            .map(Host::from)
+            .unwrap_or(source_host.clone());
+
+        for (name, url) in &user.emojis {
+            self.insert(host.clone(), name.clone(), url.clone());
+        }
+    }
+
+    // `reacted` イベントの絵文字。名前は "name@host" か "name@." の形。
+    pub fn insert_from_reaction(&self, emoji: &EmojiSimple, source_host: &Host) {
+        self.insert_qualified(&emoji.name, &emoji.url, source_host);
+    }
 
-        info!("fetching emoji info of :{}@{}:", name, host);
+    fn insert_qualified(&self, qualified_name: &str, url: &str, source_host: &Host) {
+        let (name, host) = match qualified_name.split_once('@') {
+            Some((name, ".")) => (name, source_host.clone()),
+            Some((name, host)) => (name, Host::from(host.to_owned())),
+            None => (qualified_name, source_host.clone()),
+        };
+        self.insert(host, name.to_owned(), url.to_owned());
+    }
 
-        let res = reqwest::Client::new()
-            .post(format!("https://{host}/api/emoji"))
+    async fn fetch_emoji(&self, host: &Host, name: &str) -> Result<EmojiSimple, EmojiServiceError> {
+        let res = self
+            .client
+            .post(self.api_url(host, "emoji"))
             .json(&json!({"name": name}))
             .send()
             .await
-            .map_err(|_e| EmojiServiceError::HttpRequestError)?
+            .map_err(|_e| EmojiServiceError::HttpRequestError)?;
+
+        // 無い絵文字は `NO_SUCH_EMOJI` の 400 で返ってくる。
+        if matches!(
+            res.status(),
+            StatusCode::BAD_REQUEST | StatusCode::NOT_FOUND
+        ) {
+            return Err(EmojiServiceError::NotFound);
+        }
+        let res = res
             .error_for_status()
             .map_err(|_e| EmojiServiceError::HttpRequestError)?;
 
@@ -65,11 +305,206 @@ impl EmojiService {
             .text()
             .await
             .map_err(|_e| EmojiServiceError::HttpRequestError)?;
-        dbg!(&text);
-        let emoji: EmojiSimple =
-            serde_json::from_str(&text).map_err(|_e| EmojiServiceError::InvalidFormatResponse)?;
+        serde_json::from_str(&text).map_err(|_e| EmojiServiceError::InvalidFormatResponse)
+    }
+
+    async fn fetch_catalogue(&self, host: &Host) -> Result<Vec<EmojiSimple>, EmojiServiceError> {
+        info!("fetching emoji list of {host}");
+
+        let res = self
+            .client
+            .post(self.api_url(host, "emojis"))
+            .json(&json!({}))
+            .send()
+            .await
+            .map_err(|_e| EmojiServiceError::HttpRequestError)?
+            .error_for_status()
+            .map_err(|_e| EmojiServiceError::HttpRequestError)?;
+
+        let res: EmojisResponse = res
+            .json()
+            .await
+            .map_err(|_e| EmojiServiceError::InvalidFormatResponse)?;
+        Ok(res.emojis)
+    }
+}
+
+impl EmojiServiceState {
+    // 問い合わせるまでもなく結果が分かる場合は `Some` を返す。
+    fn lookup(&self, key: &EmojiKey) -> Option<Result<EmojiSimple, EmojiServiceError>> {
+        if let Some(cached) = self.cache.get(key) {
+            return Some(Ok(cached.clone()));
+        }
+        let is_fresh = self.catalogues.get(&key.0).is_some_and(Catalogue::is_fresh);
+        if is_fresh || self.no_lookup.contains(&key.0) {
+            return Some(Err(EmojiServiceError::NotFound));
+        }
+        if let Some(miss) = self.misses.get(key).filter(|x| x.is_fresh()) {
+            return Some(Err(miss.error.clone()));
+        }
+        None
+    }
+}
 
-        entry.insert(emoji.clone());
-        Ok(emoji)
+// ディスク上のキャッシュがあれば先に読み込み、その後サーバーから取り直す。
+pub async fn refresh_catalogue(service: &EmojiService, host: Host) {
+    let cached = load_catalogue_cache(&host)
+        .map_err(|e| warn!("failed to load emoji cache of {host}: {e}"))
+        .ok()
+        .flatten();
+    if let Some(emojis) = cached {
+        service.install_catalogue(host.clone(), emojis, None);
+    }
+
+    match service.fetch_catalogue(&host).await {
+        Ok(emojis) => {
+            if let Err(e) = save_catalogue_cache(&host, &emojis) {
+                warn!("failed to save emoji cache of {host}: {e}");
+            }
+            service.install_catalogue(host, emojis, Some(Instant::now()));
+        }
+        Err(e) => warn!("failed to fetch emoji list of {host}: {e}"),
+    }
+}
+
+fn catalogue_cache_path(host: &Host) -> PathBuf {
+    let file_name = host.to_string().replace([':', '/', '\\'], "_");
+    config::cache_dir()
+        .join(CATALOGUE_CACHE_DIR)
+        .join(format!("{file_name}.json"))
+}
+
+fn load_catalogue_cache(host: &Host) -> Result<Option<Vec<EmojiSimple>>, Box<dyn Error>> {
+    let path = catalogue_cache_path(host);
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let file: CatalogueCacheFile = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+    if file.version != CATALOGUE_CACHE_VERSION || file.host != host.to_string() {
+        return Ok(None);
+    }
+    Ok(Some(file.emojis))
+}
+
+fn save_catalogue_cache(host: &Host, emojis: &[EmojiSimple]) -> Result<(), Box<dyn Error>> {
+    let path = catalogue_cache_path(host);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let file = CatalogueCacheFile {
+        version: CATALOGUE_CACHE_VERSION,
+        host: host.to_string(),
+        emojis: emojis.to_vec(),
+    };
+    serde_json::to_writer(BufWriter::new(File::create(path)?), &file)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::future::join_all;
+
+    use super::*;
+    use crate::test_server::TestServer;
+
+    fn emoji(name: &str) -> EmojiSimple {
+        EmojiSimple {
+            name: name.to_owned(),
+            url: format!("https://example.com/{name}.png"),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn stale_catalogue_falls_through_to_lookup() {
+        let host = Host::from("example.com".to_owned());
+        let service = EmojiService::new();
+        service.install_catalogue(host.clone(), vec![emoji("known")], None);
+
+        let state = service.state.read().unwrap();
+        assert!(matches!(
+            state.lookup(&(host.clone(), "known".to_owned())),
+            Some(Ok(_))
+        ));
+        assert!(state.lookup(&(host, "added_later".to_owned())).is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn concurrent_fetches_share_one_request() {
+        let server = TestServer::start(|path, body| {
+            assert_eq!(path, "/api/emoji");
+            assert!(body.contains("\"blobcat\""));
+            std::thread::sleep(Duration::from_millis(200));
+            (200, json!(emoji("blobcat")).to_string())
+        });
+        let service = EmojiService::with_base_url(server.base_url());
+        let host = Host::from("example.com".to_owned());
+
+        let results = join_all((0..16).map(|_| service.fetch(&host, "blobcat"))).await;
+        assert!(results
+            .iter()
+            .all(|x| x.as_ref().unwrap().name == "blobcat"));
+        assert_eq!(server.requests(), 1);
+
+        // 取得したものはキャッシュから返す。
+        service.fetch(&host, "blobcat").await.unwrap();
+        assert_eq!(server.requests(), 1);
+    }
+
+    #[test]
+    fn fresh_catalogue_answers_not_found() {
+        let host = Host::from("example.com".to_owned());
+        let service = EmojiService::new();
+        service.install_catalogue(host.clone(), vec![emoji("known")], Some(Instant::now()));
+
+        let state = service.state.read().unwrap();
+        assert!(matches!(
+            state.lookup(&(host.clone(), "missing".to_owned())),
+            Some(Err(EmojiServiceError::NotFound))
+        ));
+
+        let expired = Instant::now().checked_sub(CATALOGUE_TTL + Duration::from_secs(1));
+        drop(state);
+        service.install_catalogue(host.clone(), vec![emoji("known")], expired);
+        let state = service.state.read().unwrap();
+        assert!(state.lookup(&(host, "missing".to_owned())).is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn failures_are_retried_sooner_than_misses() {
+        let server = TestServer::start(|_, body| {
+            if body.contains("\"missing\"") {
+                (400, r#"{"error":{"code":"NO_SUCH_EMOJI"}}"#.to_owned())
+            } else {
+                (500, String::new())
+            }
+        });
+        let service = EmojiService::with_base_url(server.base_url());
+        let host = Host::from("example.com".to_owned());
+
+        assert!(matches!(
+            service.fetch(&host, "missing").await,
+            Err(EmojiServiceError::NotFound)
+        ));
+        assert!(matches!(
+            service.fetch(&host, "broken").await,
+            Err(EmojiServiceError::HttpRequestError)
+        ));
+
+        // どちらも、すぐには問い合わせ直さない。
+        service.fetch(&host, "missing").await.unwrap_err();
+        service.fetch(&host, "broken").await.unwrap_err();
+        assert_eq!(server.requests(), 2);
+
+        // 通信の失敗だけが、`FAILURE_RETRY` の後に問い合わせ直される。
+        let later = Instant::now().checked_sub(FAILURE_RETRY).unwrap();
+        for miss in service.state.write().unwrap().misses.values_mut() {
+            miss.at = later;
+        }
+        service.fetch(&host, "missing").await.unwrap_err();
+        service.fetch(&host, "broken").await.unwrap_err();
+        assert_eq!(server.requests(), 3);
     }
 }
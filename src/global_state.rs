@@ -2,7 +2,11 @@ use std::sync::OnceLock;
 
 use tokio::sync::RwLock;
 
-use crate::{app_model::AppModel, emoji_service::EmojiService, mfm::Decomposer};
+use crate::{
+    app_model::AppModel, blurhash_decoder::BlurhashDecoder, common_types::StorageSettings,
+    credential_store::CredentialStore, emoji_service::EmojiService, media_cache::MediaCache,
+    mfm::Decomposer, note_store::NoteStore, search_index::SearchIndex,
+};
 
 pub static APP_MODEL: OnceLock<RwLock<AppModel>> = OnceLock::new();
 
@@ -16,8 +20,45 @@ pub fn get_decomposer() -> &'static Decomposer {
     DECOMPOSER.get_or_init(|| Decomposer::new())
 }
 
-pub static EMOJI_SERVICE: OnceLock<RwLock<EmojiService>> = OnceLock::new();
+pub static BLURHASH_DECODER: OnceLock<BlurhashDecoder> = OnceLock::new();
 
-pub fn get_emoji_service() -> &'static RwLock<EmojiService> {
-    EMOJI_SERVICE.get_or_init(|| RwLock::new(EmojiService::new()))
+pub fn get_blurhash_decoder() -> &'static BlurhashDecoder {
+    BLURHASH_DECODER.get_or_init(BlurhashDecoder::new)
+}
+
+pub static EMOJI_SERVICE: OnceLock<EmojiService> = OnceLock::new();
+
+pub fn get_emoji_service() -> &'static EmojiService {
+    EMOJI_SERVICE.get_or_init(EmojiService::new)
+}
+
+// 再起動するまで変えられない。設定ファイルを読む前に使われたら、既定値のままになる。
+pub static STORAGE_SETTINGS: OnceLock<StorageSettings> = OnceLock::new();
+
+pub fn get_storage_settings() -> &'static StorageSettings {
+    STORAGE_SETTINGS.get_or_init(StorageSettings::default)
+}
+
+pub static MEDIA_CACHE: OnceLock<MediaCache> = OnceLock::new();
+
+pub fn get_media_cache() -> &'static MediaCache {
+    MEDIA_CACHE.get_or_init(|| MediaCache::from_settings(get_storage_settings()))
+}
+
+pub static NOTE_STORE: OnceLock<NoteStore> = OnceLock::new();
+
+pub fn get_note_store() -> &'static NoteStore {
+    NOTE_STORE.get_or_init(|| NoteStore::from_settings(get_storage_settings()))
+}
+
+pub static SEARCH_INDEX: OnceLock<SearchIndex> = OnceLock::new();
+
+pub fn get_search_index() -> &'static SearchIndex {
+    SEARCH_INDEX.get_or_init(|| SearchIndex::from_settings(get_storage_settings()))
+}
+
+pub static CREDENTIAL_STORE: OnceLock<CredentialStore> = OnceLock::new();
+
+pub fn get_credential_store() -> &'static CredentialStore {
+    CREDENTIAL_STORE.get_or_init(CredentialStore::from_env)
 }
@@ -1,35 +1,77 @@
 mod app_model;
+mod blurhash_decoder;
+mod branch_rules;
 mod cached_req;
+mod clock;
 mod common_types;
+mod config;
+mod connection_status;
+mod credential_store;
 mod emoji_service;
 mod global_state;
+mod mastodon_cxn;
+mod mastodon_models;
+mod mastodon_source;
+mod media_cache;
 mod merged_timeline;
 mod mfm;
 mod mi_models;
+mod miauth;
+mod misskey_source;
+mod note_filter;
+mod note_store;
+mod pager;
+mod search_index;
+mod server_capabilities;
 mod server_cxn;
 mod server_note_repo;
+mod server_source;
+#[cfg(test)]
+mod test_server;
 mod view;
 mod ws_msg_router;
 mod ws_poller;
 
-use dioxus::{desktop::use_window, prelude::*};
+use dioxus::{
+    desktop::{
+        use_asset_handler, use_window,
+        wry::http::{Response, StatusCode},
+    },
+    prelude::*,
+};
 
-use global_state::get_app_model;
+use global_state::{
+    get_app_model, get_credential_store, get_media_cache, get_storage_settings, STORAGE_SETTINGS,
+};
 
-use tracing::Level;
+use tracing::{error, warn, Level};
 
-use crate::view::Home;
+use crate::view::{Accounts, ConfigErrors, Home, Search, Unlock};
 
 #[derive(Clone, Routable, Debug, PartialEq)]
 enum Route {
     #[route("/")]
     Home {},
+
+    #[route("/search")]
+    Search {},
+
+    #[route("/accounts")]
+    Accounts {},
 }
 
 fn main() {
     dotenv::dotenv().ok();
     dioxus_logger::init(Level::DEBUG).expect("failed to init logger");
 
+    if std::env::args().nth(1).as_deref() == Some("migrate-credentials") {
+        if let Err(e) = credential_store::migrate_credentials() {
+            eprintln!("failed to migrate the credentials: {e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
     dioxus::launch(App);
 }
 
@@ -37,17 +79,93 @@ fn main() {
 fn App() -> Element {
     use_window().window.set_always_on_top(false);
 
-    spawn(async {
-        get_app_model()
-            .write()
-            .await
-            .connect_all()
-            .await
-            .expect("TODO: connect error");
+    use_asset_handler(media_cache::MEDIA_HANDLER_NAME, |request, responder| {
+        tokio::spawn(async move {
+            let res = match get_media_cache().serve(request.uri().path()).await {
+                Ok(media) => {
+                    let mut res = Response::builder();
+                    if let Some(content_type) = media.content_type {
+                        res = res.header("Content-Type", content_type);
+                    }
+                    res.body(media.bytes)
+                }
+                Err(e) => {
+                    warn!("failed to serve media: {e}");
+                    Response::builder().status(404).body(Vec::new())
+                }
+            };
+            let res = res.unwrap_or_else(|e| {
+                error!("failed to build a media response: {e}");
+                let mut res = Response::new(Vec::new());
+                *res.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                res
+            });
+            responder.respond(res);
+        });
     });
 
+    // API キーを暗号化していれば、パスフレーズを聞いてから設定を読む。
+    let mut locked = use_signal(|| get_credential_store().is_locked());
+
     rsx! {
         link { rel: "stylesheet", href: "main.css" }
+        if locked() {
+            Unlock { onunlock: move |_| locked.set(false) }
+        } else {
+            Main {}
+        }
+    }
+}
+
+#[component]
+fn Main() -> Element {
+    // 設定に問題があれば、繋がずにエラー画面を出す。
+    let config = use_hook(|| -> Result<config::Config, Vec<String>> {
+        let config = config::load().map_err(|e| e.messages())?;
+        if STORAGE_SETTINGS.set(config.storage.clone()).is_err() {
+            warn!("the storage settings were used before the config was loaded");
+        }
+        // キャッシュのディレクトリを読むので、非同期の処理から使う前に作っておく。
+        get_media_cache();
+        Ok(config)
+    });
+
+    use_hook({
+        let config = config.clone();
+        move || {
+            if let Ok(config) = config {
+                spawn(async {
+                    let mut changes = config::watch();
+
+                    let connected = app_model::connect_all(get_app_model(), config).await;
+                    if let Err(e) = connected {
+                        error!("failed to start: {e}");
+                    }
+
+                    // 起動した後の設定の誤りは、前の設定のまま動き続ける。
+                    while let Some(config) = changes.recv().await {
+                        match config {
+                            Ok(config) => {
+                                if &config.storage != get_storage_settings() {
+                                    warn!("restart to apply the changes to [storage]");
+                                }
+                                app_model::reload(get_app_model(), config).await
+                            }
+                            Err(e) => warn!("ignored the config change: {e}"),
+                        }
+                    }
+                });
+            }
+        }
+    });
+
+    if let Err(errors) = config {
+        return rsx! {
+            ConfigErrors { path: config::config_path().display().to_string(), errors }
+        };
+    }
+
+    rsx! {
         Router::<Route> {}
     }
 }
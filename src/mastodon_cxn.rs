@@ -0,0 +1,249 @@
+use async_trait::async_trait;
+use futures_util::{stream::SplitStream, StreamExt};
+use serde_json::json;
+use tokio::{
+    net::TcpStream,
+    sync::{
+        mpsc::{self, error::TryRecvError, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+};
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{
+        client::IntoClientRequest,
+        http::{header::AUTHORIZATION, HeaderValue},
+        Message,
+    },
+    MaybeTlsStream, WebSocketStream,
+};
+use tracing::{debug, info, warn};
+
+use crate::{
+    common_types::{ChannelChannel, Host},
+    mastodon_models::{Status, StreamingMessage},
+    mi_models::{WsMsg, WsMsgChannelBody},
+    server_cxn::{SendThr, ServerCxnError, ThrResource},
+    server_source::ServerStream,
+};
+
+struct RecvThr {
+    tx: UnboundedSender<WsMsg>,
+    ws_rx: SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>,
+}
+
+impl RecvThr {
+    async fn run(mut self) -> UnboundedSender<WsMsg> {
+        while let Some(Ok(m)) = self.ws_rx.next().await {
+            let m = match m {
+                Message::Text(m) => m,
+                Message::Ping(_) => continue,
+                m => {
+                    debug!("{m:?}");
+                    continue;
+                }
+            };
+            let m = match serde_json::from_str::<StreamingMessage>(&m) {
+                Ok(m) => m,
+                Err(e) => {
+                    debug!("{e:?}");
+                    continue;
+                }
+            };
+
+            // 削除や通知などは扱わない。
+            if m.event != "update" && m.event != "status.update" {
+                debug!("skipped {} event", m.event);
+                continue;
+            }
+            let Some(payload) = m.payload else {
+                continue;
+            };
+            let status = match serde_json::from_str::<Status>(&payload) {
+                Ok(status) => status,
+                Err(e) => {
+                    warn!("skipped a status that could not be read: {e}");
+                    continue;
+                }
+            };
+
+            let m = WsMsg::Channel(WsMsgChannelBody::Note {
+                id: stream_key(&m.stream),
+                body: status.into_mi_note(),
+            });
+            debug!("{m:?}");
+
+            // `MastodonCxn` が捨てられたら、受け取るのをやめる。
+            if self.tx.send(m).is_err() {
+                break;
+            }
+        }
+
+        self.tx
+    }
+}
+
+// 届いたメッセージの `stream` から、`connect_to` が返したものと同じ ID を作る。
+// ハッシュタグの大文字と小文字は、サーバーによって揃えたり揃えなかったりする。
+fn stream_key(stream: &[String]) -> String {
+    match stream {
+        [name, tag] if name == "hashtag" => format!("hashtag:{}", tag.to_lowercase()),
+        xs => xs.join(":"),
+    }
+}
+
+// Mastodon のストリーミング API 。1本の WebSocket に複数のストリームを載せる。
+// チャンネルの ID には、ストリームの名前をそのまま使う。
+pub struct MastodonCxn {
+    host: Host,
+    api_key: String,
+    streaming_url: String,
+
+    outlet: UnboundedReceiver<WsMsg>,
+    inlet: UnboundedSender<String>,
+
+    recv_thr: ThrResource<UnboundedSender<WsMsg>>,
+    send_thr: ThrResource<(UnboundedReceiver<String>, Option<String>)>,
+
+    closed: bool,
+}
+
+impl std::fmt::Debug for MastodonCxn {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("MastodonCxn")
+            .field("host", &self.host)
+            .field("api_key", &"<redacted>")
+            .field("streaming_url", &self.streaming_url)
+            .field("closed", &self.closed)
+            .finish_non_exhaustive()
+    }
+}
+
+impl MastodonCxn {
+    pub fn new(host: Host, api_key: String, streaming_url: String) -> Self {
+        let (outlet_tx, outlet) = mpsc::unbounded_channel::<WsMsg>();
+        let (inlet, inlet_rx) = mpsc::unbounded_channel::<String>();
+        Self {
+            host,
+            api_key,
+            streaming_url,
+
+            outlet,
+            inlet,
+            recv_thr: ThrResource::Offline(outlet_tx),
+            send_thr: ThrResource::Offline((inlet_rx, None)),
+
+            closed: false,
+        }
+    }
+
+    // 閉じた後に送ったものは捨てる。
+    fn send(&self, message: String) {
+        if self.inlet.send(message).is_err() {
+            debug!("dropped a message to closed {}", self.host);
+        }
+    }
+}
+
+#[async_trait]
+impl ServerStream for MastodonCxn {
+    async fn spawn(&mut self) -> Result<(), ServerCxnError> {
+        if !matches!(self.recv_thr, ThrResource::Offline(_)) {
+            return Err(ServerCxnError::AlreadySpawnedError);
+        }
+        info!("connecting to {}", self.host);
+        // トークンは URL に載せず、ヘッダーで渡す。URL はログやエラーに出やすい。
+        let mut req = format!(
+            "{}/api/v1/streaming",
+            self.streaming_url.trim_end_matches('/')
+        )
+        .into_client_request()
+        .map_err(|_| ServerCxnError::ConnectError)?;
+        let authorization = HeaderValue::from_str(&format!("Bearer {}", self.api_key))
+            .map_err(|_| ServerCxnError::ConnectError)?;
+        req.headers_mut().insert(AUTHORIZATION, authorization);
+        let (ws, _res) = connect_async(req)
+            .await
+            .map_err(|_| ServerCxnError::ConnectError)?;
+        let (ws_tx, ws_rx) = ws.split();
+        let (idle_tx, idle_rs) = oneshot::channel::<()>();
+
+        self.recv_thr
+            .into_online(|tx| RecvThr { tx, ws_rx }.run())?;
+        self.send_thr.into_online(|(rx, pending)| {
+            SendThr {
+                rx,
+                pending,
+                ws_tx,
+                idle_tx: Some(idle_tx),
+            }
+            .run()
+        })?;
+
+        match idle_rs.await {
+            Ok(_) => info!("the receive thread is idle"),
+            Err(_) => warn!("the receive thread has downed before it becomes idle"),
+        }
+
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        info!("disconnecting from {}", self.host);
+        self.recv_thr.abort();
+        self.send_thr.abort();
+        self.closed = true;
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    fn connect_to(&mut self, channel: &ChannelChannel) -> String {
+        let (message, key) = match channel {
+            ChannelChannel::HomeTimeline => (
+                json!({ "type": "subscribe", "stream": "user" }),
+                "user".to_owned(),
+            ),
+            ChannelChannel::LocalTimeline => (
+                json!({ "type": "subscribe", "stream": "public:local" }),
+                "public:local".to_owned(),
+            ),
+            ChannelChannel::Hashtag { tag } => (
+                json!({ "type": "subscribe", "stream": "hashtag", "tag": tag }),
+                format!("hashtag:{}", tag.to_lowercase()),
+            ),
+            ChannelChannel::Channel { channel_id } => {
+                warn!("{} has no channels", self.host);
+                return format!("channel:{channel_id}");
+            }
+        };
+        self.send(message.to_string());
+        key
+    }
+
+    fn disconnect(&mut self, channel_id: &str) {
+        let message = match channel_id.split_once(':') {
+            Some(("hashtag", tag)) => {
+                json!({ "type": "unsubscribe", "stream": "hashtag", "tag": tag })
+            }
+            Some(("channel", _)) => return,
+            _ => json!({ "type": "unsubscribe", "stream": channel_id }),
+        };
+        self.send(message.to_string());
+    }
+
+    // リアクションの増減は届かない。
+    fn subscribe_note(&mut self, _note_id: &str) {}
+
+    fn unsubscribe_note(&mut self, _note_id: &str) {}
+
+    fn try_recv(&mut self) -> Result<WsMsg, TryRecvError> {
+        match self.outlet.try_recv() {
+            Err(TryRecvError::Empty) if self.recv_thr.is_finished() => {
+                Err(TryRecvError::Disconnected)
+            }
+            x => x,
+        }
+    }
+}
@@ -0,0 +1,526 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+use chrono::prelude::*;
+use regex::{Captures, Regex};
+use serde::Deserialize;
+
+use crate::mi_models::{
+    deserialize_null_default, DriveFile, DriveFileProperties, Note, OnlineStatus, Poll, PollChoice,
+    User, Visibility,
+};
+
+// お気に入りは、Misskey の「いいね」と同じリアクションとして数える。
+pub const FAVOURITE_REACTION: &str = "❤";
+
+// Mastodon の API の応答のうち、使うものだけ。GoToSocial などは一部を省くので、既定値で補う。
+#[derive(Deserialize, Debug, Clone)]
+pub struct Status {
+    pub id: String,
+
+    pub uri: String,
+
+    #[serde(default)]
+    pub url: Option<String>,
+
+    pub created_at: DateTime<Utc>,
+
+    pub account: Account,
+
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    pub content: String,
+
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    pub spoiler_text: String,
+
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    pub sensitive: bool,
+
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    pub visibility: String,
+
+    #[serde(default)]
+    pub in_reply_to_id: Option<String>,
+
+    #[serde(default)]
+    pub reblog: Option<Box<Status>>,
+
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    pub media_attachments: Vec<MediaAttachment>,
+
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    pub mentions: Vec<Mention>,
+
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    pub tags: Vec<Tag>,
+
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    pub emojis: Vec<CustomEmoji>,
+
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    pub reblogs_count: i64,
+
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    pub favourites_count: i64,
+
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    pub replies_count: i64,
+
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    pub favourited: bool,
+
+    #[serde(default)]
+    pub poll: Option<StatusPoll>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Account {
+    pub id: String,
+
+    pub username: String,
+
+    // ローカルのアカウントは "username" 、リモートは "username@domain" 。
+    pub acct: String,
+
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    pub display_name: String,
+
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    pub avatar: String,
+
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    pub emojis: Vec<CustomEmoji>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct MediaAttachment {
+    pub id: String,
+
+    // "image" 、"gifv" 、"video" 、"audio" 、"unknown" 。
+    #[serde(rename = "type")]
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    pub type_: String,
+
+    #[serde(default)]
+    pub url: Option<String>,
+
+    #[serde(default)]
+    pub preview_url: Option<String>,
+
+    #[serde(default)]
+    pub remote_url: Option<String>,
+
+    #[serde(default)]
+    pub description: Option<String>,
+
+    #[serde(default)]
+    pub blurhash: Option<String>,
+
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    pub meta: MediaMeta,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct MediaMeta {
+    #[serde(default)]
+    pub original: Option<MediaSize>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct MediaSize {
+    #[serde(default)]
+    pub width: Option<i64>,
+
+    #[serde(default)]
+    pub height: Option<i64>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Mention {
+    pub id: String,
+    pub url: String,
+    pub acct: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Tag {
+    pub name: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CustomEmoji {
+    pub shortcode: String,
+    pub url: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct StatusPoll {
+    #[serde(default)]
+    pub expires_at: Option<String>,
+
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    pub multiple: bool,
+
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    pub options: Vec<PollOption>,
+
+    // 投票した選択肢の番号。
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    pub own_votes: Vec<usize>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PollOption {
+    pub title: String,
+
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    pub votes_count: i64,
+}
+
+// `/api/v1/streaming` から届くメッセージ。`payload` は JSON を文字列にしたもの。
+#[derive(Deserialize, Debug, Clone)]
+pub struct StreamingMessage {
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    pub stream: Vec<String>,
+
+    pub event: String,
+
+    #[serde(default)]
+    pub payload: Option<String>,
+}
+
+impl Account {
+    // リモートのアカウントのドメイン。
+    pub fn host(&self) -> Option<String> {
+        self.acct.split_once('@').map(|(_, host)| host.to_owned())
+    }
+
+    fn into_mi_user(self) -> User {
+        User {
+            host: self.host(),
+            name: (!self.display_name.is_empty()).then_some(self.display_name),
+            online_status: OnlineStatus::Unknown,
+            avatar_url: self.avatar,
+            avatar_blurhash: None,
+            emojis: emoji_map(self.emojis),
+            instance: None,
+            extra: Default::default(),
+            id: self.id,
+            username: self.username,
+        }
+    }
+}
+
+impl Status {
+    // Misskey のノートの形に直す。ブーストは本文の無いリノートになる。
+    pub fn into_mi_note(self) -> Note {
+        let visibility = match self.visibility.as_str() {
+            "public" => Visibility::Public,
+            "unlisted" => Visibility::Home,
+            "private" => Visibility::Followers,
+            "direct" => Visibility::Specified,
+            _ => Visibility::Unknown,
+        };
+
+        let renote = self.reblog.map(|x| Box::new(x.into_mi_note()));
+        let text = match renote {
+            Some(_) => None,
+            None => Some(html_to_text(&self.content, &self.mentions)),
+        };
+
+        let reactions = if self.favourites_count > 0 {
+            HashMap::from([(FAVOURITE_REACTION.to_owned(), self.favourites_count)])
+        } else {
+            HashMap::new()
+        };
+
+        let files: Vec<_> = self
+            .media_attachments
+            .into_iter()
+            .map(|x| x.into_mi_file(self.sensitive))
+            .collect();
+
+        Note {
+            id: self.id,
+            created_at: self.created_at,
+            text,
+            cw: (!self.spoiler_text.is_empty()).then_some(self.spoiler_text),
+            user_id: self.account.id.clone(),
+            user: self.account.into_mi_user(),
+            reply: None,
+            reply_id: self.in_reply_to_id,
+            renote_id: renote.as_ref().map(|x| x.id.clone()),
+            renote,
+            file_ids: files.iter().map(|x| x.id.clone()).collect(),
+            files,
+            visibility,
+            visible_user_ids: None,
+            local_only: None,
+            my_reaction: self.favourited.then(|| FAVOURITE_REACTION.to_owned()),
+            reactions,
+            renote_count: self.reblogs_count,
+            replies_count: self.replies_count,
+            poll: self.poll.map(StatusPoll::into_mi_poll),
+            emojis: emoji_map(self.emojis),
+            reaction_emojis: HashMap::new(),
+            uri: Some(self.uri),
+            url: self.url,
+            is_hidden: None,
+            mentions: Some(self.mentions.into_iter().map(|x| x.id).collect()),
+            tags: Some(self.tags.into_iter().map(|x| x.name).collect()),
+            reaction_acceptance: None,
+            extra: Default::default(),
+        }
+    }
+}
+
+impl MediaAttachment {
+    fn into_mi_file(self, is_sensitive: bool) -> DriveFile {
+        // 表示に使うのは種類の部分だけ。
+        let type_ = match self.type_.as_str() {
+            "image" => "image/*",
+            "gifv" | "video" => "video/*",
+            "audio" => "audio/*",
+            _ => "application/octet-stream",
+        };
+        let size = self.meta.original.unwrap_or(MediaSize {
+            width: None,
+            height: None,
+        });
+
+        DriveFile {
+            id: self.id,
+            created_at: String::new(),
+            is_sensitive,
+            name: String::new(),
+            url: self
+                .url
+                .or(self.remote_url)
+                .or(self.preview_url.clone())
+                .unwrap_or_default(),
+            thumbnail_url: self.preview_url,
+            type_: type_.to_owned(),
+            size: 0,
+            md5: String::new(),
+            blur_hash: self.blurhash,
+            comment: self.description,
+            properties: DriveFileProperties {
+                width: size.width,
+                height: size.height,
+            },
+            extra: Default::default(),
+        }
+    }
+}
+
+impl StatusPoll {
+    fn into_mi_poll(self) -> Poll {
+        let own_votes = self.own_votes;
+        Poll {
+            expires_at: self.expires_at,
+            multiple: self.multiple,
+            choices: self
+                .options
+                .into_iter()
+                .enumerate()
+                .map(|(i, x)| PollChoice {
+                    is_voted: own_votes.contains(&i),
+                    text: x.title,
+                    votes: x.votes_count,
+                })
+                .collect(),
+        }
+    }
+}
+
+fn emoji_map(emojis: Vec<CustomEmoji>) -> HashMap<String, String> {
+    emojis.into_iter().map(|x| (x.shortcode, x.url)).collect()
+}
+
+// 本文は HTML なので、MFM として読めるテキストに直す。
+// メンションのリンクは、ドメインまで付けた "@user@host" にする。
+pub fn html_to_text(html: &str, mentions: &[Mention]) -> String {
+    static LINE_BREAK: OnceLock<Regex> = OnceLock::new();
+    static PARAGRAPH: OnceLock<Regex> = OnceLock::new();
+    static ANCHOR: OnceLock<Regex> = OnceLock::new();
+    static TAG: OnceLock<Regex> = OnceLock::new();
+
+    let line_break = LINE_BREAK.get_or_init(|| Regex::new(r"(?i)<br\s*/?>").unwrap());
+    let paragraph = PARAGRAPH.get_or_init(|| Regex::new(r"(?i)</p>\s*<p[^>]*>").unwrap());
+    let anchor = ANCHOR
+        .get_or_init(|| Regex::new(r#"(?is)<a\s[^>]*href="([^"]*)"[^>]*>(.*?)</a>"#).unwrap());
+    let tag = TAG.get_or_init(|| Regex::new(r"<[^>]*>").unwrap());
+
+    let text = line_break.replace_all(html, "\n");
+    let text = paragraph.replace_all(&text, "\n\n");
+    let text = anchor.replace_all(&text, |caps: &Captures| {
+        let href = html_escape::decode_html_entities(&caps[1]);
+        match mentions.iter().find(|x| x.url == href) {
+            Some(x) => format!("@{}", x.acct),
+            None => caps[2].to_owned(),
+        }
+    });
+    let text = tag.replace_all(&text, "");
+    html_escape::decode_html_entities(&text).trim().to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mention(acct: &str, url: &str) -> Mention {
+        Mention {
+            id: "1".to_owned(),
+            url: url.to_owned(),
+            acct: acct.to_owned(),
+        }
+    }
+
+    fn status(json: serde_json::Value) -> Status {
+        serde_json::from_value(json).unwrap()
+    }
+
+    fn account() -> serde_json::Value {
+        serde_json::json!({
+            "id": "109",
+            "username": "bob",
+            "acct": "bob@remote.example",
+            "display_name": "",
+            "avatar": "https://mastodon.example/avatars/bob.png",
+            "emojis": [{ "shortcode": "wave", "url": "https://mastodon.example/emoji/wave.png" }],
+        })
+    }
+
+    #[test]
+    fn html_to_text_keeps_mentions_and_breaks() {
+        let html = concat!(
+            r#"<p>hello <span class="h-card"><a href="https://remote.example/@carol" class="u-url mention">@<span>carol</span></a></span> &amp; "#,
+            r#"<a href="https://mastodon.example/tags/t" class="mention hashtag" rel="tag">#<span>t</span></a><br />line2</p>"#,
+            r#"<p>para</p>"#,
+        );
+        let mentions = [mention(
+            "carol@remote.example",
+            "https://remote.example/@carol",
+        )];
+        assert_eq!(
+            html_to_text(html, &mentions),
+            "hello @carol@remote.example & #t\nline2\n\npara"
+        );
+    }
+
+    #[test]
+    fn html_to_text_decodes_entities_in_links() {
+        let html =
+            r#"<p><a href="https://remote.example/users/carol?a=1&amp;b=2">@carol</a> &lt;3</p>"#;
+        let mentions = [mention(
+            "carol@remote.example",
+            "https://remote.example/users/carol?a=1&b=2",
+        )];
+        assert_eq!(html_to_text(html, &mentions), "@carol@remote.example <3");
+
+        // メンションでないリンクは、表示されている文字だけを残す。
+        let html = r#"<p>see <a href="https://example.com/">example.com</a></p>"#;
+        assert_eq!(html_to_text(html, &[]), "see example.com");
+    }
+
+    #[test]
+    fn into_mi_note() {
+        let note = status(serde_json::json!({
+            "id": "110",
+            "uri": "https://remote.example/users/bob/statuses/110",
+            "url": "https://remote.example/@bob/110",
+            "created_at": "2024-05-01T12:00:00.000Z",
+            "account": account(),
+            "content": "<p>hi :wave:</p>",
+            "spoiler_text": "cw",
+            "sensitive": true,
+            "visibility": "unlisted",
+            "in_reply_to_id": "100",
+            "media_attachments": [{
+                "id": "m1",
+                "type": "gifv",
+                "url": null,
+                "remote_url": "https://remote.example/media/m1.mp4",
+                "preview_url": "https://mastodon.example/media/m1_small.png",
+                "description": "a gif",
+                "blurhash": "UAAA",
+                "meta": { "original": { "width": 640, "height": 480 } },
+            }],
+            "favourites_count": 2,
+            "favourited": true,
+            "reblogs_count": 3,
+            "replies_count": null,
+            "poll": {
+                "expires_at": null,
+                "multiple": false,
+                "options": [{ "title": "a", "votes_count": 1 }, { "title": "b", "votes_count": null }],
+                "own_votes": [1],
+            },
+        }))
+        .into_mi_note();
+
+        assert_eq!(note.text.as_deref(), Some("hi :wave:"));
+        assert_eq!(note.cw.as_deref(), Some("cw"));
+        assert_eq!(note.visibility, Visibility::Home);
+        assert_eq!(note.reply_id.as_deref(), Some("100"));
+        assert_eq!(
+            note.uri.as_deref(),
+            Some("https://remote.example/users/bob/statuses/110")
+        );
+        assert_eq!(note.user_id, "109");
+        assert_eq!(note.user.host.as_deref(), Some("remote.example"));
+        assert_eq!(note.user.name, None);
+        assert_eq!(
+            note.user.emojis["wave"],
+            "https://mastodon.example/emoji/wave.png"
+        );
+        assert_eq!(
+            note.reactions,
+            HashMap::from([(FAVOURITE_REACTION.to_owned(), 2)])
+        );
+        assert_eq!(note.my_reaction.as_deref(), Some(FAVOURITE_REACTION));
+        assert_eq!((note.renote_count, note.replies_count), (3, 0));
+
+        let file = &note.files[0];
+        assert_eq!(note.file_ids, vec!["m1".to_owned()]);
+        assert!(file.is_sensitive);
+        assert_eq!(file.type_, "video/*");
+        assert_eq!(file.url, "https://remote.example/media/m1.mp4");
+        assert_eq!(
+            (file.properties.width, file.properties.height),
+            (Some(640), Some(480))
+        );
+
+        let poll = note.poll.unwrap();
+        assert_eq!(poll.choices[1].text, "b");
+        assert!(!poll.choices[0].is_voted && poll.choices[1].is_voted);
+        assert_eq!(poll.choices[1].votes, 0);
+    }
+
+    // ブーストは、本文の無いリノートになる。
+    #[test]
+    fn reblog_becomes_a_renote() {
+        let note = status(serde_json::json!({
+            "id": "111",
+            "uri": "https://mastodon.example/users/alice/statuses/111/activity",
+            "created_at": "2024-05-01T12:05:00.000Z",
+            "account": { "id": "1", "username": "alice", "acct": "alice" },
+            "content": "",
+            "visibility": "public",
+            "reblog": {
+                "id": "110",
+                "uri": "https://remote.example/users/bob/statuses/110",
+                "created_at": "2024-05-01T12:00:00.000Z",
+                "account": account(),
+                "content": "<p>original</p>",
+                "visibility": "public",
+            },
+        }))
+        .into_mi_note();
+
+        assert_eq!(note.text, None);
+        assert_eq!(note.user.host, None);
+        assert_eq!(note.visibility, Visibility::Public);
+        assert_eq!(note.renote_id.as_deref(), Some("110"));
+        assert_eq!(note.renote.unwrap().text.as_deref(), Some("original"));
+    }
+}
@@ -0,0 +1,140 @@
+use std::sync::OnceLock;
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    common_types::{ChannelChannel, Host, MiMergeError},
+    mastodon_cxn::MastodonCxn,
+    mastodon_models::Status,
+    mi_models::{MeDetailed, Note},
+    server_capabilities::{self, ServerCapabilities},
+    server_source::{ServerSource, ServerStream},
+};
+
+// Mastodon と、GoToSocial など同じ API を持つサーバー。`api_key` はアクセストークン。
+pub struct MastodonSource {
+    host: Host,
+    api_key: String,
+
+    // ストリーミングを別のサーバーで受けていれば、調べたときに入れる。
+    streaming_url: OnceLock<String>,
+}
+
+impl std::fmt::Debug for MastodonSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("MastodonSource")
+            .field("host", &self.host)
+            .field("api_key", &"<redacted>")
+            .field("streaming_url", &self.streaming_url)
+            .finish()
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct CredentialAccount {
+    id: String,
+    username: String,
+}
+
+impl MastodonSource {
+    pub fn new(host: Host, api_key: String) -> Self {
+        Self {
+            host,
+            api_key,
+            streaming_url: OnceLock::new(),
+        }
+    }
+
+    async fn get<T: for<'de> Deserialize<'de>>(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<T, MiMergeError> {
+        let res = reqwest::Client::new()
+            .get(format!("https://{}{path}", self.host))
+            .bearer_auth(&self.api_key)
+            .query(query)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl ServerSource for MastodonSource {
+    fn host(&self) -> &Host {
+        &self.host
+    }
+
+    async fn probe(&self) -> ServerCapabilities {
+        let (capabilities, streaming_url) = server_capabilities::probe_mastodon(&self.host).await;
+        if let Some(x) = streaming_url {
+            let _ = self.streaming_url.set(x);
+        }
+        capabilities
+    }
+
+    async fn fetch_me(&self) -> Result<MeDetailed, MiMergeError> {
+        let account: CredentialAccount =
+            self.get("/api/v1/accounts/verify_credentials", &[]).await?;
+
+        // 閲覧の設定で「常に隠す」にしていれば、Misskey の「常に NSFW にする」と同じに扱う。
+        let preferences: Value = self.get("/api/v1/preferences", &[]).await?;
+        let always_mark_nsfw = preferences["reading:expand:media"].as_str() == Some("hide_all");
+
+        Ok(MeDetailed {
+            id: account.id,
+            username: account.username,
+            always_mark_nsfw,
+            policies: Default::default(),
+        })
+    }
+
+    async fn fetch_page(
+        &self,
+        channel: &ChannelChannel,
+        until_id: Option<&str>,
+    ) -> Result<Vec<Note>, MiMergeError> {
+        let mut query = Vec::new();
+        if let Some(until_id) = until_id {
+            query.push(("max_id", until_id));
+        }
+
+        let statuses: Vec<Status> = match channel {
+            ChannelChannel::HomeTimeline => self.get("/api/v1/timelines/home", &query).await?,
+            ChannelChannel::LocalTimeline => {
+                query.push(("local", "true"));
+                self.get("/api/v1/timelines/public", &query).await?
+            }
+            ChannelChannel::Hashtag { tag } => {
+                let path = format!("/api/v1/timelines/tag/{}", urlencoding::encode(tag));
+                self.get(&path, &query).await?
+            }
+            ChannelChannel::Channel { .. } => {
+                return Err(MiMergeError::UnsupportedChannel {
+                    host: self.host.to_string(),
+                    channel: format!("{channel:?}"),
+                })
+            }
+        };
+        Ok(statuses.into_iter().map(Status::into_mi_note).collect())
+    }
+
+    fn stream(&self) -> Box<dyn ServerStream> {
+        let streaming_url = self
+            .streaming_url
+            .get()
+            .cloned()
+            .unwrap_or_else(|| format!("wss://{}", self.host));
+        Box::new(MastodonCxn::new(
+            self.host.clone(),
+            self.api_key.clone(),
+            streaming_url,
+        ))
+    }
+}
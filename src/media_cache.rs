@@ -0,0 +1,325 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use itertools::Itertools;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::common_types::StorageSettings;
+
+// アセットハンドラに登録する名前。`/media/{エンコードされた URL}` で配信する。
+pub const MEDIA_HANDLER_NAME: &str = "media";
+
+const CONTENT_TYPE_EXTENSION: &str = "type";
+
+#[derive(Debug)]
+pub enum MediaCacheError {
+    InvalidPath,
+    HttpRequestError,
+    IoError,
+}
+
+impl std::fmt::Display for MediaCacheError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MediaCacheError::InvalidPath => {
+                write!(f, "invalid media path")
+            }
+            MediaCacheError::HttpRequestError => {
+                write!(f, "http request error")
+            }
+            MediaCacheError::IoError => {
+                write!(f, "io error")
+            }
+        }
+    }
+}
+
+impl Error for MediaCacheError {}
+
+impl From<std::io::Error> for MediaCacheError {
+    fn from(_value: std::io::Error) -> Self {
+        Self::IoError
+    }
+}
+
+impl From<reqwest::Error> for MediaCacheError {
+    fn from(_value: reqwest::Error) -> Self {
+        Self::HttpRequestError
+    }
+}
+
+#[derive(Debug)]
+pub struct MediaCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    state: Mutex<MediaCacheState>,
+}
+
+#[derive(Debug, Default)]
+struct MediaCacheState {
+    entries: HashMap<String, MediaCacheEntry>,
+    total_bytes: u64,
+    clock: u64,
+}
+
+#[derive(Debug)]
+struct MediaCacheEntry {
+    size: u64,
+    last_used: u64,
+
+    // キャッシュしたファイルと同じ名前に `.type` を付けたファイルにも書いておく。
+    content_type: Option<String>,
+}
+
+pub struct CachedMedia {
+    pub bytes: Vec<u8>,
+    pub content_type: Option<String>,
+}
+
+// リモートの URL を、キャッシュを経由するローカルの URL に置き換える。
+pub fn local_url(remote_url: &str) -> String {
+    format!("/{MEDIA_HANDLER_NAME}/{}", urlencoding::encode(remote_url))
+}
+
+fn remote_url(local_path: &str) -> Result<String, MediaCacheError> {
+    let encoded = local_path
+        .trim_start_matches('/')
+        .strip_prefix(MEDIA_HANDLER_NAME)
+        .and_then(|x| x.strip_prefix('/'))
+        .ok_or(MediaCacheError::InvalidPath)?;
+    let decoded = urlencoding::decode(encoded).map_err(|_| MediaCacheError::InvalidPath)?;
+    Ok(decoded.into_owned())
+}
+
+impl MediaCache {
+    pub fn from_settings(settings: &StorageSettings) -> Self {
+        Self::new(
+            settings.media_cache_dir.clone(),
+            settings.media_cache_max_bytes(),
+        )
+    }
+
+    pub fn new(dir: impl Into<PathBuf>, max_bytes: u64) -> Self {
+        let dir = dir.into();
+        let state = match scan_dir(&dir) {
+            Ok(state) => state,
+            Err(e) => {
+                warn!("failed to scan media cache: {e}");
+                Default::default()
+            }
+        };
+        info!(
+            "media cache: {} files, {} bytes",
+            state.entries.len(),
+            state.total_bytes
+        );
+
+        Self {
+            dir,
+            max_bytes,
+            state: Mutex::new(state),
+        }
+    }
+
+    // `local_url` で作ったパスへのリクエストに応える。
+    pub async fn serve(&self, local_path: &str) -> Result<CachedMedia, MediaCacheError> {
+        let url = remote_url(local_path)?;
+        let key = Uuid::new_v5(&Uuid::NAMESPACE_URL, url.as_bytes()).to_string();
+        let path = self.dir.join(&key);
+
+        if let Some(content_type) = self.touch(&key) {
+            match tokio::fs::read(&path).await {
+                Ok(bytes) => {
+                    return Ok(CachedMedia {
+                        bytes,
+                        content_type,
+                    })
+                }
+                Err(e) => {
+                    warn!("cached media is gone: {e}");
+                    self.forget(&key);
+                }
+            }
+        }
+
+        debug!("downloading {url}");
+        let res = reqwest::get(&url).await?.error_for_status()?;
+        let content_type = res
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|x| x.to_str().ok())
+            .map(|x| x.to_owned());
+        let bytes = res.bytes().await?.to_vec();
+
+        tokio::fs::create_dir_all(&self.dir).await?;
+        tokio::fs::write(&path, &bytes).await?;
+        let type_path = content_type_path(&path);
+        match &content_type {
+            Some(content_type) => tokio::fs::write(&type_path, content_type).await?,
+            None => remove_file(&type_path).await,
+        }
+
+        let evicted = self.insert(key, bytes.len() as u64, content_type.clone());
+        for key in evicted {
+            let path = self.dir.join(key);
+            remove_file(&path).await;
+            remove_file(&content_type_path(&path)).await;
+        }
+
+        Ok(CachedMedia {
+            bytes,
+            content_type,
+        })
+    }
+
+    // キャッシュにあれば、その Content-Type を返す。
+    fn touch(&self, key: &str) -> Option<Option<String>> {
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        let clock = state.clock;
+        let entry = state.entries.get_mut(key)?;
+        entry.last_used = clock;
+        Some(entry.content_type.clone())
+    }
+
+    fn forget(&self, key: &str) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(entry) = state.entries.remove(key) {
+            state.total_bytes -= entry.size;
+        }
+    }
+
+    // 追い出したもののキーを返す。ファイルはロックを手放してから消す。
+    fn insert(&self, key: String, size: u64, content_type: Option<String>) -> Vec<String> {
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        let entry = MediaCacheEntry {
+            size,
+            last_used: state.clock,
+            content_type,
+        };
+        if let Some(old) = state.entries.insert(key, entry) {
+            state.total_bytes -= old.size;
+        }
+        state.total_bytes += size;
+
+        // 最後に使われたのが古いものから消す。
+        let mut evicted = Vec::new();
+        while state.total_bytes > self.max_bytes && state.entries.len() > 1 {
+            let (oldest, _) = state
+                .entries
+                .iter()
+                .min_by_key(|(_, x)| x.last_used)
+                .unwrap();
+            let oldest = oldest.clone();
+            let entry = state.entries.remove(&oldest).unwrap();
+            state.total_bytes -= entry.size;
+            evicted.push(oldest);
+        }
+        evicted
+    }
+}
+
+fn content_type_path(path: &Path) -> PathBuf {
+    path.with_extension(CONTENT_TYPE_EXTENSION)
+}
+
+async fn remove_file(path: &Path) {
+    match tokio::fs::remove_file(path).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => warn!("failed to remove cached media: {e}"),
+    }
+}
+
+fn scan_dir(dir: &Path) -> Result<MediaCacheState, std::io::Error> {
+    let mut state = MediaCacheState::default();
+    if !dir.exists() {
+        return Ok(state);
+    }
+
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let path = entry.path();
+        if !metadata.is_file() || path.extension().is_some() {
+            continue;
+        }
+        let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+        if let Some(key) = entry.file_name().to_str() {
+            let content_type = std::fs::read_to_string(content_type_path(&path)).ok();
+            files.push((key.to_owned(), metadata.len(), modified, content_type));
+        }
+    }
+
+    for (key, size, _, content_type) in files.into_iter().sorted_by_key(|x| x.2) {
+        state.clock += 1;
+        state.total_bytes += size;
+        let entry = MediaCacheEntry {
+            size,
+            last_used: state.clock,
+            content_type,
+        };
+        state.entries.insert(key, entry);
+    }
+
+    Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::TestServer;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn cache_hit_keeps_content_type() {
+        let server = TestServer::start(|_path, _body| (200, "{}".to_owned()));
+        let dir = tempfile::tempdir().unwrap();
+        let url = format!("{}/files/a.png", server.base_url());
+
+        let cache = MediaCache::new(
+            dir.path(),
+            StorageSettings::default().media_cache_max_bytes(),
+        );
+        let downloaded = cache.serve(&local_url(&url)).await.unwrap();
+        let hit = cache.serve(&local_url(&url)).await.unwrap();
+        assert_eq!(server.requests(), 1);
+        assert_eq!(hit.bytes, downloaded.bytes);
+        assert_eq!(hit.content_type.as_deref(), Some("application/json"));
+
+        // 作り直しても、ディスクから読み直す。
+        let cache = MediaCache::new(
+            dir.path(),
+            StorageSettings::default().media_cache_max_bytes(),
+        );
+        let hit = cache.serve(&local_url(&url)).await.unwrap();
+        assert_eq!(server.requests(), 1);
+        assert_eq!(hit.content_type.as_deref(), Some("application/json"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn eviction_removes_content_type() {
+        let server = TestServer::start(|_path, _body| (200, "0123456789".to_owned()));
+        let dir = tempfile::tempdir().unwrap();
+        let cache = MediaCache::new(dir.path(), 15);
+
+        cache
+            .serve(&local_url(&format!("{}/a", server.base_url())))
+            .await
+            .unwrap();
+        cache
+            .serve(&local_url(&format!("{}/b", server.base_url())))
+            .await
+            .unwrap();
+
+        let files = std::fs::read_dir(dir.path()).unwrap().count();
+        assert_eq!(files, 2);
+    }
+}
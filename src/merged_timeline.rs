@@ -1,5 +1,5 @@
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
     error::Error,
     ops::DerefMut,
     sync::Arc,
@@ -11,7 +11,16 @@ use tokio::sync::{
     RwLock,
 };
 
-use crate::common_types::{DynNoteModel, MiMergeError};
+use chrono::{DateTime, Utc};
+use tracing::{debug, warn};
+
+use crate::{
+    clock::Clock,
+    common_types::{BranchKey, DynNoteModel, Host, MiMergeError, RetentionPolicy},
+};
+
+// ソースごとに指定が無ければ、これだけ前までに届いたノートとは作成日時順に並べる。
+pub const DEFAULT_REORDER_WINDOW: Duration = Duration::from_millis(500);
 
 #[derive(Debug)]
 pub enum MergedTimeLineError {
@@ -27,36 +36,130 @@ impl std::fmt::Display for MergedTimeLineError {
         }
     }
 }
-
 impl Error for MergedTimeLineError {}
 
+// 列の購読者に送る差分。インデックスは購読者ごとの絞り込み後の位置。
+#[derive(Debug, Clone)]
+pub enum ColumnDiff {
+    // 購読の開始時と、並べ直したときに送る全体。
+    Reset(Vec<DynNoteModel>),
+    Insert(usize, DynNoteModel),
+    Update(DynNoteModel),
+    Remove(String),
+}
+
 #[derive(Debug)]
 struct ColumnEntry {
+    uri: String,
     dyn_note_model: Arc<RwLock<DynNoteModel>>,
     inserted_at: Instant,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct MergedTimeline {
     column: VecDeque<ColumnEntry>,
     dictionary: HashMap<String, Arc<RwLock<DynNoteModel>>>,
-    column_senders: Vec<UnboundedSender<Vec<DynNoteModel>>>,
+    column_senders: Vec<ColumnSender>,
+    retention: RetentionPolicy,
+
+    // これより古いノートは、届いた順ではなく作成日時順に並べる。
+    history_until: Option<DateTime<Utc>>,
+
+    // 遅れて届きがちなサーバーほど、長めにとる。
+    reorder_windows: HashMap<Host, Duration>,
+    clock: Arc<dyn Clock>,
+
+    // 捨てたノートの URI を、各サーバーの `ServerNoteRepo` にも伝える。
+    eviction_senders: Vec<UnboundedSender<Vec<String>>>,
+
+    // 保存は `NoteStore` の書き込みスレッドに任せる。`None` なら保存しない。
+    store_writer: Option<UnboundedSender<DynNoteModel>>,
+    index_writer: Option<UnboundedSender<DynNoteModel>>,
+}
+
+#[derive(Debug)]
+struct ColumnSender {
+    // `None` ならすべてのノートを送る。
+    branches: Option<HashSet<BranchKey>>,
+    sender: UnboundedSender<ColumnDiff>,
+
+    // 購読者に送り済みのノートの URI。
+    visible: HashSet<String>,
+}
+
+impl ColumnSender {
+    fn accepts(&self, note: &DynNoteModel) -> bool {
+        match &self.branches {
+            Some(branches) => !branches.is_disjoint(&note.branches),
+            None => true,
+        }
+    }
+
+    // `column` の `n` 番目に置かれたノートの、この購読者から見た位置。
+    fn position(&self, column: &VecDeque<ColumnEntry>, n: usize) -> usize {
+        column
+            .range(..n)
+            .filter(|x| self.visible.contains(&x.uri))
+            .count()
+    }
+
+    fn send(&self, diff: ColumnDiff) {
+        // 受け手の列が閉じられていれば、次の `upsert` で取り除かれる。
+        let _ = self.sender.send(diff);
+    }
 }
 
 impl MergedTimeline {
-    pub fn new() -> Self {
-        Self::default()
+    pub fn new(retention: RetentionPolicy, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            column: VecDeque::new(),
+            dictionary: HashMap::new(),
+            column_senders: Vec::new(),
+            retention,
+            history_until: None,
+            reorder_windows: HashMap::new(),
+            clock,
+            eviction_senders: Vec::new(),
+            store_writer: None,
+            index_writer: None,
+        }
+    }
+
+    pub fn set_store_writer(&mut self, writer: UnboundedSender<DynNoteModel>) {
+        self.store_writer = Some(writer);
+    }
+
+    // ミュートで落としたノートは、ここまで届かないので検索にも出ない。
+    pub fn set_index_writer(&mut self, writer: UnboundedSender<DynNoteModel>) {
+        self.index_writer = Some(writer);
+    }
+
+    // 溢れたノートは、次に挿入するときに捨てる。
+    pub fn set_retention(&mut self, retention: RetentionPolicy) {
+        self.retention = retention;
+    }
+
+    pub fn set_reorder_window(&mut self, source_host: Host, window: Duration) {
+        self.reorder_windows.insert(source_host, window);
     }
 
     pub async fn upsert(&mut self, mut incoming: DynNoteModel) -> Result<(), MiMergeError> {
         use std::collections::hash_map::Entry::{Occupied, Vacant};
 
+        self.column_senders.retain(|x| !x.sender.is_closed());
+
         let uri = incoming.uri.clone();
 
-        match self.dictionary.entry(uri) {
+        match self.dictionary.entry(uri.clone()) {
             Occupied(current) => {
                 let mut current = current.get().write().await;
 
+                // チャンネルで付いたブランチは足し合わせる。
+                let mut branches: HashSet<_> = channel_branches(&current)
+                    .chain(channel_branches(&incoming))
+                    .cloned()
+                    .collect();
+
                 // 次の優先順位で `self.dictionary` に格納する。
                 // 1. ソースホストとオリジナルホストが同じもの。
                 // 2. 新しく来たもの。
@@ -66,25 +169,71 @@ impl MergedTimeline {
                     std::mem::swap(current.deref_mut(), &mut incoming);
                 }
 
-                current.branches.extend(incoming.branches.into_iter());
+                // ルールで付いたブランチは、残した方の内容から求めたものだけにする。
+                branches.extend(current.rule_branches.iter().cloned());
+                current.branches = branches;
+
+                let note = current.clone();
+                drop(current);
+                save(&self.store_writer, &note);
+                save(&self.index_writer, &note);
+
+                for sender in &mut self.column_senders {
+                    if sender.visible.contains(&uri) {
+                        if sender.accepts(&note) {
+                            sender.send(ColumnDiff::Update(note.clone()));
+                        } else {
+                            // ルールに合わなくなって、この購読者には見えなくなった。
+                            sender.visible.remove(&uri);
+                            sender.send(ColumnDiff::Remove(uri.clone()));
+                        }
+                    } else if sender.accepts(&note) {
+                        // ブランチが増えて、この購読者に見えるようになった。
+                        let Some(n) = self.column.iter().position(|x| x.uri == uri) else {
+                            continue;
+                        };
+                        let position = sender.position(&self.column, n);
+                        sender.visible.insert(uri.clone());
+                        sender.send(ColumnDiff::Insert(position, note.clone()));
+                    }
+                }
             }
             Vacant(entry) => {
-                let now = Instant::now();
+                let now = self.clock.now();
+                let window = self
+                    .reorder_windows
+                    .get(&incoming.source_host)
+                    .copied()
+                    .unwrap_or(DEFAULT_REORDER_WINDOW);
 
+                let note = incoming.clone();
+                save(&self.store_writer, &note);
+                save(&self.index_writer, &note);
+                let is_history = self
+                    .history_until
+                    .is_some_and(|t| note.mi_note.created_at <= t);
                 let incoming = Arc::new(RwLock::new(incoming));
                 entry.insert(incoming.clone());
-                insert_into_column(&mut self.column, incoming.clone(), now).await;
-            }
-        };
+                let n = if is_history {
+                    insert_into_history(&mut self.column, uri.clone(), incoming, now).await
+                } else {
+                    insert_into_column(&mut self.column, uri.clone(), incoming, now, window).await
+                };
 
-        let mut sending_item = Vec::new();
-        for x in &self.column {
-            sending_item.push(x.dyn_note_model.read().await.clone());
-        }
+                for sender in &mut self.column_senders {
+                    if sender.accepts(&note) {
+                        let position = sender.position(&self.column, n);
+                        sender.visible.insert(uri.clone());
+                        sender.send(ColumnDiff::Insert(position, note.clone()));
+                    }
+                }
 
-        for sender in &self.column_senders {
-            sender.send(sending_item.clone()).expect("mpsc error");
-        }
+                // 遡って読み込んだノートを、すぐに捨ててしまわないようにする。
+                if !is_history {
+                    self.evict().await;
+                }
+            }
+        };
 
         Ok(())
     }
@@ -101,40 +250,498 @@ impl MergedTimeline {
             let a = &keys[&Arc::as_ptr(&a.dyn_note_model)];
             let b = &keys[&Arc::as_ptr(&b.dyn_note_model)];
             a.cmp(b).reverse()
-        })
+        });
+
+        if self.column_senders.is_empty() {
+            return;
+        }
+
+        // 位置がすべて変わりうるので、差分ではなく全体を送り直す。
+        let snapshot = self.snapshot().await;
+        for sender in &mut self.column_senders {
+            let notes: Vec<_> = snapshot
+                .iter()
+                .filter(|x| sender.accepts(x))
+                .cloned()
+                .collect();
+            sender.visible = notes.iter().map(|x| x.uri.clone()).collect();
+            sender.send(ColumnDiff::Reset(notes));
+        }
     }
 
-    pub fn make_column_receiver(&mut self) -> UnboundedReceiver<Vec<DynNoteModel>> {
+    pub async fn make_column_receiver(
+        &mut self,
+        branches: Option<HashSet<BranchKey>>,
+    ) -> UnboundedReceiver<ColumnDiff> {
         let (tx, rx) = unbounded_channel();
-        self.column_senders.push(tx);
+        let mut sender = ColumnSender {
+            branches,
+            sender: tx,
+            visible: HashSet::new(),
+        };
+
+        let notes: Vec<_> = self
+            .snapshot()
+            .await
+            .into_iter()
+            .filter(|x| sender.accepts(x))
+            .collect();
+        sender.visible = notes.iter().map(|x| x.uri.clone()).collect();
+        sender.send(ColumnDiff::Reset(notes));
+
+        self.column_senders.push(sender);
         rx
     }
+
+    // 前回の起動時に保存したノートを、古い側に並べる。`notes` は新しい順。
+    pub fn restore(&mut self, notes: Vec<DynNoteModel>) {
+        let now = self.clock.now();
+        for note in notes {
+            if self.dictionary.contains_key(&note.uri) {
+                continue;
+            }
+            let uri = note.uri.clone();
+            let note = Arc::new(RwLock::new(note));
+            self.dictionary.insert(uri.clone(), note.clone());
+            self.column.push_back(ColumnEntry {
+                uri,
+                dyn_note_model: note,
+                inserted_at: now,
+            });
+        }
+    }
+
+    pub fn extend_history(&mut self, until: DateTime<Utc>) {
+        self.history_until = self.history_until.max(Some(until));
+    }
+
+    pub fn add_eviction_sender(&mut self, sender: UnboundedSender<Vec<String>>) {
+        self.eviction_senders.push(sender);
+    }
+
+    // 保持の方針から外れたノートを、古いものから捨てる。
+    // 遡って読み込んだノートは利用者が求めたものなので、捨てずに数からも外す。
+    // 捨てると、ページャーが次に読み込む位置との間に穴が開く。
+    async fn evict(&mut self) {
+        let now = self.clock.utc_now();
+        let mut evicted = Vec::new();
+        let mut history = 0;
+
+        let mut i = self.column.len();
+        while i > 0 {
+            i -= 1;
+            let created_at = self.column[i]
+                .dyn_note_model
+                .read()
+                .await
+                .mi_note
+                .created_at;
+            if self.history_until.is_some_and(|t| created_at <= t) {
+                history += 1;
+                continue;
+            }
+
+            let over_count = self
+                .retention
+                .max_notes
+                .is_some_and(|x| self.column.len() - history > x);
+            let over_age = match self.retention.max_age {
+                Some(max_age) => (now - created_at).to_std().is_ok_and(|x| x > max_age),
+                None => false,
+            };
+            if !over_count && !over_age {
+                break;
+            }
+
+            let entry = self.column.remove(i).unwrap();
+            self.dictionary.remove(&entry.uri);
+            evicted.push(entry.uri);
+        }
+
+        if evicted.is_empty() {
+            return;
+        }
+        debug!("evicted {} notes", evicted.len());
+
+        for sender in &mut self.column_senders {
+            for uri in &evicted {
+                if sender.visible.remove(uri) {
+                    sender.send(ColumnDiff::Remove(uri.clone()));
+                }
+            }
+        }
+
+        self.eviction_senders.retain(|x| !x.is_closed());
+        for sender in &self.eviction_senders {
+            let _ = sender.send(evicted.clone());
+        }
+    }
+
+    async fn snapshot(&self) -> Vec<DynNoteModel> {
+        let mut notes = Vec::new();
+        for x in &self.column {
+            notes.push(x.dyn_note_model.read().await.clone());
+        }
+        notes
+    }
+}
+
+fn save(writer: &Option<UnboundedSender<DynNoteModel>>, note: &DynNoteModel) {
+    let Some(writer) = writer else {
+        return;
+    };
+    if writer.send(note.clone()).is_err() {
+        warn!("a note writer has stopped");
+    }
 }
 
+fn channel_branches(note: &DynNoteModel) -> impl Iterator<Item = &BranchKey> {
+    note.branches.difference(&note.rule_branches)
+}
+
+// `window` の内に挿入された、より新しいノートすべての下に置く。挿入した位置を返す。
 async fn insert_into_column(
     column: &mut VecDeque<ColumnEntry>,
+    uri: String,
     incoming: Arc<RwLock<DynNoteModel>>,
     now: Instant,
-) {
-    let sort_limit_dur = Duration::from_millis(500);
-
-    let inserting_created_at = incoming.read().await.mi_note.created_at.clone();
+    window: Duration,
+) -> usize {
+    let inserting_created_at = incoming.read().await.mi_note.created_at;
 
+    // 窓の外に出たノートを挟んでいても、その下にある新しいノートより上には置かない。
     let mut n = 0;
-    for i in 0..column.len() {
-        let current = column[i].dyn_note_model.read().await;
-        if inserting_created_at >= current.mi_note.created_at
-            || now.duration_since(column[i].inserted_at) > sort_limit_dur
-        {
-            n = i;
+    for (i, entry) in column.iter().enumerate() {
+        if now.duration_since(entry.inserted_at) > window {
+            continue;
+        }
+        if entry.dyn_note_model.read().await.mi_note.created_at > inserting_created_at {
+            n = i + 1;
+        }
+    }
+    column.insert(
+        n,
+        ColumnEntry {
+            uri,
+            dyn_note_model: incoming,
+            inserted_at: now,
+        },
+    );
+    n
+}
+
+// 末尾から作成日時順の位置を探して挿入する。挿入した位置を返す。
+async fn insert_into_history(
+    column: &mut VecDeque<ColumnEntry>,
+    uri: String,
+    incoming: Arc<RwLock<DynNoteModel>>,
+    now: Instant,
+) -> usize {
+    let inserting_created_at = incoming.read().await.mi_note.created_at;
+
+    let mut n = column.len();
+    while n > 0 {
+        let current = column[n - 1].dyn_note_model.read().await;
+        if current.mi_note.created_at >= inserting_created_at {
             break;
         }
+        drop(current);
+        n -= 1;
     }
     column.insert(
         n,
         ColumnEntry {
+            uri,
             dyn_note_model: incoming,
             inserted_at: now,
         },
     );
+    n
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::{clock::FakeClock, mi_models::Note};
+
+    const CASES: u64 = 300;
+
+    // proptest の代わりに、種から決まる乱数で入力を作る。失敗したら種を表示する。
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: u64) -> u64 {
+            self.next() % n
+        }
+    }
+
+    fn base_time() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()
+    }
+
+    // `source_host` から見た、`origin.example` のノート。
+    fn note(id: u64, created_secs: i64, source_host: &str, branch: &str) -> DynNoteModel {
+        let mut mi_note: Note = serde_json::from_value(serde_json::json!({
+            "id": format!("{source_host}-{id}"),
+            "createdAt": base_time() + chrono::TimeDelta::seconds(created_secs),
+            "text": format!("note {id}"),
+            "userId": "u",
+            "user": { "id": "u", "username": "alice", "host": "origin.example" },
+        }))
+        .unwrap();
+        mi_note.uri = Some(note_uri(id));
+
+        let mut note = DynNoteModel::from_mi_model(mi_note, Host::from(source_host.to_owned()));
+        note.branches.insert(BranchKey(branch.to_owned()));
+        note
+    }
+
+    fn note_uri(id: u64) -> String {
+        format!("https://origin.example/notes/{id}")
+    }
+
+    // 窓の内に届いた、より新しいノートはすべて上にある。
+    async fn assert_below_recent_newer(
+        timeline: &MergedTimeline,
+        uri: &str,
+        now: Instant,
+        seed: u64,
+    ) {
+        let position = timeline.column.iter().position(|x| x.uri == uri).unwrap();
+        let created_at = timeline.column[position]
+            .dyn_note_model
+            .read()
+            .await
+            .mi_note
+            .created_at;
+        for below in timeline.column.iter().skip(position + 1) {
+            if now.duration_since(below.inserted_at) > DEFAULT_REORDER_WINDOW {
+                continue;
+            }
+            let below_at = below.dyn_note_model.read().await.mi_note.created_at;
+            assert!(
+                below_at <= created_at,
+                "seed {seed}: {uri} is above newer {}",
+                below.uri
+            );
+        }
+    }
+
+    fn unlimited() -> RetentionPolicy {
+        RetentionPolicy {
+            max_notes: None,
+            max_age: None,
+        }
+    }
+
+    // 購読者の側で差分を当てた結果。
+    fn apply_diffs(rx: &mut UnboundedReceiver<ColumnDiff>, view: &mut Vec<String>) {
+        while let Ok(diff) = rx.try_recv() {
+            match diff {
+                ColumnDiff::Reset(xs) => *view = xs.into_iter().map(|x| x.uri).collect(),
+                ColumnDiff::Insert(i, x) => {
+                    assert!(!view.contains(&x.uri), "inserted twice: {}", x.uri);
+                    view.insert(i, x.uri);
+                }
+                ColumnDiff::Update(x) => assert!(view.contains(&x.uri)),
+                ColumnDiff::Remove(uri) => view.retain(|x| x != &uri),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn arrival_order_keeps_every_note_once_and_in_order() {
+        for seed in 1..=CASES {
+            let mut rng = XorShift(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+            let clock = Arc::new(FakeClock::new(base_time()));
+            let mut timeline = MergedTimeline::new(unlimited(), clock.clone());
+            let mut all = timeline.make_column_receiver(None).await;
+            let branch_a = HashSet::from([BranchKey("a".to_owned())]);
+            let mut only_a = timeline.make_column_receiver(Some(branch_a.clone())).await;
+
+            // 同じノートが、別のサーバーやリアクションの更新で何度も届く。
+            let n = 1 + rng.below(30);
+            let mut expected = HashMap::new();
+            for _ in 0..n * 2 {
+                let id = rng.below(n);
+                let created_secs = rng.below(20) as i64;
+                let created_secs = *expected.entry(id).or_insert(created_secs);
+                let source_host = ["origin.example", "relay.example"][rng.below(2) as usize];
+                let branch = ["a", "b"][rng.below(2) as usize];
+                let is_new = !timeline.dictionary.contains_key(&note_uri(id));
+                timeline
+                    .upsert(note(id, created_secs, source_host, branch))
+                    .await
+                    .unwrap();
+                if is_new {
+                    assert_below_recent_newer(&timeline, &note_uri(id), clock.now(), seed).await;
+                }
+                clock.advance(Duration::from_millis(rng.below(2 * 500)));
+            }
+
+            // 失われず、重ならない。
+            let uris: Vec<_> = timeline.column.iter().map(|x| x.uri.clone()).collect();
+            let unique: HashSet<_> = uris.iter().collect();
+            assert_eq!(uris.len(), unique.len(), "seed {seed}: duplicated");
+            assert_eq!(uris.len(), expected.len(), "seed {seed}: lost");
+
+            // 購読者が差分から組み立てた列も、同じ並びになる。
+            let mut view = Vec::new();
+            apply_diffs(&mut all, &mut view);
+            assert_eq!(view, uris, "seed {seed}: diverged");
+
+            let mut view = Vec::new();
+            apply_diffs(&mut only_a, &mut view);
+            let mut filtered = Vec::new();
+            for x in &timeline.column {
+                if !x
+                    .dyn_note_model
+                    .read()
+                    .await
+                    .branches
+                    .is_disjoint(&branch_a)
+                {
+                    filtered.push(x.uri.clone());
+                }
+            }
+            assert_eq!(view, filtered, "seed {seed}: diverged filtered");
+        }
+    }
+
+    #[tokio::test]
+    async fn notes_within_the_window_are_sorted_by_creation() {
+        for seed in 1..=CASES {
+            let mut rng = XorShift(seed.wrapping_mul(0x2545_F491_4F6C_DD1D));
+            let clock = Arc::new(FakeClock::new(base_time()));
+            let mut timeline = MergedTimeline::new(unlimited(), clock.clone());
+
+            let n = 1 + rng.below(30);
+            for id in 0..n {
+                let created_secs = rng.below(1000) as i64;
+                timeline
+                    .upsert(note(id, created_secs, "origin.example", "a"))
+                    .await
+                    .unwrap();
+                clock.advance(Duration::from_millis(rng.below(500 / n)));
+            }
+
+            let mut created = Vec::new();
+            for x in &timeline.column {
+                created.push(x.dyn_note_model.read().await.mi_note.created_at);
+            }
+            assert!(
+                created.windows(2).all(|x| x[0] >= x[1]),
+                "seed {seed}: not sorted"
+            );
+        }
+    }
+
+    // Misskey から見たノートと、Mastodon から見た同じ投稿は、1つにまとめる。
+    #[tokio::test]
+    async fn misskey_and_mastodon_copies_merge() {
+        let clock = Arc::new(FakeClock::new(base_time()));
+        let mut timeline = MergedTimeline::new(unlimited(), clock.clone());
+        let mut rx = timeline.make_column_receiver(None).await;
+
+        // Misskey のローカルのノートには `uri` が無い。
+        let mi_note: Note = serde_json::from_value(serde_json::json!({
+            "id": "9abc",
+            "createdAt": base_time(),
+            "text": "hello",
+            "userId": "u",
+            "user": { "id": "u", "username": "alice", "host": null },
+        }))
+        .unwrap();
+        let mut from_misskey =
+            DynNoteModel::from_mi_model(mi_note, Host::from("misskey.example".to_owned()));
+        from_misskey
+            .branches
+            .insert(BranchKey("misskey".to_owned()));
+
+        let status: crate::mastodon_models::Status = serde_json::from_value(serde_json::json!({
+            "id": "112233",
+            "uri": "https://misskey.example/notes/9abc",
+            "created_at": base_time(),
+            "account": { "id": "a", "username": "alice", "acct": "alice@misskey.example" },
+            "content": "<p>hello</p>",
+            "visibility": "public",
+        }))
+        .unwrap();
+        let mut from_mastodon = DynNoteModel::from_mi_model(
+            status.into_mi_note(),
+            Host::from("mastodon.example".to_owned()),
+        );
+        from_mastodon
+            .branches
+            .insert(BranchKey("mastodon".to_owned()));
+
+        assert_eq!(from_misskey.uri, from_mastodon.uri);
+        assert_eq!(from_misskey.original_host, from_mastodon.original_host);
+
+        timeline.upsert(from_misskey).await.unwrap();
+        clock.advance(Duration::from_millis(100));
+        timeline.upsert(from_mastodon).await.unwrap();
+
+        assert_eq!(timeline.column.len(), 1);
+        assert_eq!(timeline.dictionary.len(), 1);
+        let merged = timeline.column[0].dyn_note_model.read().await;
+        assert_eq!(
+            merged.branches,
+            HashSet::from([
+                BranchKey("misskey".to_owned()),
+                BranchKey("mastodon".to_owned())
+            ])
+        );
+
+        let mut view = Vec::new();
+        apply_diffs(&mut rx, &mut view);
+        assert_eq!(view, vec!["https://misskey.example/notes/9abc".to_owned()]);
+    }
+
+    // 上限を超えても、遡って読み込んだノートは残る。
+    #[tokio::test]
+    async fn history_survives_eviction() {
+        let clock = Arc::new(FakeClock::new(base_time()));
+        let retention = RetentionPolicy {
+            max_notes: Some(3),
+            max_age: None,
+        };
+        let mut timeline = MergedTimeline::new(retention, clock.clone());
+
+        for id in 10..14 {
+            timeline
+                .upsert(note(id, id as i64, "origin.example", "a"))
+                .await
+                .unwrap();
+            clock.advance(Duration::from_secs(1));
+        }
+        assert_eq!(timeline.column.len(), 3);
+
+        // ページャーが、残っている最も古いノートより前を読み込む。
+        timeline.extend_history(base_time() + chrono::TimeDelta::seconds(2));
+        for id in 0..3 {
+            timeline
+                .upsert(note(id, id as i64, "origin.example", "a"))
+                .await
+                .unwrap();
+        }
+
+        timeline
+            .upsert(note(14, 14, "origin.example", "a"))
+            .await
+            .unwrap();
+
+        let uris: Vec<_> = timeline.column.iter().map(|x| x.uri.clone()).collect();
+        let expected: Vec<_> = [14, 13, 12, 2, 1, 0].map(note_uri).into();
+        assert_eq!(uris, expected);
+    }
 }
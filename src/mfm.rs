@@ -1,29 +1,67 @@
-use fancy_regex::Regex;
+use fancy_regex::{Captures, Regex};
+
+use crate::common_types::Host;
 
 pub enum DecomposedTextItem<'a> {
     Text(&'a str),
-    Emoji(&'a str), // "icon_syuilo", not ":icon_syuilo:"
+    Emoji(EmojiRef<'a>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EmojiRef<'a> {
+    pub name: &'a str,         // "icon_syuilo", not ":icon_syuilo:"
+    pub host: Option<&'a str>, // "misskey.io" or "." for ":icon_syuilo@misskey.io:" / ":icon_syuilo@.:"
 }
 
+impl<'a> EmojiRef<'a> {
+    // ホスト無しの参照は `implicit` に、`.` は `local` に解決する。
+    pub fn resolve_host(&self, implicit: &Host, local: &Host) -> Host {
+        match self.host {
+            None => implicit.clone(),
+            Some(".") => local.clone(),
+            Some(host) => Host::from(host.to_owned()),
+        }
+    }
+
+    pub fn qualified(&self, implicit: &Host, local: &Host) -> String {
+        format!(":{}@{}:", self.name, self.resolve_host(implicit, local))
+    }
+
+    fn from_captures(captures: &Captures<'a>) -> Self {
+        Self {
+            name: captures.get(1).unwrap().as_str(),
+            host: captures.get(2).map(|x| x.as_str()),
+        }
+    }
+}
+
+// ":name:", ":name@host:", ":name@.:"
+const EMOJI_PATTERN: &str = r":(\w+)(?:@(\.|[\w\-]+(?:\.[\w\-]+)*(?::\d+)?))?:";
+
 pub struct Decomposer {
     re: Regex,
+    re_whole: Regex,
 }
 
 impl Decomposer {
     pub fn new() -> Self {
         Self {
-            re: Regex::new(r":(\w+):(?=\W|$)").unwrap(),
+            re: Regex::new(&format!(r"{EMOJI_PATTERN}(?=\W|$)")).unwrap(),
+            re_whole: Regex::new(&format!(r"^{EMOJI_PATTERN}$")).unwrap(),
         }
     }
 
     pub fn decompose<'a>(&self, mut s: &'a str) -> Vec<DecomposedTextItem<'a>> {
         let mut result = Vec::new();
-        while let Ok(Some(m)) = self.re.find(s) {
+        while let Ok(Some(captures)) = self.re.captures(s) {
+            let m = captures.get(0).unwrap();
             if m.start() != 0 {
                 result.push(DecomposedTextItem::Text(&s[..m.start()]))
             }
 
-            result.push(DecomposedTextItem::Emoji(&s[m.start() + 1..m.end() - 1]));
+            result.push(DecomposedTextItem::Emoji(EmojiRef::from_captures(
+                &captures,
+            )));
 
             s = &s[m.end()..];
         }
@@ -34,4 +72,12 @@ impl Decomposer {
 
         result
     }
+
+    // リアクション名のように、文字列全体が1つの絵文字参照である場合にのみ分解する。
+    pub fn parse_emoji<'a>(&self, s: &'a str) -> Option<EmojiRef<'a>> {
+        match self.re_whole.captures(s) {
+            Ok(Some(captures)) => Some(EmojiRef::from_captures(&captures)),
+            _ => None,
+        }
+    }
 }
@@ -1,8 +1,12 @@
 use std::collections::HashMap;
 
 use chrono::prelude::*;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
+use crate::common_types::Host;
+
+// フォークや古いバージョンは、一部の項目を省いたり `null` にしたりするので、
+// 描画に欠かせない項目のほかは既定値で補う。知らない項目は `extra` に残す。
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct Note {
     pub id: String,
@@ -19,6 +23,7 @@ pub struct Note {
     pub user: User,
 
     #[serde(rename = "userId")]
+    #[serde(default, deserialize_with = "deserialize_null_default")]
     pub user_id: String,
 
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -33,11 +38,14 @@ pub struct Note {
     #[serde(rename = "renoteId")]
     pub renote_id: Option<String>,
 
+    #[serde(default, deserialize_with = "deserialize_null_default")]
     pub files: Vec<DriveFile>,
 
     #[serde(rename = "fileIds")]
+    #[serde(default, deserialize_with = "deserialize_null_default")]
     pub file_ids: Vec<String>,
 
+    #[serde(default, deserialize_with = "deserialize_null_default")]
     pub visibility: Visibility,
 
     #[serde(rename = "visibleUserIds")]
@@ -51,19 +59,29 @@ pub struct Note {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub my_reaction: Option<String>,
 
+    #[serde(default, deserialize_with = "deserialize_null_default")]
     pub reactions: HashMap<String, i64>,
 
     #[serde(rename = "renoteCount")]
+    #[serde(default, deserialize_with = "deserialize_null_default")]
     pub renote_count: i64,
 
     #[serde(rename = "repliesCount")]
+    #[serde(default, deserialize_with = "deserialize_null_default")]
     pub replies_count: i64,
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub poll: Option<Poll>,
 
-    // #[serde(skip_serializing_if = "Option::is_none")]
-    // pub emojis: Option<Vec<Emoji>>,
+    // リモートのノートにのみ含まれる。キーは絵文字名。
+    #[serde(default, deserialize_with = "deserialize_emoji_map")]
+    pub emojis: HashMap<String, String>,
+
+    // キーは "name@host" 。ローカルの絵文字は含まれない。
+    #[serde(rename = "reactionEmojis")]
+    #[serde(default, deserialize_with = "deserialize_emoji_map")]
+    pub reaction_emojis: HashMap<String, String>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     pub uri: Option<String>,
 
@@ -76,6 +94,18 @@ pub struct Note {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub mentions: Option<Vec<String>>,
+
+    // 先頭の `#` を除いたハッシュタグ。
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+
+    // 制限が無ければ `null` 。v13.10 より前の Misskey と、対応していないフォークには無い。
+    #[serde(rename = "reactionAcceptance")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reaction_acceptance: Option<ReactionAcceptance>,
+
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
@@ -89,19 +119,61 @@ pub struct User {
     pub name: Option<String>,
 
     #[serde(rename = "onlineStatus")]
+    #[serde(default, deserialize_with = "deserialize_null_default")]
     pub online_status: OnlineStatus,
 
     #[serde(rename = "avatarUrl")]
+    #[serde(default, deserialize_with = "deserialize_null_default")]
     pub avatar_url: String,
 
     #[serde(rename = "avatarBlurhash")]
     pub avatar_blurhash: Option<String>,
 
-    // emojis: Vec<Emoji>,
+    #[serde(default, deserialize_with = "deserialize_emoji_map")]
+    pub emojis: HashMap<String, String>,
+
     pub instance: Option<UserInstance>,
+
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl User {
+    // "username@host" 。ホストが無ければノートを受け取ったサーバーのユーザー。
+    pub fn acct(&self, source_host: &Host) -> String {
+        let host = self.host.clone().unwrap_or(source_host.to_string());
+        format!("{}@{}", self.username, host).to_lowercase()
+    }
 }
 
+// `/api/i` の応答のうち、使うものだけ。
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MeDetailed {
+    pub id: String,
+
+    pub username: String,
+
+    #[serde(rename = "alwaysMarkNsfw")]
+    #[serde(default)]
+    pub always_mark_nsfw: bool,
+
+    // v13 から。ロールで決まる、そのアカウントに許された機能。
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    pub policies: RolePolicies,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct RolePolicies {
+    #[serde(rename = "ltlAvailable")]
+    #[serde(default)]
+    pub ltl_available: Option<bool>,
+
+    #[serde(rename = "gtlAvailable")]
+    #[serde(default)]
+    pub gtl_available: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
 pub enum OnlineStatus {
     #[serde(rename = "online")]
     Online,
@@ -112,7 +184,8 @@ pub enum OnlineStatus {
     #[serde(rename = "offline")]
     Offline,
 
-    #[serde(rename = "unknown")]
+    #[default]
+    #[serde(rename = "unknown", other)]
     Unknown,
 }
 
@@ -141,11 +214,14 @@ pub struct DriveFile {
     pub id: String,
 
     #[serde(rename = "createdAt")]
+    #[serde(default, deserialize_with = "deserialize_null_default")]
     pub created_at: String,
 
     #[serde(rename = "isSensitive")]
+    #[serde(default, deserialize_with = "deserialize_null_default")]
     pub is_sensitive: bool,
 
+    #[serde(default, deserialize_with = "deserialize_null_default")]
     pub name: String,
 
     #[serde(rename = "thumbnailUrl")]
@@ -154,20 +230,37 @@ pub struct DriveFile {
     pub url: String,
 
     #[serde(rename = "type")]
+    #[serde(default, deserialize_with = "deserialize_null_default")]
     pub type_: String,
 
+    #[serde(default, deserialize_with = "deserialize_null_default")]
     pub size: i64,
 
+    #[serde(default, deserialize_with = "deserialize_null_default")]
     pub md5: String,
 
     #[serde(rename = "blurhash")]
     pub blur_hash: Option<String>,
 
     pub comment: Option<String>,
-    // TODO: properties: Record<string, any>,
+
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    pub properties: DriveFileProperties,
+
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct DriveFileProperties {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<i64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
 pub enum Visibility {
     #[serde(rename = "public")]
     Public,
@@ -180,6 +273,54 @@ pub enum Visibility {
 
     #[serde(rename = "specified")]
     Specified,
+
+    // フォーク独自の公開範囲。
+    #[default]
+    #[serde(rename = "unknown", other)]
+    Unknown,
+}
+
+// ノートの作者が決める、受け付けるリアクション。
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReactionAcceptance {
+    #[serde(rename = "likeOnly")]
+    LikeOnly,
+
+    #[serde(rename = "likeOnlyForRemote")]
+    LikeOnlyForRemote,
+
+    #[serde(rename = "nonSensitiveOnly")]
+    NonSensitiveOnly,
+
+    #[serde(rename = "nonSensitiveOnlyForLocalLikeOnlyForRemote")]
+    NonSensitiveOnlyForLocalLikeOnlyForRemote,
+
+    // 知らない値は制限しないものとして扱う。
+    #[serde(rename = "unknown", other)]
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReactionLimit {
+    LikeOnly,
+    NonSensitiveOnly,
+}
+
+impl ReactionAcceptance {
+    // `remote` は、リアクションするアカウントがノートの作者と別のサーバーにいるか。
+    pub fn limit(self, remote: bool) -> Option<ReactionLimit> {
+        match self {
+            Self::LikeOnly => Some(ReactionLimit::LikeOnly),
+            Self::LikeOnlyForRemote => remote.then_some(ReactionLimit::LikeOnly),
+            Self::NonSensitiveOnly => Some(ReactionLimit::NonSensitiveOnly),
+            Self::NonSensitiveOnlyForLocalLikeOnlyForRemote => Some(if remote {
+                ReactionLimit::LikeOnly
+            } else {
+                ReactionLimit::NonSensitiveOnly
+            }),
+            Self::Unknown => None,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
@@ -187,25 +328,70 @@ pub struct Poll {
     #[serde(rename = "expiresAt")]
     pub expires_at: Option<String>,
 
+    #[serde(default, deserialize_with = "deserialize_null_default")]
     pub multiple: bool,
 
+    #[serde(default, deserialize_with = "deserialize_null_default")]
     pub choices: Vec<PollChoice>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct PollChoice {
     #[serde(rename = "isVoted")]
+    #[serde(default, deserialize_with = "deserialize_null_default")]
     pub is_voted: bool,
 
     pub text: String,
 
+    #[serde(default, deserialize_with = "deserialize_null_default")]
     pub votes: i64,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
 pub struct EmojiSimple {
     pub name: String,
     pub url: String,
+
+    // `/api/emojis` でのみ送られてくる。
+    #[serde(default)]
+    pub aliases: Vec<String>,
+
+    #[serde(default)]
+    pub category: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct EmojisResponse {
+    pub emojis: Vec<EmojiSimple>,
+}
+
+// v12 以前は `[{ name, url }]` 、v13 以降は `{ name: url }` の形で送られてくる。
+fn deserialize_emoji_map<'de, D>(deserializer: D) -> Result<HashMap<String, String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum EmojiMap {
+        Map(HashMap<String, String>),
+        List(Vec<EmojiSimple>),
+        Null(()),
+    }
+
+    Ok(match EmojiMap::deserialize(deserializer)? {
+        EmojiMap::Map(x) => x,
+        EmojiMap::List(xs) => xs.into_iter().map(|x| (x.name, x.url)).collect(),
+        EmojiMap::Null(()) => HashMap::new(),
+    })
+}
+
+// 値が `null` のときも既定値にする。
+pub(crate) fn deserialize_null_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
@@ -269,5 +455,166 @@ pub struct NoteUpdatedBodyReactedBody {
     pub reaction: String,
 
     #[serde(rename = "userId")]
+    #[serde(default, deserialize_with = "deserialize_null_default")]
     pub user_id: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 各サーバーの API の応答と同じ形のもの。まだ実際の応答ではない。`fixtures/README.md` を参照。
+    const SOFTWARES: &[(&str, &str, &str, &str)] = &[
+        (
+            "sharkey",
+            include_str!("../fixtures/sharkey/note.json"),
+            include_str!("../fixtures/sharkey/user.json"),
+            include_str!("../fixtures/sharkey/drive_file.json"),
+        ),
+        (
+            "firefish",
+            include_str!("../fixtures/firefish/note.json"),
+            include_str!("../fixtures/firefish/user.json"),
+            include_str!("../fixtures/firefish/drive_file.json"),
+        ),
+        (
+            "iceshrimp",
+            include_str!("../fixtures/iceshrimp/note.json"),
+            include_str!("../fixtures/iceshrimp/user.json"),
+            include_str!("../fixtures/iceshrimp/drive_file.json"),
+        ),
+        (
+            "misskey-v12",
+            include_str!("../fixtures/misskey-v12/note.json"),
+            include_str!("../fixtures/misskey-v12/user.json"),
+            include_str!("../fixtures/misskey-v12/drive_file.json"),
+        ),
+    ];
+
+    fn fixture<T: for<'de> Deserialize<'de>>(software: &str, json: &str) -> T {
+        serde_json::from_str(json).unwrap_or_else(|e| panic!("{software}: {e}"))
+    }
+
+    #[test]
+    fn deserializes_every_software() {
+        for (software, note, user, drive_file) in SOFTWARES {
+            let note: Note = fixture(software, note);
+            let user: User = fixture(software, user);
+            let drive_file: DriveFile = fixture(software, drive_file);
+
+            assert_eq!(note.user, user, "{software}");
+            assert!(!drive_file.url.is_empty(), "{software}");
+        }
+    }
+
+    #[test]
+    fn sharkey() {
+        let (software, note, _, _) = SOFTWARES[0];
+        let note: Note = fixture(software, note);
+        assert_eq!(note.visibility, Visibility::Public);
+        assert_eq!(note.user.online_status, OnlineStatus::Online);
+        assert_eq!(note.files[0].properties.width, Some(1024));
+        assert_eq!(note.emojis.len(), 1);
+
+        assert_eq!(
+            note.reaction_acceptance,
+            Some(ReactionAcceptance::LikeOnlyForRemote)
+        );
+
+        // 編集の履歴などの独自の項目は、捨てずに残す。
+        for key in ["updatedAt", "noteEditHistory"] {
+            assert!(note.extra.contains_key(key), "{key}");
+        }
+        assert_eq!(note.user.extra["isCat"], true);
+        assert!(note.files[0].extra.contains_key("folderId"));
+    }
+
+    #[test]
+    fn firefish() {
+        let (software, note, _, _) = SOFTWARES[1];
+        let note: Note = fixture(software, note);
+        let renote = note.renote.as_ref().unwrap();
+        assert_eq!(renote.visibility, Visibility::Home);
+        assert!(renote.files[0].is_sensitive);
+        assert_eq!(renote.files[0].properties, DriveFileProperties::default());
+
+        // 絵文字は v12 と同じ配列で送られてくる。
+        assert_eq!(
+            note.user.emojis["ff_heart"],
+            "https://firefish.example/emoji/ff_heart.png"
+        );
+        assert_eq!(note.user.online_status, OnlineStatus::Unknown);
+        assert!(note.user.extra.contains_key("isIndexable"));
+        assert!(renote.extra.contains_key("lang"));
+    }
+
+    #[test]
+    fn iceshrimp() {
+        let (software, note, _, _) = SOFTWARES[2];
+        let note: Note = fixture(software, note);
+        assert_eq!(note.visibility, Visibility::Followers);
+        assert_eq!(note.user.online_status, OnlineStatus::Active);
+        assert_eq!(note.reply.as_ref().unwrap().replies_count, 1);
+
+        let poll = note.poll.as_ref().unwrap();
+        assert_eq!(poll.choices.len(), 2);
+        assert!(poll.choices[0].is_voted);
+        assert_eq!(note.files[0].properties.height, Some(4032));
+        assert!(note.files[0].extra.contains_key("folderId"));
+        assert!(note.user.extra.contains_key("movedToUri"));
+    }
+
+    #[test]
+    fn misskey_v12() {
+        let (software, note, _, _) = SOFTWARES[3];
+        let note: Note = fixture(software, note);
+
+        // `null` の項目は既定値で補う。
+        assert_eq!(note.user.online_status, OnlineStatus::Unknown);
+        assert_eq!(note.user.avatar_url, "");
+        assert_eq!(note.renote_count, 0);
+        assert!(note.reactions.is_empty());
+        assert!(note.file_ids.is_empty());
+        assert!(!note.files[0].is_sensitive);
+        assert_eq!(note.emojis["party"], "https://old.example/files/party.gif");
+        assert!(note.user.extra.contains_key("avatarColor"));
+    }
+
+    // フォーク独自の値は、読めないところだけを `Unknown` にする。
+    #[test]
+    fn unknown_values_fall_back() {
+        for (software, note, _, _) in SOFTWARES {
+            let mut json: serde_json::Value = serde_json::from_str(note).unwrap();
+            json["visibility"] = "private".into();
+            json["user"]["onlineStatus"] = "busy".into();
+            json["reactionAcceptance"] = "emojiOnly".into();
+
+            let note: Note = fixture(software, &json.to_string());
+            assert_eq!(note.visibility, Visibility::Unknown, "{software}");
+            assert_eq!(note.user.online_status, OnlineStatus::Unknown, "{software}");
+            assert_eq!(
+                note.reaction_acceptance,
+                Some(ReactionAcceptance::Unknown),
+                "{software}"
+            );
+        }
+    }
+
+    #[test]
+    fn reaction_limit_depends_on_the_reactor() {
+        use ReactionAcceptance::*;
+
+        assert_eq!(LikeOnly.limit(false), Some(ReactionLimit::LikeOnly));
+        assert_eq!(LikeOnlyForRemote.limit(false), None);
+        assert_eq!(LikeOnlyForRemote.limit(true), Some(ReactionLimit::LikeOnly));
+        assert_eq!(
+            NonSensitiveOnlyForLocalLikeOnlyForRemote.limit(false),
+            Some(ReactionLimit::NonSensitiveOnly)
+        );
+        assert_eq!(
+            NonSensitiveOnlyForLocalLikeOnlyForRemote.limit(true),
+            Some(ReactionLimit::LikeOnly)
+        );
+        assert_eq!(Unknown.limit(true), None);
+    }
+}
@@ -0,0 +1,198 @@
+use std::{error::Error, time::Duration};
+
+use serde::Deserialize;
+use tokio::time::Instant;
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::common_types::Host;
+
+pub const MIAUTH_APP_NAME: &str = "mi-merge";
+
+// タイムラインとアカウントの情報を読むだけなので、読み取りの権限だけを求める。
+const MIAUTH_PERMISSIONS: &[&str] = &["read:account"];
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+// ブラウザで許可されるのを待つ時間。
+pub const LOGIN_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug)]
+pub enum MiAuthError {
+    HttpRequestError(String),
+    Timeout,
+}
+
+impl std::fmt::Display for MiAuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MiAuthError::HttpRequestError(e) => {
+                write!(f, "http request error: {e}")
+            }
+            MiAuthError::Timeout => {
+                write!(f, "the login was not permitted in time")
+            }
+        }
+    }
+}
+
+impl Error for MiAuthError {}
+
+impl From<reqwest::Error> for MiAuthError {
+    fn from(value: reqwest::Error) -> Self {
+        Self::HttpRequestError(value.to_string())
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct MiAuthToken {
+    pub token: String,
+    pub username: String,
+}
+
+impl std::fmt::Debug for MiAuthToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("MiAuthToken")
+            .field("token", &"<redacted>")
+            .field("username", &self.username)
+            .finish()
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct CheckResponse {
+    ok: bool,
+    token: Option<String>,
+    user: Option<CheckUser>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CheckUser {
+    username: String,
+}
+
+// Misskey の MiAuth 。ブラウザで `auth_url` を開いて許可してもらい、トークンを受け取る。
+#[derive(Debug, Clone)]
+pub struct MiAuthSession {
+    host: Host,
+    base_url: String,
+    session: String,
+}
+
+impl MiAuthSession {
+    pub fn new(host: Host) -> Self {
+        let base_url = format!("https://{host}");
+        Self::with_base_url(host, base_url)
+    }
+
+    // ローカルの代役のサーバーで試せるように、URL の前半を差し替えられる。
+    pub fn with_base_url(host: Host, base_url: impl Into<String>) -> Self {
+        Self {
+            host,
+            base_url: base_url.into(),
+            session: Uuid::new_v4().to_string(),
+        }
+    }
+
+    pub fn auth_url(&self) -> String {
+        format!(
+            "{}/miauth/{}?name={}&permission={}",
+            self.base_url,
+            self.session,
+            urlencoding::encode(MIAUTH_APP_NAME),
+            urlencoding::encode(&MIAUTH_PERMISSIONS.join(","))
+        )
+    }
+
+    // まだ許可されていなければ `None` 。
+    pub async fn check(&self) -> Result<Option<MiAuthToken>, MiAuthError> {
+        let client = reqwest::Client::new();
+        let res: CheckResponse = client
+            .post(format!(
+                "{}/api/miauth/{}/check",
+                self.base_url, self.session
+            ))
+            .json(&serde_json::json!({}))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        debug!("miauth check of {}: ok={}", self.host, res.ok);
+
+        match res {
+            CheckResponse {
+                ok: true,
+                token: Some(token),
+                user: Some(user),
+            } => Ok(Some(MiAuthToken {
+                token,
+                username: user.username,
+            })),
+            _ => Ok(None),
+        }
+    }
+
+    // 許可されるまで、繰り返し確かめる。
+    pub async fn wait(&self, timeout: Duration) -> Result<MiAuthToken, MiAuthError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(token) = self.check().await? {
+                return Ok(token);
+            }
+            if Instant::now() + CHECK_INTERVAL > deadline {
+                return Err(MiAuthError::Timeout);
+            }
+            tokio::time::sleep(CHECK_INTERVAL).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::test_server::TestServer;
+
+    fn session(server: &TestServer) -> MiAuthSession {
+        MiAuthSession::with_base_url(Host::from("misskey.test".to_owned()), server.base_url())
+    }
+
+    // 時間を止めておくと、確かめる間隔を待たずに進む。
+    #[tokio::test(start_paused = true)]
+    async fn waits_until_permitted() {
+        let checks = AtomicUsize::new(0);
+        let server = TestServer::start(move |path, _| {
+            assert!(path.starts_with("/api/miauth/") && path.ends_with("/check"));
+            if checks.fetch_add(1, Ordering::SeqCst) < 2 {
+                (200, r#"{"ok":false}"#.to_owned())
+            } else {
+                (
+                    200,
+                    r#"{"ok":true,"token":"secret","user":{"id":"u","username":"alice"}}"#
+                        .to_owned(),
+                )
+            }
+        });
+
+        let token = session(&server).wait(LOGIN_TIMEOUT).await.unwrap();
+        assert_eq!(
+            token,
+            MiAuthToken {
+                token: "secret".to_owned(),
+                username: "alice".to_owned(),
+            }
+        );
+        assert_eq!(server.requests(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn times_out_while_pending() {
+        let server = TestServer::start(|_, _| (200, r#"{"ok":false}"#.to_owned()));
+
+        let result = session(&server).wait(CHECK_INTERVAL * 5).await;
+        assert!(matches!(result, Err(MiAuthError::Timeout)), "{result:?}");
+        assert_eq!(server.requests(), 6);
+    }
+}
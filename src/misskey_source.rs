@@ -0,0 +1,166 @@
+use async_trait::async_trait;
+use serde_json::json;
+
+use crate::{
+    common_types::{ChannelChannel, Host, MiMergeError},
+    mi_models::{MeDetailed, Note},
+    server_capabilities::{self, ServerCapabilities},
+    server_cxn::ServerCxn,
+    server_source::{ServerSource, ServerStream},
+};
+
+pub struct MisskeySource {
+    host: Host,
+    api_key: String,
+}
+
+impl std::fmt::Debug for MisskeySource {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("MisskeySource")
+            .field("host", &self.host)
+            .field("api_key", &"<redacted>")
+            .finish()
+    }
+}
+
+impl MisskeySource {
+    pub fn new(host: Host, api_key: String) -> Self {
+        Self { host, api_key }
+    }
+}
+
+#[async_trait]
+impl ServerSource for MisskeySource {
+    fn host(&self) -> &Host {
+        &self.host
+    }
+
+    async fn probe(&self) -> ServerCapabilities {
+        server_capabilities::probe_misskey(&self.host).await
+    }
+
+    async fn fetch_me(&self) -> Result<MeDetailed, MiMergeError> {
+        fetch_me(&self.host, &self.api_key).await
+    }
+
+    async fn fetch_page(
+        &self,
+        channel: &ChannelChannel,
+        until_id: Option<&str>,
+    ) -> Result<Vec<Note>, MiMergeError> {
+        let (host, api_key) = (&self.host, self.api_key.as_str());
+        match channel {
+            ChannelChannel::HomeTimeline => fetch_home_notes(host, api_key, until_id).await,
+            ChannelChannel::LocalTimeline => fetch_local_notes(host, api_key, until_id).await,
+            ChannelChannel::Channel { channel_id } => {
+                fetch_channel_notes(host, api_key, channel_id, until_id).await
+            }
+            ChannelChannel::Hashtag { tag } => {
+                fetch_hashtag_notes(host, api_key, tag, until_id).await
+            }
+        }
+    }
+
+    fn stream(&self) -> Box<dyn ServerStream> {
+        Box::new(ServerCxn::new(self.host.clone(), self.api_key.clone()))
+    }
+}
+
+async fn fetch_me(host: &Host, api_key: &str) -> Result<MeDetailed, MiMergeError> {
+    let client = reqwest::Client::new();
+    let res = client
+        .post(format!("https://{}/api/i", host.to_string()))
+        .json(&json!({ "i": api_key }))
+        .send()
+        .await?
+        .error_for_status()?;
+
+    let res = res.json().await?;
+    Ok(res)
+}
+
+async fn fetch_home_notes(
+    host: &Host,
+    api_key: &str,
+    until_id: Option<&str>,
+) -> Result<Vec<Note>, MiMergeError> {
+    let client = reqwest::Client::new();
+    let res = client
+        .post(format!("https://{}/api/notes/timeline", host.to_string()))
+        .json(&page_params(json!({ "i": api_key }), until_id))
+        .send()
+        .await?
+        .error_for_status()?;
+
+    let res = res.json().await?;
+    Ok(res)
+}
+
+async fn fetch_local_notes(
+    host: &Host,
+    api_key: &str,
+    until_id: Option<&str>,
+) -> Result<Vec<Note>, MiMergeError> {
+    let client = reqwest::Client::new();
+    let res = client
+        .post(format!(
+            "https://{}/api/notes/local-timeline",
+            host.to_string()
+        ))
+        .json(&page_params(json!({ "i": api_key }), until_id))
+        .send()
+        .await?
+        .error_for_status()?;
+
+    let res = res.json().await?;
+    Ok(res)
+}
+
+async fn fetch_channel_notes(
+    host: &Host,
+    api_key: &str,
+    channel_id: &str,
+    until_id: Option<&str>,
+) -> Result<Vec<Note>, MiMergeError> {
+    let client = reqwest::Client::new();
+    let res = client
+        .post(format!(
+            "https://{}/api/channels/timeline",
+            host.to_string()
+        ))
+        .json(&page_params(
+            json!({ "channelId": channel_id, "i": api_key }),
+            until_id,
+        ))
+        .send()
+        .await?
+        .error_for_status()?;
+
+    let res = res.json().await?;
+    Ok(res)
+}
+
+async fn fetch_hashtag_notes(
+    host: &Host,
+    api_key: &str,
+    tag: &str,
+    until_id: Option<&str>,
+) -> Result<Vec<Note>, MiMergeError> {
+    let client = reqwest::Client::new();
+    let res = client
+        .post(format!("https://{host}/api/notes/search-by-tag"))
+        .json(&page_params(json!({ "tag": tag, "i": api_key }), until_id))
+        .send()
+        .await?
+        .error_for_status()?;
+
+    let res = res.json().await?;
+    Ok(res)
+}
+
+fn page_params(mut params: serde_json::Value, until_id: Option<&str>) -> serde_json::Value {
+    if let Some(until_id) = until_id {
+        params["untilId"] = json!(until_id);
+    }
+    params
+}
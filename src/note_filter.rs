@@ -0,0 +1,194 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
+
+use fancy_regex::Regex;
+use tracing::debug;
+
+use crate::{
+    common_types::{DynNoteModel, Host, MuteSettings},
+    mi_models::{Note, User},
+};
+
+// `ServerNoteRepo` から `MergedTimeline` に渡す前に、ミュート対象のノートを落とす。
+#[derive(Debug, Default)]
+pub struct NoteFilter {
+    words: Vec<String>,
+    regexes: Vec<Regex>,
+    users: AcctSet,
+    instances: HashSet<String>,
+    hide_renotes_from: AcctSet,
+
+    hidden: Mutex<HiddenStats>,
+}
+
+// リアクションの更新などで同じノートが何度届いても、1回と数える。
+#[derive(Debug, Default)]
+struct HiddenStats {
+    counts: HashMap<String, usize>,
+    uris: HashSet<String>,
+}
+
+#[derive(Debug, Default)]
+struct AcctSet {
+    accts: HashSet<String>,     // "username@host"
+    usernames: HashSet<String>, // ホストが省略されたもの
+}
+
+impl NoteFilter {
+    pub fn new(settings: MuteSettings) -> Result<Self, fancy_regex::Error> {
+        let regexes = settings
+            .regexes
+            .iter()
+            .map(|x| Regex::new(x))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            words: settings.words.iter().map(|x| x.to_lowercase()).collect(),
+            regexes,
+            users: AcctSet::new(&settings.users),
+            instances: settings
+                .instances
+                .iter()
+                .map(|x| x.to_lowercase())
+                .collect(),
+            hide_renotes_from: AcctSet::new(&settings.hide_renotes_from),
+            hidden: Default::default(),
+        })
+    }
+
+    // 隠したものは理由ごとに数えておく。
+    pub fn is_hidden(&self, note: &DynNoteModel) -> bool {
+        let Some(reason) = self.judge(note) else {
+            return false;
+        };
+
+        let mut hidden = self.hidden.lock().unwrap();
+        if !hidden.uris.insert(note.uri.clone()) {
+            return true;
+        }
+        let count = hidden.counts.entry(reason.clone()).or_default();
+        *count += 1;
+        debug!("hid {} ({reason}, {count} times)", note.uri);
+        true
+    }
+
+    // 数えずに判定だけする。検索結果のように、同じノートを何度も見るときに使う。
+    pub fn hides(&self, note: &DynNoteModel) -> bool {
+        self.judge(note).is_some()
+    }
+
+    pub fn hidden_counts(&self) -> HashMap<String, usize> {
+        self.hidden.lock().unwrap().counts.clone()
+    }
+
+    fn judge(&self, note: &DynNoteModel) -> Option<String> {
+        let mi_note = &note.mi_note;
+        let source_host = &note.source_host;
+
+        let is_pure_renote = mi_note.renote.is_some() && mi_note.text.is_none();
+        if is_pure_renote && self.hide_renotes_from.contains(&mi_note.user, source_host) {
+            return Some(format!("renote by {}", mi_note.user.acct(source_host)));
+        }
+
+        let notes = std::iter::once(mi_note).chain(mi_note.renote.as_deref());
+        for x in notes {
+            if let Some(reason) = self.judge_content(x, source_host) {
+                return Some(reason);
+            }
+        }
+
+        None
+    }
+
+    fn judge_content(&self, note: &Note, source_host: &Host) -> Option<String> {
+        if self.users.contains(&note.user, source_host) {
+            return Some(format!("user {}", note.user.acct(source_host)));
+        }
+
+        let host = user_host(&note.user, source_host);
+        if self.instances.contains(&host) {
+            return Some(format!("instance {host}"));
+        }
+
+        let texts = [note.text.as_deref(), note.cw.as_deref()];
+        for text in texts.into_iter().flatten() {
+            let lower = text.to_lowercase();
+            if let Some(word) = self.words.iter().find(|x| lower.contains(x.as_str())) {
+                return Some(format!("word {word}"));
+            }
+            if let Some(re) = self
+                .regexes
+                .iter()
+                .find(|x| x.is_match(text).unwrap_or(false))
+            {
+                return Some(format!("regex {}", re.as_str()));
+            }
+        }
+
+        None
+    }
+}
+
+impl AcctSet {
+    fn new(xs: &[String]) -> Self {
+        let mut set = Self::default();
+        for x in xs {
+            let x = x.trim_start_matches('@').to_lowercase();
+            if x.contains('@') {
+                set.accts.insert(x);
+            } else {
+                set.usernames.insert(x);
+            }
+        }
+        set
+    }
+
+    fn contains(&self, user: &User, source_host: &Host) -> bool {
+        self.usernames.contains(&user.username.to_lowercase())
+            || self.accts.contains(&user.acct(source_host))
+    }
+}
+
+fn user_host(user: &User, source_host: &Host) -> String {
+    user.host
+        .clone()
+        .unwrap_or(source_host.to_string())
+        .to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(id: &str, text: &str) -> DynNoteModel {
+        let mi_note = serde_json::from_value(serde_json::json!({
+            "id": id,
+            "createdAt": "2024-01-01T00:00:00.000Z",
+            "text": text,
+            "userId": "u",
+            "user": { "id": "u", "username": "alice", "host": null },
+        }))
+        .unwrap();
+        DynNoteModel::from_mi_model(mi_note, Host::from("example.com".to_owned()))
+    }
+
+    #[test]
+    fn counts_each_note_once() {
+        let filter = NoteFilter::new(MuteSettings {
+            words: vec!["spoiler".to_owned()],
+            ..Default::default()
+        })
+        .unwrap();
+
+        let muted = note("a", "a spoiler");
+        for _ in 0..3 {
+            assert!(filter.is_hidden(&muted));
+        }
+        assert!(filter.is_hidden(&note("b", "another SPOILER")));
+        assert!(!filter.is_hidden(&note("c", "fine")));
+
+        assert_eq!(filter.hidden_counts()["word spoiler"], 2);
+    }
+}
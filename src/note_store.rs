@@ -0,0 +1,337 @@
+use std::{
+    collections::HashSet,
+    error::Error,
+    path::Path,
+    sync::{Mutex, MutexGuard},
+};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use rusqlite::{params, Connection};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tracing::{error, info, warn};
+
+use crate::{
+    common_types::{BranchKey, DynNoteModel, Host, MiMergeError, StorageSettings},
+    mi_models::Note,
+};
+
+pub const NOTE_STORE_FILE_NAME: &str = "notes.sqlite3";
+
+// これより古いノートは、開いたときに消す。
+pub const STORE_MAX_NOTES: usize = 10000;
+
+// 起動時に、ネットワークに繋ぐ前にタイムラインへ戻すノートの数。
+pub const RESTORED_NOTES: usize = 200;
+
+// スキーマのバージョンは `PRAGMA user_version` に記録する。
+const MIGRATIONS: &[&str] = &[
+    // 1: ノート本体と、ブランチ、リアクションの数。
+    "
+    CREATE TABLE notes (
+        uri TEXT PRIMARY KEY,
+        source_host TEXT NOT NULL,
+        original_host TEXT NOT NULL,
+        created_at TEXT NOT NULL,
+        note TEXT NOT NULL,
+        branches TEXT NOT NULL,
+        reactions TEXT NOT NULL
+    );
+    CREATE INDEX notes_created_at ON notes (created_at);
+    CREATE INDEX notes_source_host ON notes (source_host, created_at);
+    ",
+    // 2: `branches` のうち、ルールで付いたもの。
+    "
+    ALTER TABLE notes ADD COLUMN rule_branches TEXT NOT NULL DEFAULT '[]';
+    ",
+];
+
+#[derive(Debug)]
+pub enum NoteStoreError {
+    SqliteError(rusqlite::Error),
+    InvalidFormatRecord,
+    UnknownSchemaVersion(usize),
+    IoError,
+}
+
+impl std::fmt::Display for NoteStoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            NoteStoreError::SqliteError(e) => {
+                write!(f, "sqlite error: {e}")
+            }
+            NoteStoreError::InvalidFormatRecord => {
+                write!(f, "invalid format record")
+            }
+            NoteStoreError::UnknownSchemaVersion(v) => {
+                write!(f, "unknown schema version: {v}")
+            }
+            NoteStoreError::IoError => {
+                write!(f, "io error")
+            }
+        }
+    }
+}
+
+impl Error for NoteStoreError {}
+
+impl From<rusqlite::Error> for NoteStoreError {
+    fn from(value: rusqlite::Error) -> Self {
+        Self::SqliteError(value)
+    }
+}
+
+impl From<serde_json::Error> for NoteStoreError {
+    fn from(_value: serde_json::Error) -> Self {
+        Self::InvalidFormatRecord
+    }
+}
+
+impl From<std::io::Error> for NoteStoreError {
+    fn from(_value: std::io::Error) -> Self {
+        Self::IoError
+    }
+}
+
+// `MergedTimeline` に入ったノートを、再起動をまたいで保持する。
+#[derive(Debug)]
+pub struct NoteStore {
+    // `None` なら、何も保存せず何も読み込まない。
+    conn: Option<Mutex<Connection>>,
+}
+
+impl NoteStore {
+    // メモリ上のデータベースすら開けなければ、保存しないまま動かす。
+    pub fn from_settings(settings: &StorageSettings) -> Self {
+        match Self::open_or_in_memory(&settings.note_store) {
+            Ok(store) => store,
+            Err(e) => {
+                error!("failed to open any note store, notes will not be kept: {e}");
+                Self::disabled()
+            }
+        }
+    }
+
+    // 開けなければ、メモリ上のデータベースで代用する。
+    fn open_or_in_memory(path: &Path) -> Result<Self, MiMergeError> {
+        match Self::open(path) {
+            Ok(store) => Ok(store),
+            Err(e) => {
+                warn!("failed to open note store {}: {e}", path.display());
+                Ok(Self::open_in_memory()?)
+            }
+        }
+    }
+
+    pub fn disabled() -> Self {
+        Self { conn: None }
+    }
+
+    pub fn open(path: &Path) -> Result<Self, NoteStoreError> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        Self::init(conn)
+    }
+
+    pub fn open_in_memory() -> Result<Self, NoteStoreError> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(mut conn: Connection) -> Result<Self, NoteStoreError> {
+        migrate(&mut conn, MIGRATIONS)?;
+
+        let pruned = conn.execute(
+            "DELETE FROM notes WHERE uri NOT IN
+                (SELECT uri FROM notes ORDER BY created_at DESC LIMIT ?1)",
+            params![STORE_MAX_NOTES],
+        )?;
+        if pruned > 0 {
+            info!("pruned {pruned} notes from the note store");
+        }
+
+        Ok(Self {
+            conn: Some(Mutex::new(conn)),
+        })
+    }
+
+    fn conn(&self) -> Option<MutexGuard<'_, Connection>> {
+        self.conn.as_ref().map(|x| x.lock().unwrap())
+    }
+
+    // 書き込みを別のスレッドで行う。`MergedTimeline` のロックを持ったまま待たないように。
+    pub fn spawn_writer(&'static self) -> UnboundedSender<DynNoteModel> {
+        let (tx, mut rx) = unbounded_channel::<DynNoteModel>();
+        std::thread::spawn(move || {
+            while let Some(note) = rx.blocking_recv() {
+                if let Err(e) = self.save(&note) {
+                    warn!("failed to save a note: {e}");
+                }
+            }
+        });
+        tx
+    }
+
+    pub fn save(&self, note: &DynNoteModel) -> Result<(), NoteStoreError> {
+        let branches: Vec<_> = note.branches.iter().map(|x| &x.0).collect();
+        let rule_branches: Vec<_> = note.rule_branches.iter().map(|x| &x.0).collect();
+        let Some(conn) = self.conn() else {
+            return Ok(());
+        };
+
+        conn.execute(
+            "INSERT OR REPLACE INTO notes
+                (uri, source_host, original_host, created_at, note, branches, reactions,
+                    rule_branches)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                note.uri,
+                note.source_host.to_string(),
+                note.original_host.to_string(),
+                note.mi_note
+                    .created_at
+                    .to_rfc3339_opts(SecondsFormat::Millis, true),
+                serde_json::to_string(&note.mi_note)?,
+                serde_json::to_string(&branches)?,
+                serde_json::to_string(&note.reactions)?,
+                serde_json::to_string(&rule_branches)?,
+            ],
+        )?;
+        Ok(())
+    }
+
+    // 新しい順に `limit` 件まで読み込む。
+    pub fn load_recent(&self, limit: usize) -> Result<Vec<DynNoteModel>, NoteStoreError> {
+        let Some(conn) = self.conn() else {
+            return Ok(Vec::new());
+        };
+        let mut stmt = conn.prepare(
+            "SELECT uri, source_host, original_host, note, branches, reactions, rule_branches
+                FROM notes ORDER BY created_at DESC LIMIT ?1",
+        )?;
+        let rows = stmt.query_map(params![limit], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, String>(5)?,
+                row.get::<_, String>(6)?,
+            ))
+        })?;
+
+        let mut notes = Vec::new();
+        for row in rows {
+            let (uri, source_host, original_host, note, branches, reactions, rule_branches) = row?;
+            let mi_note: Note = match serde_json::from_str(&note) {
+                Ok(x) => x,
+                Err(e) => {
+                    warn!("skipped a stored note {uri}: {e}");
+                    continue;
+                }
+            };
+            let branches: Vec<String> = serde_json::from_str(&branches)?;
+            let rule_branches: Vec<String> = serde_json::from_str(&rule_branches)?;
+
+            notes.push(DynNoteModel {
+                original_host: Host::from(original_host),
+                source_host: Host::from(source_host),
+                uri,
+                mi_note,
+                reactions: serde_json::from_str(&reactions)?,
+                branches: branches.into_iter().map(BranchKey).collect::<HashSet<_>>(),
+                rule_branches: rule_branches.into_iter().map(BranchKey).collect(),
+            });
+        }
+        Ok(notes)
+    }
+
+    // そのホストから受け取ったノートのうち、最も新しいものの作成日時。
+    pub fn newest_created_at(
+        &self,
+        source_host: &Host,
+    ) -> Result<Option<DateTime<Utc>>, NoteStoreError> {
+        let Some(conn) = self.conn() else {
+            return Ok(None);
+        };
+        let created_at: Option<String> = conn.query_row(
+            "SELECT max(created_at) FROM notes WHERE source_host = ?1",
+            params![source_host.to_string()],
+            |row| row.get(0),
+        )?;
+
+        match created_at {
+            Some(x) => Ok(Some(
+                DateTime::parse_from_rfc3339(&x)
+                    .map_err(|_| NoteStoreError::InvalidFormatRecord)?
+                    .to_utc(),
+            )),
+            None => Ok(None),
+        }
+    }
+}
+
+// `migrations[i]` で、スキーマのバージョンを `i` から `i + 1` に上げる。
+pub fn migrate(conn: &mut Connection, migrations: &[&str]) -> Result<(), NoteStoreError> {
+    let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version > migrations.len() {
+        return Err(NoteStoreError::UnknownSchemaVersion(version));
+    }
+
+    for (i, sql) in migrations.iter().enumerate().skip(version) {
+        info!("migrating the schema to version {}", i + 1);
+        let tx = conn.transaction()?;
+        tx.execute_batch(sql)?;
+        tx.pragma_update(None, "user_version", i + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(id: &str) -> DynNoteModel {
+        let mi_note: Note = serde_json::from_value(serde_json::json!({
+            "id": id,
+            "createdAt": "2024-01-01T00:00:00.000Z",
+            "text": "hello",
+            "userId": "u",
+            "user": { "id": "u", "username": "alice", "host": null },
+        }))
+        .unwrap();
+        DynNoteModel::from_mi_model(mi_note, Host::from("example.com".to_owned()))
+    }
+
+    #[test]
+    fn keeps_rule_branches() {
+        let store = NoteStore::open_in_memory().unwrap();
+
+        let mut saved = note("a");
+        saved.branches = ["home", "files"].map(|x| BranchKey(x.to_owned())).into();
+        saved.rule_branches = [BranchKey("files".to_owned())].into();
+        store.save(&saved).unwrap();
+
+        let loaded = store.load_recent(10).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].branches, saved.branches);
+        assert_eq!(loaded[0].rule_branches, saved.rule_branches);
+    }
+
+    #[test]
+    fn disabled_store_keeps_nothing() {
+        let store = NoteStore::disabled();
+        store.save(&note("a")).unwrap();
+        assert!(store.load_recent(10).unwrap().is_empty());
+        assert_eq!(
+            store
+                .newest_created_at(&Host::from("example.com".to_owned()))
+                .unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,345 @@
+use std::{
+    collections::HashSet,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use itertools::Itertools;
+use tokio::sync::RwLock;
+use tracing::{debug, warn};
+
+use crate::{
+    common_types::{BranchKey, ChannelChannel, Host, MiMergeError, NoteModel},
+    merged_timeline::MergedTimeline,
+    mi_models::Note,
+    server_note_repo::ServerNoteRepo,
+    server_source::ServerSource,
+};
+
+// 1回の読み込みで、少なくともこれだけのノートが揃うまでページをたどる。
+const MIN_LOADED_NOTES: usize = 10;
+const MAX_ROUNDS: usize = 5;
+
+// 取得に失敗したソースは、間を空けてから取り直す。失敗が続くほど長く待つ。
+const RETRY_BACKOFF_MIN: Duration = Duration::from_secs(2);
+const RETRY_BACKOFF_MAX: Duration = Duration::from_secs(5 * 60);
+
+// タイムラインにノートを流し込むチャンネル1つ分。
+#[derive(Debug, Clone)]
+pub struct NoteSource {
+    pub host: Host,
+    pub source: Arc<dyn ServerSource>,
+    pub channel: ChannelChannel,
+    pub branches: HashSet<BranchKey>,
+    pub repo: Arc<RwLock<ServerNoteRepo>>,
+
+    // 起動時に取得したページの、最も古いノート。
+    pub oldest: Option<(String, DateTime<Utc>)>,
+}
+
+#[derive(Debug)]
+struct PagerSource {
+    source: NoteSource,
+    until_id: Option<String>,
+    reached: Option<DateTime<Utc>>,
+
+    // サーバーが空のページを返したときだけ立てる。
+    exhausted: bool,
+
+    failures: u32,
+    retry_at: Option<Instant>,
+
+    // 取得したが、まだタイムラインに流していないノート。新しい順。
+    pending: Vec<Note>,
+}
+
+// 列の下端から、より古いノートを読み込む。
+//
+// サーバーごとに1ページが覆う時間の幅は異なるので、取得したノートをそのまま流すと、
+// 遅いサーバーのページの切れ目より古いノートが先に並んでしまう。
+// そこで、すべてのソースがたどり着いた時点より新しいノートだけを、作成日時順に流す。
+#[derive(Debug)]
+pub struct Pager {
+    merged_timeline: Arc<RwLock<MergedTimeline>>,
+    sources: Vec<PagerSource>,
+}
+
+impl Pager {
+    pub fn new(merged_timeline: Arc<RwLock<MergedTimeline>>, sources: Vec<NoteSource>) -> Self {
+        let mut pager = Self {
+            merged_timeline,
+            sources: Vec::new(),
+        };
+        pager.refresh(sources);
+        pager
+    }
+
+    // 接続が増えたり減ったりしたときに、ソースを入れ替える。
+    // 続けて読み込めるように、残ったソースはたどった位置を引き継ぐ。
+    pub fn refresh(&mut self, sources: Vec<NoteSource>) {
+        let mut current = std::mem::take(&mut self.sources);
+        self.sources = sources
+            .into_iter()
+            .map(
+                |source| match current.iter().position(|x| x.source.is_same(&source)) {
+                    Some(i) => current.swap_remove(i),
+                    None => PagerSource::new(source),
+                },
+            )
+            .collect();
+    }
+
+    // ソースが無いうちは、まだ接続していないだけかもしれない。
+    pub fn is_exhausted(&self) -> bool {
+        !self.sources.is_empty()
+            && self
+                .sources
+                .iter()
+                .all(|x| x.exhausted && x.pending.is_empty())
+    }
+
+    // タイムラインに流したノートの数を返す。
+    pub async fn load_older(&mut self) -> usize {
+        let mut loaded = 0;
+
+        for _ in 0..MAX_ROUNDS {
+            if loaded >= MIN_LOADED_NOTES || self.is_exhausted() {
+                break;
+            }
+
+            // 手元のノートを流し切ったソースだけが、次のページを必要とする。
+            // 失敗したソースは、待っている間も他のソースを先に進めない。並びが崩れるため。
+            let now = Instant::now();
+            for x in &mut self.sources {
+                if x.exhausted || !x.pending.is_empty() || x.retry_at.is_some_and(|t| now < t) {
+                    continue;
+                }
+                match x.fetch_next().await {
+                    Ok(()) => {
+                        x.failures = 0;
+                        x.retry_at = None;
+                    }
+                    Err(e) => {
+                        let backoff = retry_backoff(x.failures);
+                        warn!(
+                            "failed to fetch older notes of {}, retrying in {backoff:?}: {e}",
+                            x.source.host
+                        );
+                        x.failures += 1;
+                        x.retry_at = Some(Instant::now() + backoff);
+                    }
+                }
+            }
+
+            loaded += self.flush().await;
+        }
+
+        loaded
+    }
+
+    // すべてのソースがたどり着いた時点より新しいノートを、作成日時順に流す。
+    async fn flush(&mut self) -> usize {
+        let watermark = self
+            .sources
+            .iter()
+            .filter(|x| !x.exhausted)
+            .filter_map(|x| x.reached)
+            .max();
+
+        let ready = self
+            .sources
+            .iter_mut()
+            .map(|x| {
+                let n = match watermark {
+                    Some(t) => x.pending.partition_point(|y| y.created_at >= t),
+                    None => x.pending.len(),
+                };
+                let source = &x.source;
+                x.pending.drain(..n).map(move |y| (source, y)).collect_vec()
+            })
+            .collect_vec();
+        let ready = ready
+            .into_iter()
+            .kmerge_by(|a, b| a.1.created_at > b.1.created_at)
+            .collect_vec();
+
+        if let Some((_, newest)) = ready.first() {
+            self.merged_timeline
+                .write()
+                .await
+                .extend_history(newest.created_at);
+        }
+
+        let loaded = ready.len();
+        debug!("loaded {loaded} older notes");
+        for (source, note) in ready {
+            let upserted = source.repo.write().await.upsert(
+                NoteModel::from_mi_model(note, source.host.clone()),
+                source.branches.clone(),
+            );
+            if let Err(e) = upserted {
+                warn!("failed to load an older note of {}: {e}", source.host);
+            }
+        }
+        loaded
+    }
+}
+
+impl NoteSource {
+    fn is_same(&self, other: &NoteSource) -> bool {
+        Arc::ptr_eq(&self.repo, &other.repo) && self.channel == other.channel
+    }
+}
+
+impl PagerSource {
+    fn new(source: NoteSource) -> Self {
+        Self {
+            until_id: source.oldest.as_ref().map(|x| x.0.clone()),
+            reached: source.oldest.as_ref().map(|x| x.1),
+            exhausted: source.oldest.is_none(),
+            failures: 0,
+            retry_at: None,
+            pending: Vec::new(),
+            source,
+        }
+    }
+
+    async fn fetch_next(&mut self) -> Result<(), MiMergeError> {
+        let mut notes = self
+            .source
+            .source
+            .fetch_page(&self.source.channel, self.until_id.as_deref())
+            .await?;
+        notes.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+
+        match notes.last() {
+            Some(oldest) => {
+                self.until_id = Some(oldest.id.clone());
+                self.reached = Some(oldest.created_at);
+            }
+            None => self.exhausted = true,
+        }
+        self.pending.extend(notes);
+        Ok(())
+    }
+}
+
+fn retry_backoff(failures: u32) -> Duration {
+    RETRY_BACKOFF_MIN
+        .saturating_mul(2u32.saturating_pow(failures))
+        .min(RETRY_BACKOFF_MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, sync::Mutex};
+
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::{
+        clock::SystemClock, mi_models::MeDetailed, server_capabilities::ServerCapabilities,
+        server_source::ServerStream,
+    };
+
+    // 決めておいた結果を、呼ばれた順に返す。
+    #[derive(Debug)]
+    struct ScriptedSource {
+        host: Host,
+        pages: Mutex<VecDeque<Result<Vec<Note>, MiMergeError>>>,
+    }
+
+    #[async_trait]
+    impl ServerSource for ScriptedSource {
+        fn host(&self) -> &Host {
+            &self.host
+        }
+
+        async fn probe(&self) -> ServerCapabilities {
+            ServerCapabilities::default()
+        }
+
+        async fn fetch_me(&self) -> Result<MeDetailed, MiMergeError> {
+            unimplemented!()
+        }
+
+        async fn fetch_page(
+            &self,
+            _channel: &ChannelChannel,
+            _until_id: Option<&str>,
+        ) -> Result<Vec<Note>, MiMergeError> {
+            self.pages.lock().unwrap().pop_front().unwrap()
+        }
+
+        fn stream(&self) -> Box<dyn ServerStream> {
+            unimplemented!()
+        }
+    }
+
+    fn source(pages: Vec<Result<Vec<Note>, MiMergeError>>) -> NoteSource {
+        let host = Host::from("example.com".to_owned());
+        NoteSource {
+            host: host.clone(),
+            source: Arc::new(ScriptedSource {
+                host,
+                pages: Mutex::new(pages.into()),
+            }),
+            channel: ChannelChannel::HomeTimeline,
+            branches: HashSet::new(),
+            repo: Default::default(),
+            oldest: Some(("a".to_owned(), Utc::now())),
+        }
+    }
+
+    fn pager(pages: Vec<Result<Vec<Note>, MiMergeError>>) -> Pager {
+        let merged_timeline = MergedTimeline::new(Default::default(), Arc::new(SystemClock));
+        Pager::new(Arc::new(RwLock::new(merged_timeline)), vec![source(pages)])
+    }
+
+    #[tokio::test]
+    async fn failed_fetch_is_retried_later() {
+        let mut pager = pager(vec![
+            Err(MiMergeError::HttpRequestError("timed out".to_owned())),
+            Ok(Vec::new()),
+        ]);
+
+        assert_eq!(pager.load_older().await, 0);
+        assert!(!pager.is_exhausted());
+        assert_eq!(pager.sources[0].failures, 1);
+
+        // 待っている間は取りに行かない。
+        pager.load_older().await;
+        assert!(!pager.is_exhausted());
+
+        pager.sources[0].retry_at = Some(Instant::now());
+        pager.load_older().await;
+        assert!(pager.is_exhausted());
+    }
+
+    #[test]
+    fn backoff_grows_up_to_the_limit() {
+        assert_eq!(retry_backoff(0), RETRY_BACKOFF_MIN);
+        assert_eq!(retry_backoff(1), RETRY_BACKOFF_MIN * 2);
+        assert_eq!(retry_backoff(40), RETRY_BACKOFF_MAX);
+    }
+
+    // 接続する前に作ったページャーも、後から繋いだソースを読み込める。
+    #[tokio::test]
+    async fn sources_can_be_added_later() {
+        let merged_timeline = MergedTimeline::new(Default::default(), Arc::new(SystemClock));
+        let mut pager = Pager::new(Arc::new(RwLock::new(merged_timeline)), Vec::new());
+        assert!(!pager.is_exhausted());
+
+        let added = source(vec![Ok(Vec::new())]);
+        pager.refresh(vec![added.clone()]);
+        assert!(!pager.is_exhausted());
+
+        pager.load_older().await;
+        assert!(pager.is_exhausted());
+
+        // 同じソースは、たどった位置を引き継ぐ。
+        pager.refresh(vec![added]);
+        assert!(pager.is_exhausted());
+    }
+}
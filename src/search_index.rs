@@ -0,0 +1,358 @@
+use std::{
+    collections::HashSet,
+    path::Path,
+    sync::{Mutex, MutexGuard},
+};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use rusqlite::{params, params_from_iter, types::Value, Connection, OptionalExtension};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tracing::{error, info, warn};
+
+use crate::{
+    common_types::{BranchKey, DynNoteModel, Host, MiMergeError, StorageSettings},
+    mi_models::Note,
+    note_store::{migrate, NoteStoreError},
+};
+
+pub const SEARCH_INDEX_FILE_NAME: &str = "search.sqlite3";
+pub const SEARCH_MAX_DOCS: usize = 100000;
+
+// trigram なので、これより短い語は全文検索の索引を使えない。
+const MIN_INDEXED_TERM_CHARS: usize = 3;
+
+// `search_fts` は `search_docs` の検索用の索引。トリガーで同期する。
+const MIGRATIONS: &[&str] = &[
+    // 1
+    "
+    CREATE TABLE search_docs (
+        id INTEGER PRIMARY KEY,
+        uri TEXT NOT NULL UNIQUE,
+        source_host TEXT NOT NULL,
+        created_at TEXT NOT NULL,
+        has_media INTEGER NOT NULL,
+        branches TEXT NOT NULL,
+        note TEXT NOT NULL,
+        text TEXT NOT NULL,
+        cw TEXT NOT NULL,
+        author TEXT NOT NULL,
+        host TEXT NOT NULL,
+        hashtags TEXT NOT NULL,
+        file_names TEXT NOT NULL
+    );
+    CREATE INDEX search_docs_created_at ON search_docs (created_at);
+
+    CREATE VIRTUAL TABLE search_fts USING fts5(
+        text, cw, author, host, hashtags, file_names,
+        content = 'search_docs', content_rowid = 'id', tokenize = 'trigram'
+    );
+
+    CREATE TRIGGER search_docs_ai AFTER INSERT ON search_docs BEGIN
+        INSERT INTO search_fts (rowid, text, cw, author, host, hashtags, file_names)
+            VALUES (new.id, new.text, new.cw, new.author, new.host, new.hashtags, new.file_names);
+    END;
+    CREATE TRIGGER search_docs_ad AFTER DELETE ON search_docs BEGIN
+        INSERT INTO search_fts (search_fts, rowid, text, cw, author, host, hashtags, file_names)
+            VALUES ('delete', old.id, old.text, old.cw, old.author, old.host, old.hashtags, old.file_names);
+    END;
+    CREATE TRIGGER search_docs_au AFTER UPDATE ON search_docs BEGIN
+        INSERT INTO search_fts (search_fts, rowid, text, cw, author, host, hashtags, file_names)
+            VALUES ('delete', old.id, old.text, old.cw, old.author, old.host, old.hashtags, old.file_names);
+        INSERT INTO search_fts (rowid, text, cw, author, host, hashtags, file_names)
+            VALUES (new.id, new.text, new.cw, new.author, new.host, new.hashtags, new.file_names);
+    END;
+    ",
+];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchQuery {
+    pub text: String,
+    pub branch: Option<BranchKey>,
+
+    // ノートを書いたユーザーのホスト。
+    pub host: Option<Host>,
+
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub has_media: bool,
+}
+
+// `MergedTimeline` に入ったすべてのノートを、サーバーの検索機能に頼らず探せるようにする。
+#[derive(Debug)]
+pub struct SearchIndex {
+    // `None` なら、何も索引に入れず何も見つけない。
+    conn: Option<Mutex<Connection>>,
+}
+
+impl SearchIndex {
+    // メモリ上のデータベースすら開けなければ、検索できないまま動かす。
+    pub fn from_settings(settings: &StorageSettings) -> Self {
+        match Self::open_or_in_memory(&settings.search_index) {
+            Ok(index) => index,
+            Err(e) => {
+                error!("failed to open any search index, search will be unavailable: {e}");
+                Self::disabled()
+            }
+        }
+    }
+
+    // 開けなければ、メモリ上のデータベースで代用する。
+    fn open_or_in_memory(path: &Path) -> Result<Self, MiMergeError> {
+        match Self::open(path) {
+            Ok(index) => Ok(index),
+            Err(e) => {
+                warn!("failed to open search index {}: {e}", path.display());
+                Ok(Self::open_in_memory()?)
+            }
+        }
+    }
+
+    pub fn disabled() -> Self {
+        Self { conn: None }
+    }
+
+    pub fn open(path: &Path) -> Result<Self, NoteStoreError> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        Self::init(conn)
+    }
+
+    pub fn open_in_memory() -> Result<Self, NoteStoreError> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(mut conn: Connection) -> Result<Self, NoteStoreError> {
+        migrate(&mut conn, MIGRATIONS)?;
+
+        let pruned = conn.execute(
+            "DELETE FROM search_docs WHERE id NOT IN
+                (SELECT id FROM search_docs ORDER BY created_at DESC LIMIT ?1)",
+            params![SEARCH_MAX_DOCS],
+        )?;
+        if pruned > 0 {
+            info!("pruned {pruned} notes from the search index");
+        }
+
+        Ok(Self {
+            conn: Some(Mutex::new(conn)),
+        })
+    }
+
+    fn conn(&self) -> Option<MutexGuard<'_, Connection>> {
+        self.conn.as_ref().map(|x| x.lock().unwrap())
+    }
+
+    // 書き込みを別のスレッドで行う。`MergedTimeline` のロックを持ったまま待たないように。
+    pub fn spawn_writer(&'static self) -> UnboundedSender<DynNoteModel> {
+        let (tx, mut rx) = unbounded_channel::<DynNoteModel>();
+        std::thread::spawn(move || {
+            while let Some(note) = rx.blocking_recv() {
+                if let Err(e) = self.index(&note) {
+                    warn!("failed to index a note: {e}");
+                }
+            }
+        });
+        tx
+    }
+
+    // 同じ URI のノートは1つにまとめ、ブランチは合わせる。
+    pub fn index(&self, note: &DynNoteModel) -> Result<(), NoteStoreError> {
+        let Some(conn) = self.conn() else {
+            return Ok(());
+        };
+
+        let indexed: Option<String> = conn
+            .query_row(
+                "SELECT branches FROM search_docs WHERE uri = ?1",
+                params![note.uri],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let mut merged: HashSet<String> = match indexed {
+            Some(x) => serde_json::from_str(&x)?,
+            None => HashSet::new(),
+        };
+        merged.extend(note.branches.iter().map(|x| x.0.clone()));
+
+        // リノートは、リノートされたノートの内容で探せるようにする。
+        let main_note = note.mi_note.renote.as_deref().unwrap_or(&note.mi_note);
+
+        conn.execute(
+            "INSERT INTO search_docs
+                (uri, source_host, created_at, has_media, branches, note,
+                 text, cw, author, host, hashtags, file_names)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+                ON CONFLICT (uri) DO UPDATE SET
+                    source_host = excluded.source_host,
+                    has_media = excluded.has_media,
+                    branches = excluded.branches,
+                    note = excluded.note,
+                    text = excluded.text,
+                    cw = excluded.cw,
+                    author = excluded.author,
+                    hashtags = excluded.hashtags,
+                    file_names = excluded.file_names",
+            params![
+                note.uri,
+                note.source_host.to_string(),
+                note.mi_note
+                    .created_at
+                    .to_rfc3339_opts(SecondsFormat::Millis, true),
+                !main_note.files.is_empty(),
+                serde_json::to_string(&merged)?,
+                serde_json::to_string(&note.mi_note)?,
+                main_note.text.clone().unwrap_or_default(),
+                main_note.cw.clone().unwrap_or_default(),
+                format!(
+                    "{} {}",
+                    main_note.user.name.clone().unwrap_or_default(),
+                    main_note.user.acct(&note.source_host)
+                ),
+                main_note
+                    .user
+                    .host
+                    .clone()
+                    .unwrap_or(note.source_host.to_string()),
+                main_note.tags.clone().unwrap_or_default().join(" "),
+                main_note
+                    .files
+                    .iter()
+                    .map(|x| x.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ],
+        )?;
+        Ok(())
+    }
+
+    // 語が多く当たるものほど上に並べる。語が無ければ新しい順。
+    pub fn search(
+        &self,
+        query: &SearchQuery,
+        limit: usize,
+    ) -> Result<Vec<DynNoteModel>, NoteStoreError> {
+        let mut joins = String::new();
+        let mut conditions = Vec::new();
+        let mut values = Vec::<Value>::new();
+
+        let (indexed_terms, short_terms): (Vec<_>, Vec<_>) = query
+            .text
+            .split_whitespace()
+            .partition(|x| x.chars().count() >= MIN_INDEXED_TERM_CHARS);
+
+        if !indexed_terms.is_empty() {
+            joins.push_str("JOIN search_fts ON search_fts.rowid = search_docs.id");
+            conditions.push("search_fts MATCH ?".to_owned());
+            values.push(Value::Text(
+                indexed_terms
+                    .iter()
+                    .map(|x| format!("\"{}\"", x.replace('"', "\"\"")))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ));
+        }
+        for term in short_terms {
+            conditions.push(
+                "(search_docs.text LIKE ? ESCAPE '\\' OR search_docs.cw LIKE ? ESCAPE '\\'
+                    OR search_docs.author LIKE ? ESCAPE '\\'
+                    OR search_docs.hashtags LIKE ? ESCAPE '\\'
+                    OR search_docs.file_names LIKE ? ESCAPE '\\')"
+                    .to_owned(),
+            );
+            let pattern = format!("%{}%", escape_like(term));
+            values.extend(std::iter::repeat(Value::Text(pattern)).take(5));
+        }
+
+        if let Some(branch) = &query.branch {
+            conditions.push(
+                "EXISTS (SELECT 1 FROM json_each(search_docs.branches) WHERE value = ?)".to_owned(),
+            );
+            values.push(Value::Text(branch.0.clone()));
+        }
+        if let Some(host) = &query.host {
+            conditions.push("search_docs.host = ?".to_owned());
+            values.push(Value::Text(host.to_string()));
+        }
+        if let Some(since) = &query.since {
+            conditions.push("search_docs.created_at >= ?".to_owned());
+            values.push(Value::Text(
+                since.to_rfc3339_opts(SecondsFormat::Millis, true),
+            ));
+        }
+        if let Some(until) = &query.until {
+            conditions.push("search_docs.created_at < ?".to_owned());
+            values.push(Value::Text(
+                until.to_rfc3339_opts(SecondsFormat::Millis, true),
+            ));
+        }
+        if query.has_media {
+            conditions.push("search_docs.has_media = 1".to_owned());
+        }
+
+        let where_clause = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
+        };
+        // 列の重みは text, cw, author, host, hashtags, file_names の順。
+        let order = if joins.is_empty() {
+            "search_docs.created_at DESC"
+        } else {
+            "bm25(search_fts, 1.0, 1.0, 3.0, 0.5, 5.0, 1.0), search_docs.created_at DESC"
+        };
+        values.push(Value::Integer(limit as i64));
+
+        let sql = format!(
+            "SELECT search_docs.uri, search_docs.source_host, search_docs.note, search_docs.branches
+                FROM search_docs {joins}
+                {where_clause} ORDER BY {order} LIMIT ?"
+        );
+
+        let Some(conn) = self.conn() else {
+            return Ok(Vec::new());
+        };
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt.query_map(params_from_iter(values), |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?;
+
+        let mut notes = Vec::new();
+        for row in rows {
+            let (uri, source_host, note, branches) = row?;
+            let mi_note: Note = match serde_json::from_str(&note) {
+                Ok(x) => x,
+                Err(e) => {
+                    warn!("skipped an indexed note {uri}: {e}");
+                    continue;
+                }
+            };
+            let branches: Vec<String> = serde_json::from_str(&branches)?;
+
+            let mut note = DynNoteModel::from_mi_model(mi_note, Host::from(source_host));
+            note.reactions = note
+                .mi_note
+                .reactions
+                .iter()
+                .map(|(k, &v)| (k.clone(), v))
+                .collect();
+            note.branches = branches.into_iter().map(BranchKey).collect();
+            notes.push(note);
+        }
+        Ok(notes)
+    }
+}
+
+fn escape_like(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
@@ -0,0 +1,298 @@
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use tracing::{info, warn};
+
+use crate::{
+    common_types::{ChannelChannel, Host, MiMergeError},
+    mi_models::RolePolicies,
+};
+
+// ノートごとのリアクションの受け入れ設定 (`reactionAcceptance`) が入った Misskey のバージョン。
+const REACTION_ACCEPTANCE_SINCE: (u32, u32) = (13, 10);
+
+// サーバーごとに使える機能。接続するときに nodeinfo と `/api/meta` から調べる。
+// 調べられなかった項目は、使えるものとして扱う。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerCapabilities {
+    pub software_name: Option<String>,
+    pub software_version: Option<String>,
+    pub local_timeline: bool,
+    pub global_timeline: bool,
+    pub max_note_length: Option<usize>,
+
+    // Misskey のチャンネル。
+    pub channels: bool,
+
+    // ノートごとにリアクションを制限できるか。バージョンから分からないフォークは `None` 。
+    pub reaction_acceptance: Option<bool>,
+
+    // `/api/emoji` と `/api/emojis` があるか。v12 までは `/api/meta` に絵文字が入っている。
+    pub emoji_endpoint: bool,
+}
+
+impl Default for ServerCapabilities {
+    fn default() -> Self {
+        Self {
+            software_name: None,
+            software_version: None,
+            local_timeline: true,
+            global_timeline: true,
+            max_note_length: None,
+            channels: true,
+            reaction_acceptance: None,
+            emoji_endpoint: true,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct NodeInfoLinks {
+    links: Vec<NodeInfoLink>,
+}
+
+#[derive(Deserialize, Debug)]
+struct NodeInfoLink {
+    rel: String,
+    href: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct NodeInfo {
+    software: NodeInfoSoftware,
+
+    #[serde(default)]
+    metadata: Map<String, Value>,
+}
+
+#[derive(Deserialize, Debug)]
+struct NodeInfoSoftware {
+    name: String,
+
+    #[serde(default)]
+    version: Option<String>,
+}
+
+impl ServerCapabilities {
+    pub fn supports(&self, channel: &ChannelChannel) -> bool {
+        match channel {
+            ChannelChannel::HomeTimeline => true,
+            ChannelChannel::LocalTimeline => self.local_timeline,
+            ChannelChannel::Channel { .. } => self.channels,
+            ChannelChannel::Hashtag { .. } => true,
+        }
+    }
+
+    // ロールで許されているかは、アカウントごとに違う。
+    pub fn apply_policies(&mut self, policies: &RolePolicies) {
+        if let Some(x) = policies.ltl_available {
+            self.local_timeline = x;
+        }
+        if let Some(x) = policies.gtl_available {
+            self.global_timeline = x;
+        }
+    }
+
+    // "misskey 2024.5.0" など。
+    pub fn software(&self) -> Option<String> {
+        let name = self.software_name.as_ref()?;
+        Some(match &self.software_version {
+            Some(version) => format!("{name} {version}"),
+            None => name.clone(),
+        })
+    }
+
+    fn apply_nodeinfo(&mut self, nodeinfo: NodeInfo) {
+        if let Some(x) = nodeinfo.metadata.get("disableLocalTimeline") {
+            self.local_timeline = !x.as_bool().unwrap_or(false);
+        }
+        if let Some(x) = nodeinfo.metadata.get("disableGlobalTimeline") {
+            self.global_timeline = !x.as_bool().unwrap_or(false);
+        }
+        if let Some(x) = nodeinfo.metadata.get("maxNoteTextLength") {
+            self.max_note_length = x.as_u64().map(|x| x as usize);
+        }
+        self.software_name = Some(nodeinfo.software.name.to_lowercase());
+        self.software_version = nodeinfo.software.version;
+    }
+
+    fn apply_meta(&mut self, meta: &Map<String, Value>) {
+        // v12 までは `disable*Timeline` 、v13 からはロールの `policies` 。
+        if let Some(x) = meta.get("disableLocalTimeline").and_then(Value::as_bool) {
+            self.local_timeline = !x;
+        }
+        if let Some(x) = meta.get("disableGlobalTimeline").and_then(Value::as_bool) {
+            self.global_timeline = !x;
+        }
+        if let Some(policies) = meta.get("policies") {
+            match serde_json::from_value(policies.clone()) {
+                Ok(policies) => self.apply_policies(&policies),
+                Err(e) => warn!("failed to read policies: {e}"),
+            }
+        }
+        if let Some(x) = meta.get("maxNoteTextLength").and_then(Value::as_u64) {
+            self.max_note_length = Some(x as usize);
+        }
+        if self.software_version.is_none() {
+            self.software_version = meta
+                .get("version")
+                .and_then(Value::as_str)
+                .map(str::to_owned);
+        }
+
+        self.emoji_endpoint = !meta.contains_key("emojis");
+        if self.software_name.as_deref() == Some("misskey") {
+            self.reaction_acceptance = self
+                .software_version
+                .as_deref()
+                .and_then(parse_version)
+                .map(|x| x >= REACTION_ACCEPTANCE_SINCE);
+        }
+    }
+}
+
+// 失敗しても接続は続けるので、調べられたところまでを返す。
+pub async fn probe_misskey(host: &Host) -> ServerCapabilities {
+    let mut capabilities = probe_nodeinfo(host).await;
+    match fetch_meta(host).await {
+        Ok(meta) => capabilities.apply_meta(&meta),
+        Err(e) => warn!("failed to fetch meta of {host}: {e}"),
+    }
+
+    info!("capabilities of {host}: {capabilities:?}");
+    capabilities
+}
+
+// Misskey の機能は無いものとし、上限だけを `/api/v2/instance` から読む。
+// ストリーミングのサーバーが別にあれば、その URL も返す。
+pub async fn probe_mastodon(host: &Host) -> (ServerCapabilities, Option<String>) {
+    let mut capabilities = ServerCapabilities {
+        channels: false,
+        reaction_acceptance: Some(false),
+        emoji_endpoint: false,
+        ..probe_nodeinfo(host).await
+    };
+
+    let mut streaming_url = None;
+    match fetch_instance(host).await {
+        Ok(instance) => {
+            if let Some(x) = instance.pointer("/configuration/statuses/max_characters") {
+                capabilities.max_note_length = x.as_u64().map(|x| x as usize);
+            }
+            streaming_url = instance
+                .pointer("/configuration/urls/streaming")
+                .and_then(Value::as_str)
+                .map(str::to_owned);
+        }
+        Err(e) => warn!("failed to fetch instance of {host}: {e}"),
+    }
+
+    info!("capabilities of {host}: {capabilities:?}");
+    (capabilities, streaming_url)
+}
+
+async fn probe_nodeinfo(host: &Host) -> ServerCapabilities {
+    let mut capabilities = ServerCapabilities::default();
+    match fetch_nodeinfo(host).await {
+        Ok(nodeinfo) => capabilities.apply_nodeinfo(nodeinfo),
+        Err(e) => warn!("failed to fetch nodeinfo of {host}: {e}"),
+    }
+    capabilities
+}
+
+async fn fetch_nodeinfo(host: &Host) -> Result<NodeInfo, MiMergeError> {
+    let client = reqwest::Client::new();
+    let links: NodeInfoLinks = client
+        .get(format!("https://{host}/.well-known/nodeinfo"))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    // 新しいスキーマを優先する。
+    let href = links
+        .links
+        .iter()
+        .filter(|x| {
+            x.rel
+                .starts_with("http://nodeinfo.diaspora.software/ns/schema/")
+        })
+        .max_by(|a, b| a.rel.cmp(&b.rel))
+        .map(|x| x.href.clone())
+        .ok_or_else(|| MiMergeError::HttpRequestError("no nodeinfo link".to_owned()))?;
+
+    let nodeinfo = client
+        .get(href)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(nodeinfo)
+}
+
+async fn fetch_meta(host: &Host) -> Result<Map<String, Value>, MiMergeError> {
+    let res = reqwest::Client::new()
+        .post(format!("https://{host}/api/meta"))
+        .json(&json!({ "detail": true }))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(res)
+}
+
+async fn fetch_instance(host: &Host) -> Result<Value, MiMergeError> {
+    let res = reqwest::Client::new()
+        .get(format!("https://{host}/api/v2/instance"))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(res)
+}
+
+// "13.14.2" や "2024.5.0-beta.1" の先頭の2つの数。
+fn parse_version(s: &str) -> Option<(u32, u32)> {
+    let mut parts = s.split(['.', '-']);
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta(version: &str) -> Map<String, Value> {
+        json!({ "version": version, "maxNoteTextLength": 3000 })
+            .as_object()
+            .unwrap()
+            .clone()
+    }
+
+    #[test]
+    fn reaction_acceptance_follows_the_misskey_version() {
+        for (name, version, expected) in [
+            ("misskey", "13.9.2", Some(false)),
+            ("misskey", "13.10.0", Some(true)),
+            ("misskey", "2024.5.0-beta.1", Some(true)),
+            // フォークのバージョンは Misskey と比べられない。
+            ("sharkey", "2024.3.1", None),
+            ("firefish", "1.0.5", None),
+        ] {
+            let mut capabilities = ServerCapabilities {
+                software_name: Some(name.to_owned()),
+                ..Default::default()
+            };
+            capabilities.apply_meta(&meta(version));
+            assert_eq!(
+                capabilities.reaction_acceptance, expected,
+                "{name} {version}"
+            );
+            assert_eq!(capabilities.max_note_length, Some(3000));
+        }
+    }
+}
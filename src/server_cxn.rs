@@ -1,5 +1,6 @@
 use std::{collections::HashSet, error::Error, future::Future};
 
+use async_trait::async_trait;
 use futures_util::{
     stream::{SplitSink, SplitStream},
     SinkExt, StreamExt,
@@ -17,13 +18,18 @@ use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, Web
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
-use crate::{common_types::Host, mi_models::WsMsg};
+use crate::{
+    common_types::{ChannelChannel, Host},
+    mi_models::WsMsg,
+    server_source::ServerStream,
+};
 
 #[derive(Debug)]
 pub enum ServerCxnError {
     ConnectError,
     SendToClosedServerError,
     HttpRequestError,
+    AlreadySpawnedError,
 }
 
 impl std::fmt::Display for ServerCxnError {
@@ -38,6 +44,9 @@ impl std::fmt::Display for ServerCxnError {
             ServerCxnError::HttpRequestError => {
                 write!(f, "http request error")
             }
+            ServerCxnError::AlreadySpawnedError => {
+                write!(f, "the connection has already been spawned or closed")
+            }
         }
     }
 }
@@ -50,25 +59,38 @@ impl From<reqwest::Error> for ServerCxnError {
     }
 }
 
+// 送受信のスレッド。`Offline` のときは、スレッドに渡す資源を持っている。
 #[derive(Debug)]
-enum ThrResource<T> {
+pub(crate) enum ThrResource<T> {
     Online(JoinHandle<T>),
     Offline(T),
     Uninit,
 }
 
 impl<T> ThrResource<T> {
-    fn into_online<F>(&mut self, f: impl FnOnce(T) -> F)
+    pub(crate) fn into_online<F>(&mut self, f: impl FnOnce(T) -> F) -> Result<(), ServerCxnError>
     where
         F: Future<Output = T> + Send + 'static,
         F::Output: Send + 'static,
     {
-        let r = match std::mem::replace(self, Self::Uninit) {
-            Self::Online(_) => panic!("invalid operation"),
-            Self::Offline(r) => r,
-            Self::Uninit => unreachable!(),
+        if !matches!(self, Self::Offline(_)) {
+            return Err(ServerCxnError::AlreadySpawnedError);
+        }
+        let Self::Offline(r) = std::mem::replace(self, Self::Uninit) else {
+            unreachable!()
         };
         *self = Self::Online(tokio::spawn(f(r)));
+        Ok(())
+    }
+
+    pub(crate) fn is_finished(&self) -> bool {
+        matches!(self, Self::Online(handle) if handle.is_finished())
+    }
+
+    pub(crate) fn abort(&mut self) {
+        if let Self::Online(handle) = std::mem::replace(self, Self::Uninit) {
+            handle.abort();
+        }
     }
 }
 
@@ -89,16 +111,26 @@ impl RecvThr {
                             continue;
                         }
                     };
+                    // 扱わない種類のメッセージは読めなくてよい。
+                    // 扱う種類なのに読めなければ、モデルが対応していない。
+                    let handled = is_handled_message(&m);
                     let m = match serde_json::from_value::<WsMsg>(m) {
                         Ok(m) => m,
+                        Err(e) if handled => {
+                            warn!("skipped a message that could not be read: {e}");
+                            continue;
+                        }
                         Err(e) => {
-                            info!("{e:?}");
+                            debug!("{e:?}");
                             continue;
                         }
                     };
                     debug!("{m:?}");
 
-                    self.tx.send(m).expect("mpsc error");
+                    // `ServerCxn` が捨てられたら、受け取るのをやめる。
+                    if self.tx.send(m).is_err() {
+                        break;
+                    }
                 }
                 Message::Ping(_) => {}
                 m => debug!("{m:?}"),
@@ -109,15 +141,24 @@ impl RecvThr {
     }
 }
 
-struct SendThr {
-    rx: UnboundedReceiver<String>,
-    pending: Option<String>,
-    ws_tx: SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>,
-    idle_tx: Option<oneshot::Sender<()>>,
+fn is_handled_message(m: &serde_json::Value) -> bool {
+    let body_type = m["body"]["type"].as_str();
+    match m["type"].as_str() {
+        Some("channel") => body_type == Some("note"),
+        Some("noteUpdated") => body_type == Some("reacted"),
+        _ => false,
+    }
+}
+
+pub(crate) struct SendThr {
+    pub(crate) rx: UnboundedReceiver<String>,
+    pub(crate) pending: Option<String>,
+    pub(crate) ws_tx: SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>,
+    pub(crate) idle_tx: Option<oneshot::Sender<()>>,
 }
 
 impl SendThr {
-    async fn run(mut self) -> (UnboundedReceiver<String>, Option<String>) {
+    pub(crate) async fn run(mut self) -> (UnboundedReceiver<String>, Option<String>) {
         if let Some(m) = self.pending.take() {
             dbg!();
             if self.ws_tx.send(Message::Text(m.clone())).await.is_err() {
@@ -135,7 +176,7 @@ impl SendThr {
                 Err(TryRecvError::Disconnected) => break,
                 Err(TryRecvError::Empty) => {
                     if let Some(idle_tx) = self.idle_tx.take() {
-                        idle_tx.send(()).expect("channel error");
+                        let _ = idle_tx.send(());
                     }
                     tokio::task::yield_now().await;
                     continue;
@@ -147,7 +188,6 @@ impl SendThr {
     }
 }
 
-#[derive(Debug)]
 pub struct ServerCxn {
     host: Host,
     api_key: String,
@@ -160,6 +200,20 @@ pub struct ServerCxn {
 
     home_timeline_id: Option<String>,
     local_timeline_id: Option<String>,
+
+    closed: bool,
+}
+
+impl std::fmt::Debug for ServerCxn {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ServerCxn")
+            .field("host", &self.host)
+            .field("api_key", &"<redacted>")
+            .field("home_timeline_id", &self.home_timeline_id)
+            .field("local_timeline_id", &self.local_timeline_id)
+            .field("closed", &self.closed)
+            .finish_non_exhaustive()
+    }
 }
 
 impl ServerCxn {
@@ -177,35 +231,9 @@ impl ServerCxn {
 
             home_timeline_id: None,
             local_timeline_id: None,
-        }
-    }
-
-    pub async fn spawn(&mut self) -> Result<(), ServerCxnError> {
-        info!("connecting to {}", self.host);
-        let req = format!("wss://{}/stream?i={}", self.host, self.api_key);
-        let (ws, _res) = connect_async(req)
-            .await
-            .map_err(|_| ServerCxnError::ConnectError)?;
-        let (ws_tx, ws_rx) = ws.split();
-        let (idle_tx, idle_rs) = oneshot::channel::<()>();
 
-        self.recv_thr.into_online(|tx| RecvThr { tx, ws_rx }.run());
-        self.send_thr.into_online(|(rx, pending)| {
-            SendThr {
-                rx,
-                pending,
-                ws_tx,
-                idle_tx: Some(idle_tx),
-            }
-            .run()
-        });
-
-        match idle_rs.await {
-            Ok(_) => info!("the receive thread is idle"),
-            Err(_) => warn!("the receive thread has downed before it becomes idle"),
+            closed: false,
         }
-
-        Ok(())
     }
 
     pub fn connect_to_home(&mut self) -> String {
@@ -260,27 +288,138 @@ impl ServerCxn {
         cxn_channel_id
     }
 
-    pub fn subscribe_note(&mut self, note_id: &str) {
+    pub fn connect_to_hashtag(&mut self, tag: &str) -> String {
+        let cxn_channel_id = Uuid::new_v4().to_string();
         self.send(
             json!({
-                "type": "subNote",
+                "type": "connect",
                 "body": {
-                    "id": note_id
+                    "id": cxn_channel_id.clone(),
+                    "channel": "hashtag",
+                    "params": {
+                        "q": [[tag]]
+                    }
                 }
             })
             .to_string(),
         );
+        cxn_channel_id
     }
 
+    // 閉じた後に送ったものは捨てる。
     pub fn send(&self, message: String) {
-        self.inlet.send(message).unwrap()
+        if self.inlet.send(message).is_err() {
+            debug!("dropped a message to closed {}", self.host);
+        }
     }
 
     pub async fn recv(&mut self) -> Option<WsMsg> {
         self.outlet.recv().await
     }
+}
+
+#[async_trait]
+impl ServerStream for ServerCxn {
+    async fn spawn(&mut self) -> Result<(), ServerCxnError> {
+        if !matches!(self.recv_thr, ThrResource::Offline(_)) {
+            return Err(ServerCxnError::AlreadySpawnedError);
+        }
+        info!("connecting to {}", self.host);
+        let req = format!("wss://{}/stream?i={}", self.host, self.api_key);
+        let (ws, _res) = connect_async(req)
+            .await
+            .map_err(|_| ServerCxnError::ConnectError)?;
+        let (ws_tx, ws_rx) = ws.split();
+        let (idle_tx, idle_rs) = oneshot::channel::<()>();
+
+        self.recv_thr
+            .into_online(|tx| RecvThr { tx, ws_rx }.run())?;
+        self.send_thr.into_online(|(rx, pending)| {
+            SendThr {
+                rx,
+                pending,
+                ws_tx,
+                idle_tx: Some(idle_tx),
+            }
+            .run()
+        })?;
+
+        match idle_rs.await {
+            Ok(_) => info!("the receive thread is idle"),
+            Err(_) => warn!("the receive thread has downed before it becomes idle"),
+        }
+
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        info!("disconnecting from {}", self.host);
+        self.recv_thr.abort();
+        self.send_thr.abort();
+        self.closed = true;
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed
+    }
 
-    pub fn try_recv(&mut self) -> Result<WsMsg, TryRecvError> {
-        self.outlet.try_recv()
+    fn connect_to(&mut self, channel: &ChannelChannel) -> String {
+        match channel {
+            ChannelChannel::HomeTimeline => self.connect_to_home(),
+            ChannelChannel::LocalTimeline => self.connect_to_local(),
+            ChannelChannel::Channel { channel_id } => self.connect_to_channel(channel_id),
+            ChannelChannel::Hashtag { tag } => self.connect_to_hashtag(tag),
+        }
+    }
+
+    fn disconnect(&mut self, cxn_channel_id: &str) {
+        self.send(
+            json!({
+                "type": "disconnect",
+                "body": {
+                    "id": cxn_channel_id
+                }
+            })
+            .to_string(),
+        );
+        if self.home_timeline_id.as_deref() == Some(cxn_channel_id) {
+            self.home_timeline_id = None;
+        }
+        if self.local_timeline_id.as_deref() == Some(cxn_channel_id) {
+            self.local_timeline_id = None;
+        }
+    }
+
+    fn subscribe_note(&mut self, note_id: &str) {
+        self.send(
+            json!({
+                "type": "subNote",
+                "body": {
+                    "id": note_id
+                }
+            })
+            .to_string(),
+        );
+    }
+
+    fn unsubscribe_note(&mut self, note_id: &str) {
+        self.send(
+            json!({
+                "type": "unsubNote",
+                "body": {
+                    "id": note_id
+                }
+            })
+            .to_string(),
+        );
+    }
+
+    fn try_recv(&mut self) -> Result<WsMsg, TryRecvError> {
+        match self.outlet.try_recv() {
+            Err(TryRecvError::Empty) if self.recv_thr.is_finished() => {
+                Err(TryRecvError::Disconnected)
+            }
+            x => x,
+        }
     }
 }
@@ -41,6 +41,11 @@ impl ServerNoteRepo {
     }
 
     pub fn incr_reaction(&mut self, note_id: &str, reaction: &str) {
+        // 捨てた後に届いたイベントは無視する。
+        if !self.notes.contains_key(note_id) {
+            return;
+        }
+
         self.reactions
             .entry(note_id.to_owned())
             .or_default()
@@ -51,7 +56,25 @@ impl ServerNoteRepo {
         self.send_dyn_note(note_id);
     }
 
-    pub fn send_dyn_note(&self, note_id: &str) {
+    // URI が `uris` に含まれるノートを捨て、そのノート ID を返す。
+    pub fn evict(&mut self, uris: &HashSet<String>) -> Vec<String> {
+        let note_ids: Vec<_> = self
+            .notes
+            .iter()
+            .filter(|(_, x)| uris.contains(&x.uri))
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        for id in &note_ids {
+            self.notes.remove(id);
+            self.branches.remove(id);
+            self.reactions.remove(id);
+        }
+        note_ids
+    }
+
+    // 受け手がいなくなった送り先は捨てる。
+    pub fn send_dyn_note(&mut self, note_id: &str) {
         let note = if let Some(note) = self.notes.get(note_id) {
             note
         } else {
@@ -69,9 +92,7 @@ impl ServerNoteRepo {
             dyn_model.branches.extend(xs.iter().cloned());
         }
 
-        for tx in &self.senders {
-            tx.send(dyn_model.clone()).expect("mpsc error");
-        }
+        self.senders.retain(|tx| tx.send(dyn_model.clone()).is_ok());
     }
 
     pub fn make_updated_note_receiver(&mut self) -> UnboundedReceiver<DynNoteModel> {
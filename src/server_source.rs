@@ -0,0 +1,65 @@
+use std::{fmt::Debug, sync::Arc};
+
+use async_trait::async_trait;
+use tokio::sync::mpsc::error::TryRecvError;
+
+use crate::{
+    common_types::{Backend, ChannelChannel, Host, MiMergeError},
+    mastodon_source::MastodonSource,
+    mi_models::{MeDetailed, Note, WsMsg},
+    misskey_source::MisskeySource,
+    server_capabilities::ServerCapabilities,
+    server_cxn::ServerCxnError,
+};
+
+// サーバーの種類ごとの違いを隠す。
+// ノートは Misskey の形に揃えて渡すので、`NoteModel::from_mi_model` から先は種類を問わない。
+#[async_trait]
+pub trait ServerSource: Debug + Send + Sync {
+    fn host(&self) -> &Host;
+
+    async fn probe(&self) -> ServerCapabilities;
+
+    async fn fetch_me(&self) -> Result<MeDetailed, MiMergeError>;
+
+    // `until_id` を指定すると、そのノートより古いページを取得する。
+    // 保持の方針で捨てたノートは、これで取り直せる。
+    async fn fetch_page(
+        &self,
+        channel: &ChannelChannel,
+        until_id: Option<&str>,
+    ) -> Result<Vec<Note>, MiMergeError>;
+
+    // まだ繋いでいないストリーム。`ServerStream::spawn` で繋ぐ。
+    fn stream(&self) -> Box<dyn ServerStream>;
+}
+
+// 新しいノートを受け取る接続。チャンネルごとに ID を振り、受け取ったノートにその ID を付けて渡す。
+#[async_trait]
+pub trait ServerStream: Debug + Send + Sync {
+    async fn spawn(&mut self) -> Result<(), ServerCxnError>;
+
+    // 送受信のスレッドを止めて、接続を閉じる。
+    fn close(&mut self);
+
+    fn is_closed(&self) -> bool;
+
+    fn connect_to(&mut self, channel: &ChannelChannel) -> String;
+
+    fn disconnect(&mut self, channel_id: &str);
+
+    // リアクションの増減を受け取る。受け取れないサーバーでは何もしない。
+    fn subscribe_note(&mut self, note_id: &str);
+
+    fn unsubscribe_note(&mut self, note_id: &str);
+
+    // サーバーが接続を閉じて、受け取ったものを読み切ったら `Disconnected` 。
+    fn try_recv(&mut self) -> Result<WsMsg, TryRecvError>;
+}
+
+pub fn new_source(backend: Backend, host: Host, api_key: String) -> Arc<dyn ServerSource> {
+    match backend {
+        Backend::Misskey => Arc::new(MisskeySource::new(host, api_key)),
+        Backend::Mastodon => Arc::new(MastodonSource::new(host, api_key)),
+    }
+}
@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+
+use dioxus::prelude::*;
+use tracing::warn;
+
+use crate::{
+    common_types::{Backend, Credential, Host},
+    config::{self, ConfigError},
+    connection_status::{account_key, ConnectionStatus},
+    global_state::get_app_model,
+    miauth::{MiAuthSession, LOGIN_TIMEOUT},
+    server_capabilities::ServerCapabilities,
+    Route,
+};
+
+#[component]
+pub fn Accounts() -> Element {
+    let mut credentials = use_signal(Vec::<Credential>::new);
+    let mut errors = use_signal(Vec::<String>::new);
+    let mut host = use_signal(String::new);
+
+    // ブラウザで許可を待っているときの URL と、待っているタスク。
+    let mut pending = use_signal(|| None::<(String, Task)>);
+
+    let mut show = move |result: Result<config::Config, ConfigError>| match result {
+        Ok(config) => {
+            credentials.set(config.credentials);
+            errors.set(Vec::new());
+        }
+        Err(e) => errors.set(e.messages()),
+    };
+
+    use_hook(move || show(config::read(&config::config_path())));
+
+    let mut statuses = use_signal(HashMap::<String, ConnectionStatus>::new);
+    let mut capabilities = use_signal(HashMap::<String, ServerCapabilities>::new);
+    use_future(move || async move {
+        let app_statuses = get_app_model().read().await.statuses.clone();
+        loop {
+            let keys: Vec<_> = credentials
+                .peek()
+                .iter()
+                .map(|x| account_key(&x.host, &x.user))
+                .collect();
+
+            let next: HashMap<_, _> = keys
+                .iter()
+                .filter_map(|key| app_statuses.get(key).map(|status| (key.clone(), status)))
+                .collect();
+            if *statuses.peek() != next {
+                statuses.set(next);
+            }
+
+            // 接続している間は書き込みのロックが続くので、待たずに次の機会にする。
+            if let Ok(app_model) = get_app_model().try_read() {
+                let next: HashMap<_, _> = keys
+                    .iter()
+                    .filter_map(|key| app_model.capabilities(key).map(|x| (key.clone(), x)))
+                    .collect();
+                drop(app_model);
+                if *capabilities.peek() != next {
+                    capabilities.set(next);
+                }
+            }
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        }
+    });
+
+    let login = move |_| {
+        let Some(h) = normalize_host(&host()) else {
+            return;
+        };
+        let session = MiAuthSession::new(Host::from(h.clone()));
+        let url = session.auth_url();
+        if let Err(e) = webbrowser::open(&url) {
+            warn!("failed to open the browser: {e}");
+        }
+
+        // MiAuth は Misskey の仕組みなので、Misskey の API で繋ぐ。
+        let task = spawn(async move {
+            let result = session.wait(LOGIN_TIMEOUT).await;
+            pending.set(None);
+            match result {
+                Ok(token) => {
+                    host.set(String::new());
+                    show(config::update(|c| {
+                        c.add_account(
+                            Credential {
+                                host: h,
+                                user: token.username,
+                                api_key: token.token,
+                                disable: false,
+                                sensitive_media: None,
+                            },
+                            Backend::Misskey,
+                        )
+                    }));
+                }
+                Err(e) => errors.set(vec![e.to_string()]),
+            }
+        });
+        pending.set(Some((url, task)));
+    };
+
+    let cancel = move |_| {
+        if let Some((_, task)) = pending.take() {
+            task.cancel();
+        }
+    };
+
+    rsx! {
+        div { class: "accounts",
+            Link { to: Route::Home {}, "← タイムライン" }
+            h1 { "アカウント" }
+
+            if !errors.read().is_empty() {
+                ul { class: "accounts-errors",
+                    for x in errors.read().iter() {
+                        li { "{x}" }
+                    }
+                }
+            }
+
+            table {
+                for x in credentials.read().iter().cloned() {
+                    tr { key: "{x.user}@{x.host}",
+                        td { class: if x.disable { "account-disabled" }, "{x.user}@{x.host}" }
+                        td { class: "account-status",
+                            if x.disable {
+                                "無効"
+                            } else if let Some(status) = statuses.read().get(&account_key(&x.host, &x.user)) {
+                                "{status}"
+                            }
+                        }
+                        td { class: "account-server",
+                            if let Some(x) = capabilities.read().get(&account_key(&x.host, &x.user)) {
+                                "{describe_server(x)}"
+                            }
+                        }
+                        td {
+                            button {
+                                onclick: {
+                                    let x = x.clone();
+                                    move |_| {
+                                        show(config::update(|c| {
+                                            c.set_account_disabled(&x.host, &x.user, !x.disable)
+                                        }))
+                                    }
+                                },
+                                if x.disable { "有効にする" } else { "無効にする" }
+                            }
+                        }
+                        td {
+                            button {
+                                onclick: move |_| {
+                                    show(config::update(|c| c.remove_account(&x.host, &x.user)))
+                                },
+                                "削除"
+                            }
+                        }
+                    }
+                }
+            }
+
+            h2 { "アカウントを追加" }
+            match pending() {
+                Some((url, _)) => rsx! {
+                    p {
+                        "ブラウザで許可してください。開かなければ、次の URL を開いてください。"
+                        br {}
+                        code { "{url}" }
+                    }
+                    button { onclick: cancel, "キャンセル" }
+                },
+                None => rsx! {
+                    div { class: "accounts-form",
+                        input {
+                            placeholder: "misskey.io",
+                            value: "{host}",
+                            oninput: move |e| host.set(e.value())
+                        }
+                        button { onclick: login, "ログイン" }
+                    }
+                },
+            }
+        }
+    }
+}
+
+// "misskey 2024.5.0 / 3000 文字 / LTL 無効" など。
+fn describe_server(capabilities: &ServerCapabilities) -> String {
+    let mut parts = Vec::new();
+    parts.extend(capabilities.software());
+    if let Some(x) = capabilities.max_note_length {
+        parts.push(format!("{x} 文字"));
+    }
+    if !capabilities.local_timeline {
+        parts.push("LTL 無効".to_owned());
+    }
+    if !capabilities.global_timeline {
+        parts.push("GTL 無効".to_owned());
+    }
+    parts.join(" / ")
+}
+
+// "https://Misskey.io/" などを "misskey.io" にする。
+fn normalize_host(s: &str) -> Option<String> {
+    let s = s.trim().to_lowercase();
+    let s = s.strip_prefix("https://").unwrap_or(&s);
+    let s = s.trim_end_matches('/');
+    (!s.is_empty()).then(|| s.to_owned())
+}
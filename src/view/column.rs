@@ -1,33 +1,43 @@
-use std::ops::Deref;
-
 use dioxus::prelude::*;
 
 use super::*;
 
-#[derive(Clone, PartialEq, Eq, Props)]
+#[derive(Clone, PartialEq, Props)]
 pub struct ColumnProps {
-    pub notes: Signal<Vec<NoteProps>>,
+    // 行ごとのシグナル。並びが変わったときだけ列を描き直す。
+    pub notes: Signal<Vec<Signal<NoteProps>>>,
 }
 
 #[component]
 pub fn Column(props: ColumnProps) -> Element {
     rsx! {
         div {
-            for note in props.notes.read().deref() {
-                Note {key: "{note.uri}",
-                    original_host: note.original_host.clone(),
-                    uri: &note.uri,
-                    avatar_url: &note.avatar_url,
-                    user_name: &note.user_name,
-                    note_info: &note.note_info,
-                    text: &note.text,
-                    file_thumbnails: note.file_thumbnails.clone(),
-                    reactions: note.reactions.clone(),
-                    branch_fragments: note.branch_fragments.clone(),
-                    renote: note.renote.clone(),
-                    debug: note.debug.clone()
-                }
+            for note in props.notes.read().iter().copied() {
+                NoteRow { key: "{note.peek().uri}", note }
             }
         }
     }
 }
+
+#[component]
+fn NoteRow(note: Signal<NoteProps>) -> Element {
+    let note = note.read();
+    rsx! {
+        Note {
+            original_host: note.original_host.clone(),
+            source_host: note.source_host.clone(),
+            uri: &note.uri,
+            avatar_url: &note.avatar_url,
+            avatar_placeholder: note.avatar_placeholder.clone(),
+            user_name: &note.user_name,
+            note_info: &note.note_info,
+            cw: note.cw.clone(),
+            text: &note.text,
+            files: note.files.clone(),
+            reactions: note.reactions.clone(),
+            branch_fragments: note.branch_fragments.clone(),
+            renote: note.renote.clone(),
+            debug: note.debug.clone()
+        }
+    }
+}
@@ -0,0 +1,27 @@
+use dioxus::prelude::*;
+
+#[derive(Clone, PartialEq, Eq, Props)]
+pub struct ConfigErrorsProps {
+    #[props(into)]
+    pub path: String,
+    pub errors: Vec<String>,
+}
+
+#[component]
+pub fn ConfigErrors(props: ConfigErrorsProps) -> Element {
+    rsx! {
+        div { class: "config-errors",
+            h1 { "設定ファイルを読み込めませんでした" }
+            p {
+                "次の問題を直してから、再起動してください。"
+                br {}
+                code { "{props.path}" }
+            }
+            ul {
+                for x in props.errors.iter() {
+                    li { "{x}" }
+                }
+            }
+        }
+    }
+}
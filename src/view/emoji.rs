@@ -1,7 +1,7 @@
 use dioxus::prelude::*;
 use tracing::{debug, error};
 
-use crate::{common_types::Host, global_state::get_emoji_service};
+use crate::{common_types::Host, global_state::get_emoji_service, media_cache::local_url};
 
 #[derive(Clone, PartialEq, Eq, Props)]
 pub struct EmojiProp {
@@ -15,13 +15,11 @@ pub fn Emoji(props: EmojiProp) -> Element {
 
     async fn f(props: EmojiProp) -> Option<String> {
         let emoji = get_emoji_service()
-            .write()
-            .await
             .fetch(&props.host, &props.name)
             .await
             .map_err(|e| error!("failed to fetch emoji url: {e:?}"))
             .ok()?;
-        Some(emoji.url)
+        Some(local_url(&emoji.url))
     }
     let url = use_resource({
         let props = props.clone();
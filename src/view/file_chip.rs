@@ -0,0 +1,64 @@
+use dioxus::prelude::*;
+
+#[derive(Clone, PartialEq, Props)]
+pub struct FileChipProps {
+    #[props(into)]
+    pub name: String,
+
+    pub size: i64,
+
+    #[props(into)]
+    pub url: String,
+
+    // 閲覧注意のファイルは、開くまで名前も出さない。
+    pub revealed: bool,
+
+    pub onreveal: EventHandler<()>,
+
+    // 指定されていなければ、ブラウザでファイルを開く。
+    pub onopen: Option<EventHandler<()>>,
+}
+
+#[component]
+pub fn FileChip(props: FileChipProps) -> Element {
+    let size = format_size(props.size);
+
+    if !props.revealed {
+        let onreveal = props.onreveal;
+        rsx! {
+            div { class: "file-chip sensitive-chip", onclick: move |_| onreveal.call(()),
+                span { "閲覧注意" }
+                span { class: "file-size", "{size}" }
+            }
+        }
+    } else if let Some(onopen) = props.onopen {
+        rsx! {
+            div { class: "file-chip", onclick: move |_| onopen.call(()),
+                span { class: "file-name", "{props.name}" }
+                span { class: "file-size", "{size}" }
+            }
+        }
+    } else {
+        rsx! {
+            a { class: "file-chip", href: "{props.url}", target: "_blank",
+                span { class: "file-name", "{props.name}" }
+                span { class: "file-size", "{size}" }
+            }
+        }
+    }
+}
+
+fn format_size(size: i64) -> String {
+    let units = ["B", "KB", "MB", "GB"];
+    let mut size = size as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < units.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{size} {}", units[unit])
+    } else {
+        format!("{size:.1} {}", units[unit])
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::HashSet;
+
+use dioxus::prelude::*;
+
+use super::*;
+use crate::media_cache::local_url;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FileKind {
+    Image,
+    Video,
+    Audio,
+    Other,
+}
+
+impl FileKind {
+    pub fn from_mime(mime: &str) -> Self {
+        match mime.split('/').next() {
+            Some("image") => Self::Image,
+            Some("video") => Self::Video,
+            Some("audio") => Self::Audio,
+            _ => Self::Other,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Props)]
+pub struct FileProps {
+    pub kind: FileKind,
+
+    // 原寸のファイル。
+    #[props(into)]
+    pub url: String,
+
+    #[props(into)]
+    pub thumbnail_url: Option<String>,
+
+    #[props(into)]
+    pub placeholder: Option<String>,
+
+    #[props(into)]
+    pub aspect_ratio: Option<String>,
+
+    pub sensitive: bool,
+
+    #[props(into)]
+    pub name: String,
+
+    pub size: i64,
+
+    #[props(into)]
+    pub comment: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Eq, Props)]
+pub struct FilesProps {
+    pub files: Vec<FileProps>,
+}
+
+#[component]
+pub fn Files(props: FilesProps) -> Element {
+    let mut viewing = use_signal(|| None::<usize>);
+
+    // 閲覧注意のうち、開いたファイルの添字。
+    let mut revealed = use_signal(HashSet::<usize>::new);
+
+    let items = props.files.iter().enumerate().map(|(i, x)| match x.kind {
+        FileKind::Image | FileKind::Video => rsx! {
+            Thumbnail {
+                url: thumbnail_url(x),
+                placeholder: x.placeholder.clone(),
+                aspect_ratio: x.aspect_ratio.clone(),
+                alt: x.comment.clone(),
+                revealed: !x.sensitive || revealed.read().contains(&i),
+                is_video: x.kind == FileKind::Video,
+                onreveal: move |_| {
+                    revealed.write().insert(i);
+                },
+                onopen: move |_| viewing.set(Some(i))
+            }
+        },
+        FileKind::Audio => rsx! {
+            FileChip {
+                name: x.name.clone(),
+                size: x.size,
+                url: x.url.clone(),
+                revealed: !x.sensitive || revealed.read().contains(&i),
+                onreveal: move |_| {
+                    revealed.write().insert(i);
+                },
+                onopen: move |_| viewing.set(Some(i))
+            }
+        },
+        FileKind::Other => rsx! {
+            FileChip {
+                name: x.name.clone(),
+                size: x.size,
+                url: x.url.clone(),
+                revealed: !x.sensitive || revealed.read().contains(&i),
+                onreveal: move |_| {
+                    revealed.write().insert(i);
+                }
+            }
+        },
+    });
+
+    rsx! {
+        div { class: "files", {items} }
+        if viewing().is_some() {
+            MediaViewer { files: props.files.clone(), index: viewing, revealed: revealed() }
+        }
+    }
+}
+
+// サムネイルが無ければ、画像は原寸のものをキャッシュ経由で出す。動画は `img` には出せない。
+fn thumbnail_url(file: &FileProps) -> Option<String> {
+    match (&file.thumbnail_url, file.kind) {
+        (Some(url), _) => Some(url.clone()),
+        (None, FileKind::Image) => Some(local_url(&file.url)),
+        (None, _) => None,
+    }
+}
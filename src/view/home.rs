@@ -1,110 +1,99 @@
-use std::{collections::HashSet, ops::Deref};
+use std::collections::HashSet;
 
-use chrono::prelude::*;
 use dioxus::prelude::*;
+use itertools::Itertools;
 use palette::{FromColor, Oklab, Srgb};
 
 use super::*;
-use crate::{
-    common_types::{BranchKey, DynNoteModel},
-    global_state::get_app_model,
-};
+use crate::{common_types::BranchKey, global_state::get_app_model, Route};
 
 #[component]
 pub fn Home() -> Element {
-    let mut notes = use_signal(|| Vec::<NoteProps>::new());
+    let mut columns = use_signal(|| Vec::<TimelineColumnProps>::new());
+    let mut hidden_counts = use_signal(|| Vec::<(String, usize)>::new());
 
-    spawn(async move {
-        let branches = get_app_model().read().await.branches();
-        let mut rx = get_app_model()
-            .read()
-            .await
-            .merged_timeline
-            .write()
-            .await
-            .make_column_receiver();
+    use_future(move || async move {
+        let mut rx = get_app_model().write().await.make_branches_receiver();
 
-        while let Some(model_notes) = rx.recv().await {
-            let mut notes_prop = Vec::new();
-            let mut branch_trace = HashSet::<BranchKey>::new();
+        // 設定の再読み込みでブランチが増減したら、列を作り直す。
+        loop {
+            let app_model = get_app_model().read().await;
+            let branches = app_model.branches();
 
-            for x in model_notes {
-                notes_prop.push(make_note_prop(&x, &branches, &mut branch_trace));
+            *columns.write() = app_model
+                .columns()
+                .into_iter()
+                .map(|column| {
+                    let filter = (!column.branches.is_empty()).then(|| {
+                        column
+                            .branches
+                            .into_iter()
+                            .map(BranchKey)
+                            .collect::<HashSet<_>>()
+                    });
 
-                branch_trace.extend(x.branches);
+                    // 色はすべての列で共通にする。
+                    let lanes = branches
+                        .iter()
+                        .filter(|x| filter.as_ref().is_none_or(|f| f.contains(x)))
+                        .map(|x| (x.clone(), make_color(app_model.branch_slot(x))))
+                        .collect();
+
+                    TimelineColumnProps {
+                        name: column.name,
+                        lanes,
+                        filter,
+                    }
+                })
+                .collect();
+            drop(app_model);
+
+            if rx.recv().await.is_none() {
+                break;
             }
+        }
+    });
+
+    use_future(move || async move {
+        if !cfg!(debug_assertions) {
+            return;
+        }
 
-            *notes.write() = notes_prop;
+        let note_filter = get_app_model().read().await.note_filter.clone();
+        loop {
+            *hidden_counts.write() = note_filter
+                .hidden_counts()
+                .into_iter()
+                .sorted_by(|a, b| b.1.cmp(&a.1))
+                .collect();
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
         }
     });
 
     rsx! {
-        Column { notes }
+        nav { class: "home-nav",
+            Link { to: Route::Search {}, "検索" }
+            Link { to: Route::Accounts {}, "アカウント" }
+        }
+        if cfg!(debug_assertions) {
+            MuteStats { hidden_counts }
+        }
+        div { class: "columns",
+            for (i , column) in columns.read().iter().enumerate() {
+                TimelineColumn {
+                    key: "{i}-{lanes_key(&column.lanes)}",
+                    name: column.name.clone(),
+                    lanes: column.lanes.clone(),
+                    filter: column.filter.clone()
+                }
+            }
+        }
     }
 }
 
-fn make_note_prop(
-    x: &DynNoteModel,
-    branches: &[BranchKey],
-    branch_trace: &mut HashSet<BranchKey>,
-) -> NoteProps {
-    let renote_header;
-    let main_note;
-    if let Some(renote) = &x.mi_note.renote {
-        renote_header = Some(&x.mi_note);
-        main_note = renote.deref();
-    } else {
-        renote_header = None;
-        main_note = &x.mi_note;
-    }
-
-    NoteProps {
-        original_host: x.original_host.clone(),
-        uri: x.uri.clone(),
-        avatar_url: main_note.user.avatar_url.clone(),
-        user_name: main_note
-            .user
-            .name
-            .clone()
-            .unwrap_or(main_note.user.username.clone()),
-        note_info: format!(
-            "{} {:?} {:?}",
-            from_now(&main_note.created_at),
-            main_note.visibility,
-            main_note.local_only
-        ),
-        text: main_note.text.clone().unwrap_or("".to_owned()),
-        file_thumbnails: main_note
-            .files
-            .iter()
-            .filter_map(|x| x.thumbnail_url.clone())
-            .collect(),
-        reactions: x.reactions.clone(),
-        branch_fragments: branches
-            .iter()
-            .enumerate()
-            .map(|(i, y)| BranchFragment {
-                color: make_color(i),
-                view: if branch_trace.contains(y) {
-                    if x.branches.contains(y) {
-                        BranchFragmentView::Full
-                    } else {
-                        BranchFragmentView::Skip
-                    }
-                } else if x.branches.contains(&y) {
-                    BranchFragmentView::Top
-                } else {
-                    BranchFragmentView::None
-                },
-            })
-            .collect(),
-        renote: renote_header.map(|x| RenoteInfo {
-            avatar_url: x.user.avatar_url.clone(),
-            user_name: x.user.name.clone().unwrap_or(x.user.username.clone()),
-        }),
-        debug: cfg!(debug_assertions)
-            .then_some(serde_json::to_string_pretty(&x).unwrap_or("parse error".to_string())),
-    }
+// レーンが変わった列だけを作り直す。
+fn lanes_key(lanes: &[(BranchKey, String)]) -> String {
+    lanes.iter().map(|x| x.0 .0.as_str()).join(",")
 }
 
 fn make_color(n: usize) -> String {
@@ -120,43 +109,3 @@ fn make_color(n: usize) -> String {
     let b = (rgb.blue * 256.0).round().min(255.0) as u8;
     return format!("#{r:02x}{g:02x}{b:02x}");
 }
-
-fn from_now(t: &DateTime<chrono::Utc>) -> String {
-    let dur = Utc::now() - t;
-    let neg = dur < chrono::TimeDelta::zero();
-    let dur = dur.abs();
-    let s = if dur.subsec_nanos() >= 500 {
-        dur.num_seconds() + 1
-    } else {
-        dur.num_seconds()
-    };
-
-    if s < 45 {
-        if neg {
-            format!("{:}秒後", s)
-        } else {
-            format!("{}秒前", s)
-        }
-    } else if s < 45 * 60 {
-        let m = (s as f64 / 60.0).round();
-        if neg {
-            format!("{m}分後")
-        } else {
-            format!("{m}分前")
-        }
-    } else if s < 22 * 60 * 60 {
-        let h = (s as f64 / (60.0 * 60.0)).round();
-        if neg {
-            format!("{h}時間後")
-        } else {
-            format!("{h}時間前")
-        }
-    } else {
-        let d = (s as f64 / (60.0 * 60.0 * 24.0)).round();
-        if neg {
-            format!("{d}日後")
-        } else {
-            format!("{d}日前")
-        }
-    }
-}
@@ -0,0 +1,87 @@
+use std::collections::HashSet;
+
+use dioxus::prelude::*;
+
+use super::*;
+
+#[derive(Clone, PartialEq, Props)]
+pub struct MediaViewerProps {
+    pub files: Vec<FileProps>,
+
+    // 表示中のファイルの添字。`None` にすると閉じる。
+    pub index: Signal<Option<usize>>,
+
+    // 閲覧注意のうち、一覧で開いたファイルの添字。
+    pub revealed: HashSet<usize>,
+}
+
+#[component]
+pub fn MediaViewer(props: MediaViewerProps) -> Element {
+    let mut index = props.index;
+    let current = index()?;
+    let file = props.files.get(current)?.clone();
+
+    // 添付ファイルのうち、ビューアで開けるものだけを行き来する。
+    // 閲覧注意のファイルは、一覧で開いていなければ飛ばす。
+    let viewable = props
+        .files
+        .iter()
+        .enumerate()
+        .filter(|(i, x)| x.kind != FileKind::Other && (!x.sensitive || props.revealed.contains(i)))
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+    let position = viewable.iter().position(|&i| i == current)?;
+    let n = viewable.len();
+    let prev = viewable[(position + n - 1) % n];
+    let next = viewable[(position + 1) % n];
+
+    let content = match file.kind {
+        FileKind::Image => rsx! {
+            img {
+                src: "{file.url}",
+                alt: file.comment.clone().unwrap_or_default(),
+                title: file.comment.clone().unwrap_or_default()
+            }
+        },
+        FileKind::Video => rsx! {
+            video { src: "{file.url}", controls: true, autoplay: true }
+        },
+        FileKind::Audio => rsx! {
+            div { class: "audio-title", "{file.name}" }
+            audio { src: "{file.url}", controls: true, autoplay: true }
+        },
+        FileKind::Other => rsx! {
+            a { href: "{file.url}", target: "_blank", "{file.name}" }
+        },
+    };
+
+    rsx! {
+        div {
+            class: "media-viewer",
+            tabindex: 0,
+            onmounted: move |e| async move {
+                _ = e.set_focus(true).await;
+            },
+            onkeydown: move |e: KeyboardEvent| match e.key() {
+                Key::ArrowLeft => index.set(Some(prev)),
+                Key::ArrowRight => index.set(Some(next)),
+                Key::Escape => index.set(None),
+                _ => {}
+            },
+            onclick: move |_| index.set(None),
+            div { class: "media-viewer-content", onclick: |e| e.stop_propagation(),
+                {content},
+                if let Some(comment) = &file.comment {
+                    div { class: "comment", "{comment}" }
+                }
+                if n > 1 {
+                    div { class: "media-viewer-nav",
+                        button { onclick: move |_| index.set(Some(prev)), "<" }
+                        span { "{position + 1} / {n}" }
+                        button { onclick: move |_| index.set(Some(next)), ">" }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,13 +1,33 @@
 #![allow(non_snake_case)]
+mod accounts;
 mod column;
+mod config_errors;
 mod emoji;
+mod file_chip;
+mod files;
 mod home;
+mod media_viewer;
+mod mute_stats;
 mod note;
 mod reaction;
+mod search;
+mod thumbnail;
+mod timeline_column;
+mod unlock;
 
+pub use accounts::Accounts;
+pub use config_errors::ConfigErrors;
 pub use home::Home;
+pub use search::Search;
+pub use unlock::Unlock;
 
 use column::*;
 use emoji::*;
+use file_chip::*;
+use files::*;
+use media_viewer::*;
+use mute_stats::*;
 use note::*;
 use reaction::*;
+use thumbnail::*;
+use timeline_column::*;
@@ -0,0 +1,25 @@
+use std::ops::Deref;
+
+use dioxus::prelude::*;
+
+#[derive(Clone, PartialEq, Eq, Props)]
+pub struct MuteStatsProps {
+    pub hidden_counts: Signal<Vec<(String, usize)>>,
+}
+
+#[component]
+pub fn MuteStats(props: MuteStatsProps) -> Element {
+    let hidden_counts = props.hidden_counts.read();
+    let total: usize = hidden_counts.iter().map(|(_, n)| n).sum();
+
+    rsx! {
+        div { class: "debug",
+            details {
+                summary { "ミュート {total}件" }
+                for (reason , n) in hidden_counts.deref() {
+                    div { "{reason}: {n}" }
+                }
+            }
+        }
+    }
+}
@@ -1,13 +1,16 @@
 use dioxus::prelude::*;
 
 use super::*;
-use crate::{common_types::Host, global_state::get_decomposer};
+use crate::{common_types::Host, global_state::get_decomposer, mi_models::ReactionLimit};
 
 #[derive(Clone, PartialEq, Eq, Props)]
 pub struct RenoteInfo {
     #[props(into)]
     pub avatar_url: String,
 
+    #[props(into)]
+    pub avatar_placeholder: Option<String>,
+
     #[props(into)]
     pub user_name: String,
 }
@@ -17,27 +20,39 @@ pub struct NoteProps {
     #[props(into)]
     pub original_host: Host,
 
+    #[props(into)]
+    pub source_host: Host,
+
     #[props(into)]
     pub uri: String,
 
     #[props(into)]
     pub avatar_url: String,
 
+    #[props(into)]
+    pub avatar_placeholder: Option<String>,
+
     #[props(into)]
     pub user_name: String,
 
     #[props(into)]
     pub note_info: String,
 
+    #[props(into)]
+    pub cw: Option<String>,
+
     #[props(into)]
     pub text: String,
 
     #[props(into)]
-    pub file_thumbnails: Vec<String>,
+    pub files: Vec<FileProps>,
 
     #[props(into)]
     pub reactions: Vec<(String, i64)>,
 
+    #[props(into)]
+    pub reaction_limit: Option<ReactionLimit>,
+
     #[props(into)]
     pub branch_fragments: Vec<BranchFragment>,
 
@@ -64,21 +79,15 @@ pub enum BranchFragmentView {
 
 #[component]
 pub fn Note(props: NoteProps) -> Element {
-    let decomposed = get_decomposer().decompose(&props.user_name);
-    let username = decomposed.into_iter().map(|x| match x {
-        crate::mfm::DecomposedTextItem::Text(x) => rsx! { "{x}" },
-        crate::mfm::DecomposedTextItem::Emoji(x) => rsx! {
-            Emoji { host: props.original_host.clone(), name: x }
-        },
-    });
+    let mut cw_opened = use_signal(|| false);
 
-    let decomposed = get_decomposer().decompose(&props.text);
-    let body = decomposed.into_iter().map(|x| match x {
-        crate::mfm::DecomposedTextItem::Text(x) => rsx! { "{x}" },
-        crate::mfm::DecomposedTextItem::Emoji(x) => rsx! {
-            Emoji { host: props.original_host.clone(), name: x }
-        },
-    });
+    let username = render_text(&props.user_name, &props.original_host, &props.source_host);
+    let body = render_text(&props.text, &props.original_host, &props.source_host);
+    let cw = props
+        .cw
+        .as_ref()
+        .map(|x| render_text(x, &props.original_host, &props.source_host));
+    let collapsed = cw.is_some() && !cw_opened();
 
     let branch_line_scale = 1000 / 25;
 
@@ -171,7 +180,10 @@ pub fn Note(props: NoteProps) -> Element {
                 div { class: "renote-header",
                     if let Some(renote) = props.renote {
                         div {
-                            img { src: "{renote.avatar_url}" }
+                            img {
+                                src: "{renote.avatar_url}",
+                                style: placeholder_style(renote.avatar_placeholder.as_deref())
+                            }
                         }
                         div {
                             span { {renote.user_name} }
@@ -179,7 +191,10 @@ pub fn Note(props: NoteProps) -> Element {
                     }
                 }
                 div { class: "avatar",
-                    img { src: "{props.avatar_url}" }
+                    img {
+                        src: "{props.avatar_url}",
+                        style: placeholder_style(props.avatar_placeholder.as_deref())
+                    }
                 }
                 div { class: "header",
                     div { class: "user-name",
@@ -188,16 +203,37 @@ pub fn Note(props: NoteProps) -> Element {
                     div { class: "note-info", "{props.note_info}" }
                 }
                 div { class: "body",
-                    span { {body} }
-                }
-                div { class: "files",
-                    for x in props.file_thumbnails {
-                        img { class: "file_thumbnail", src: x }
+                    if let Some(cw) = cw {
+                        div { class: "cw",
+                            span { {cw} }
+                            button { onclick: move |_| cw_opened.toggle(),
+                                if cw_opened() {
+                                    "隠す"
+                                } else {
+                                    "もっと見る"
+                                }
+                            }
+                        }
+                    }
+                    if !collapsed {
+                        span { {body} }
                     }
                 }
+                if !collapsed {
+                    Files { files: props.files }
+                }
                 div { class: "reactions",
                     for (r , n) in props.reactions {
-                        Reaction { key: "{r}", name: r, count: n }
+                        Reaction { key: "{r}", host: props.source_host.clone(), name: r, count: n }
+                    }
+                    match props.reaction_limit {
+                        Some(ReactionLimit::LikeOnly) => rsx! {
+                            span { class: "reaction-limit", "いいねのみ" }
+                        },
+                        Some(ReactionLimit::NonSensitiveOnly) => rsx! {
+                            span { class: "reaction-limit", "センシティブな絵文字は不可" }
+                        },
+                        None => None,
                     }
                 }
                 div { class: "debug",
@@ -212,3 +248,20 @@ pub fn Note(props: NoteProps) -> Element {
         }
     }
 }
+
+fn placeholder_style(placeholder: Option<&str>) -> String {
+    placeholder
+        .map(|x| format!("background-image: url({x});"))
+        .unwrap_or_default()
+}
+
+fn render_text(text: &str, original_host: &Host, source_host: &Host) -> Element {
+    let decomposed = get_decomposer().decompose(text);
+    let items = decomposed.into_iter().map(|x| match x {
+        crate::mfm::DecomposedTextItem::Text(x) => rsx! { "{x}" },
+        crate::mfm::DecomposedTextItem::Emoji(x) => rsx! {
+            Emoji { host: x.resolve_host(original_host, source_host), name: x.name }
+        },
+    });
+    rsx! { {items} }
+}
@@ -1,12 +1,12 @@
 use dioxus::prelude::*;
-use fancy_regex::Regex;
 use tracing::debug;
 
 use super::*;
-use crate::common_types::Host;
+use crate::{common_types::Host, global_state::get_decomposer};
 
 #[derive(Clone, PartialEq, Eq, Props)]
 pub struct ReactionProp {
+    pub host: Host,
     pub name: String,
     pub count: i64,
 }
@@ -15,13 +15,12 @@ pub struct ReactionProp {
 pub fn Reaction(props: ReactionProp) -> Element {
     debug!("rendering reaction {}", props.name);
 
-    let re = Regex::new("^:(.*)@(.*):$").unwrap();
-    match re.captures(&props.name).expect("regex error") {
-        Some(captures) => rsx! {
+    match get_decomposer().parse_emoji(&props.name) {
+        Some(emoji) => rsx! {
             div { class: "reaction-button",
                 Emoji {
-                    host: Host::from(captures.get(2).unwrap().as_str().to_owned()),
-                    name: captures.get(1).unwrap().as_str()
+                    host: emoji.resolve_host(&props.host, &props.host),
+                    name: emoji.name
                 }
                 span { "{props.count}" }
             }
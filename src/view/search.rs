@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+
+use chrono::prelude::*;
+use dioxus::prelude::*;
+use tracing::warn;
+
+use super::*;
+use crate::{
+    common_types::{BranchKey, Host, MediaVisibility},
+    global_state::{get_app_model, get_search_index},
+    search_index::SearchQuery,
+    Route,
+};
+
+const SEARCH_LIMIT: usize = 100;
+
+#[component]
+pub fn Search() -> Element {
+    let mut text = use_signal(String::new);
+    let mut branch = use_signal(String::new);
+    let mut host = use_signal(String::new);
+    let mut since = use_signal(String::new);
+    let mut until = use_signal(String::new);
+    let mut has_media = use_signal(|| false);
+
+    let mut branches = use_signal(Vec::<BranchKey>::new);
+    let mut media_visibilities = use_signal(HashMap::<Host, MediaVisibility>::new);
+    let mut results = use_signal(Vec::<Signal<NoteProps>>::new);
+    let mut searched = use_signal(|| false);
+    let mut searching = use_signal(|| false);
+
+    use_future(move || async move {
+        let app_model = get_app_model().read().await;
+        branches.set(app_model.branches());
+        media_visibilities.set(app_model.media_visibilities());
+    });
+
+    let search = move || {
+        let query = SearchQuery {
+            text: text(),
+            branch: (!branch().is_empty()).then(|| BranchKey(branch())),
+            host: (!host().trim().is_empty()).then(|| Host::from(host().trim().to_owned())),
+            since: parse_date(&since()),
+            // 終わりの日も含める。
+            until: parse_date(&until()).map(|x| x + chrono::Days::new(1)),
+            has_media: has_media(),
+        };
+
+        spawn(async move {
+            searching.set(true);
+            let found = tokio::task::spawn_blocking(move || {
+                get_search_index().search(&query, SEARCH_LIMIT)
+            })
+            .await;
+            searching.set(false);
+
+            let notes = match found {
+                Ok(Ok(notes)) => notes,
+                Ok(Err(e)) => {
+                    warn!("failed to search notes: {e}");
+                    Vec::new()
+                }
+                Err(e) => {
+                    warn!("the search task failed: {e}");
+                    Vec::new()
+                }
+            };
+
+            // 索引に入った後でミュートしたものも隠す。
+            let note_filter = get_app_model().read().await.note_filter.clone();
+            let media_visibilities = media_visibilities.read();
+            let mut results = results.write();
+            for x in results.drain(..) {
+                x.manually_drop();
+            }
+            *results = notes
+                .iter()
+                .filter(|x| !note_filter.hides(x))
+                .map(|x| {
+                    let media_visibility = media_visibilities
+                        .get(&x.source_host)
+                        .copied()
+                        .unwrap_or_default();
+                    Signal::new(make_note_prop(x, media_visibility))
+                })
+                .collect();
+            searched.set(true);
+        });
+    };
+
+    rsx! {
+        div { class: "search",
+            div { class: "search-form",
+                Link { to: Route::Home {}, "← タイムライン" }
+                input {
+                    r#type: "search",
+                    placeholder: "本文、CW、ユーザー、ハッシュタグ、ファイル名",
+                    value: "{text}",
+                    oninput: move |e| text.set(e.value()),
+                    onkeydown: move |e| {
+                        if e.key() == Key::Enter {
+                            search();
+                        }
+                    }
+                }
+                select {
+                    value: "{branch}",
+                    onchange: move |e| branch.set(e.value()),
+                    option { value: "", "すべてのブランチ" }
+                    for x in branches.read().iter() {
+                        option { value: "{x.0}", "{x.0}" }
+                    }
+                }
+                input {
+                    r#type: "text",
+                    placeholder: "ホスト",
+                    value: "{host}",
+                    oninput: move |e| host.set(e.value())
+                }
+                input {
+                    r#type: "date",
+                    value: "{since}",
+                    oninput: move |e| since.set(e.value())
+                }
+                "〜"
+                input {
+                    r#type: "date",
+                    value: "{until}",
+                    oninput: move |e| until.set(e.value())
+                }
+                label {
+                    input {
+                        r#type: "checkbox",
+                        checked: has_media(),
+                        onchange: move |e| has_media.set(e.checked())
+                    }
+                    "メディア付き"
+                }
+                button { disabled: searching(), onclick: move |_| search(), "検索" }
+            }
+            div { class: "search-results",
+                if searched() && results.read().is_empty() {
+                    p { "見つかりませんでした" }
+                }
+                Column { notes: results }
+            }
+        }
+    }
+}
+
+// `<input type="date">` の値を、その日の始まりの時刻にする。
+fn parse_date(s: &str) -> Option<DateTime<Utc>> {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .ok()?
+        .and_hms_opt(0, 0, 0)?
+        .and_local_timezone(Local)
+        .earliest()
+        .map(|x| x.to_utc())
+}
@@ -0,0 +1,71 @@
+use dioxus::prelude::*;
+
+#[derive(Clone, PartialEq, Props)]
+pub struct ThumbnailProps {
+    // 動画でサムネイルが無ければ `None` 。プレースホルダーだけを出す。
+    #[props(into)]
+    pub url: Option<String>,
+
+    #[props(into)]
+    pub placeholder: Option<String>,
+
+    #[props(into)]
+    pub aspect_ratio: Option<String>,
+
+    #[props(into)]
+    pub alt: Option<String>,
+
+    // 閲覧注意のファイルは、開くまで隠しておく。開いたかどうかはビューアと共有する。
+    pub revealed: bool,
+
+    pub is_video: bool,
+
+    pub onreveal: EventHandler<()>,
+
+    pub onopen: EventHandler<()>,
+}
+
+#[component]
+pub fn Thumbnail(props: ThumbnailProps) -> Element {
+    let mut style = String::new();
+    if let Some(placeholder) = &props.placeholder {
+        style += &format!("background-image: url({placeholder});");
+    }
+    if let Some(aspect_ratio) = &props.aspect_ratio {
+        style += &format!("aspect-ratio: {aspect_ratio};");
+    }
+
+    let alt = props.alt.clone().unwrap_or_default();
+    let onopen = props.onopen;
+    let onreveal = props.onreveal;
+
+    if props.revealed {
+        rsx! {
+            div { class: "thumbnail-container", onclick: move |_| onopen.call(()),
+                if let Some(url) = &props.url {
+                    img {
+                        class: "file_thumbnail",
+                        src: "{url}",
+                        style: "{style}",
+                        alt: "{alt}",
+                        title: "{alt}"
+                    }
+                } else {
+                    div { class: "file_thumbnail no-thumbnail", style: "{style}", title: "{alt}" }
+                }
+                if props.is_video {
+                    span { class: "video-badge", "▶" }
+                }
+            }
+        }
+    } else {
+        rsx! {
+            div {
+                class: "file_thumbnail sensitive",
+                style: "{style}",
+                onclick: move |_| onreveal.call(()),
+                span { "閲覧注意" }
+            }
+        }
+    }
+}
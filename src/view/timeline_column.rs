@@ -0,0 +1,400 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Deref,
+    rc::Rc,
+};
+
+use chrono::prelude::*;
+use dioxus::prelude::*;
+use futures_util::StreamExt;
+
+use super::*;
+use crate::{
+    common_types::{BranchKey, DynNoteModel, MediaVisibility},
+    global_state::{get_app_model, get_blurhash_decoder},
+    media_cache::local_url,
+    merged_timeline::ColumnDiff,
+};
+
+#[derive(Clone, PartialEq, Eq, Props)]
+pub struct TimelineColumnProps {
+    #[props(into)]
+    pub name: String,
+
+    // 列に表示するブランチと、その色。
+    pub lanes: Vec<(BranchKey, String)>,
+
+    // `None` ならすべてのノートを表示する。
+    pub filter: Option<HashSet<BranchKey>>,
+}
+
+// 列の下端がこれだけ近づいたら、古いノートを読み込む。
+const LOAD_OLDER_THRESHOLD_PX: f64 = 800.0;
+
+#[component]
+pub fn TimelineColumn(props: TimelineColumnProps) -> Element {
+    let mut notes = use_signal(Vec::<Signal<NoteProps>>::new);
+    let mut body = use_signal(|| None::<Rc<MountedData>>);
+    let mut footer = use_signal(|| None::<Rc<MountedData>>);
+    let mut loading = use_signal(|| false);
+    let mut exhausted = use_signal(|| false);
+
+    use_future({
+        let props = props.clone();
+        move || {
+            let props = props.clone();
+            async move {
+                let media_visibilities = get_app_model().read().await.media_visibilities();
+                let mut rx = get_app_model()
+                    .read()
+                    .await
+                    .merged_timeline
+                    .write()
+                    .await
+                    .make_column_receiver(props.filter.clone())
+                    .await;
+
+                let mut rows = Rows::default();
+
+                while let Some(diff) = rx.recv().await {
+                    let make = |x: &DynNoteModel| {
+                        let media_visibility = media_visibilities
+                            .get(&x.source_host)
+                            .copied()
+                            .unwrap_or_default();
+                        make_note_prop(x, media_visibility)
+                    };
+
+                    // 並びが変わるときだけ、列全体のシグナルに書き込む。
+                    match diff {
+                        ColumnDiff::Reset(xs) => {
+                            for row in rows.rows.values() {
+                                row.props.manually_drop();
+                            }
+                            rows = Rows::default();
+                            for x in &xs {
+                                rows.order.push(x.uri.clone());
+                                rows.rows.insert(
+                                    x.uri.clone(),
+                                    Row {
+                                        branches: x.branches.clone(),
+                                        props: Signal::new(make(x)),
+                                    },
+                                );
+                            }
+                            notes.set(rows.signals());
+                            rows.refresh_branch_fragments(&props.lanes, 0, None, false);
+                        }
+                        ColumnDiff::Insert(i, x) => {
+                            if rows.rows.contains_key(&x.uri) {
+                                continue;
+                            }
+                            let i = i.min(rows.order.len());
+                            let row = Row {
+                                branches: x.branches.clone(),
+                                props: Signal::new(make(&x)),
+                            };
+                            notes.write().insert(i, row.props);
+                            rows.order.insert(i, x.uri.clone());
+                            rows.rows.insert(x.uri.clone(), row);
+                            rows.refresh_branch_fragments(&props.lanes, i, Some(x.branches), true);
+                        }
+                        ColumnDiff::Update(x) => {
+                            let Some(row) = rows.rows.get_mut(&x.uri) else {
+                                continue;
+                            };
+
+                            // ブランチが変わらなければ、その行だけを描き直す。
+                            let mut note = make(&x);
+                            if row.branches == x.branches {
+                                note.branch_fragments = row.props.peek().branch_fragments.clone();
+                                row.props.set(note);
+                                continue;
+                            }
+
+                            let changed = &row.branches ^ &x.branches;
+                            row.branches = x.branches;
+                            row.props.set(note);
+                            let Some(i) = rows.order.iter().position(|y| y == &x.uri) else {
+                                continue;
+                            };
+                            rows.refresh_branch_fragments(&props.lanes, i, Some(changed), true);
+                        }
+                        ColumnDiff::Remove(uri) => {
+                            let Some(row) = rows.rows.remove(&uri) else {
+                                continue;
+                            };
+                            row.props.manually_drop();
+                            let Some(i) = rows.order.iter().position(|y| y == &uri) else {
+                                continue;
+                            };
+                            rows.order.remove(i);
+                            notes.write().remove(i);
+                            rows.refresh_branch_fragments(
+                                &props.lanes,
+                                i,
+                                Some(row.branches),
+                                false,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    });
+
+    let loader = use_coroutine({
+        let filter = props.filter.clone();
+        move |mut rx: UnboundedReceiver<()>| async move {
+            let mut pager = get_app_model().read().await.pager(filter.as_ref());
+            exhausted.set(pager.is_exhausted());
+
+            while rx.next().await.is_some() {
+                // ページャーを作った後に繋いだり切ったりしたチャンネルを反映する。
+                let sources = get_app_model().read().await.pager_sources(filter.as_ref());
+                pager.refresh(sources);
+                if pager.is_exhausted() {
+                    exhausted.set(true);
+                    continue;
+                }
+                loading.set(true);
+                pager.load_older().await;
+                loading.set(false);
+                exhausted.set(pager.is_exhausted());
+
+                // 読み込み中に溜まった要求は捨てる。
+                while let Ok(Some(())) = rx.try_next() {}
+            }
+        }
+    });
+
+    let onscroll = move |_| async move {
+        if loading() || exhausted() {
+            return;
+        }
+        let (Some(body), Some(footer)) = (body(), footer()) else {
+            return;
+        };
+        let (Ok(body), Ok(footer)) = (body.get_client_rect().await, footer.get_client_rect().await)
+        else {
+            return;
+        };
+        if footer.min_y() - body.max_y() < LOAD_OLDER_THRESHOLD_PX {
+            loader.send(());
+        }
+    };
+
+    rsx! {
+        section { class: "timeline-column",
+            header { class: "column-header", "{props.name}" }
+            div {
+                class: "column-body",
+                onmounted: move |e| body.set(Some(e.data())),
+                onscroll,
+                Column { notes }
+                footer {
+                    class: "column-footer",
+                    onmounted: move |e| footer.set(Some(e.data())),
+                    if exhausted() {
+                        "これ以上ノートはありません"
+                    } else if loading() {
+                        "読み込み中…"
+                    } else {
+                        button { onclick: move |_| loader.send(()), "さらに読み込む" }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// `branch_fragments` は `refresh_branch_fragments` で埋める。
+pub fn make_note_prop(x: &DynNoteModel, media_visibility: MediaVisibility) -> NoteProps {
+    let renote_header;
+    let main_note;
+    if let Some(renote) = &x.mi_note.renote {
+        renote_header = Some(&x.mi_note);
+        main_note = renote.deref();
+    } else {
+        renote_header = None;
+        main_note = &x.mi_note;
+    }
+
+    NoteProps {
+        original_host: x.original_host.clone(),
+        source_host: x.source_host.clone(),
+        uri: x.uri.clone(),
+        avatar_url: local_url(&main_note.user.avatar_url),
+        avatar_placeholder: main_note
+            .user
+            .avatar_blurhash
+            .as_deref()
+            .and_then(placeholder),
+        user_name: main_note
+            .user
+            .name
+            .clone()
+            .unwrap_or(main_note.user.username.clone()),
+        note_info: format!(
+            "{} {:?} {:?}",
+            from_now(&main_note.created_at),
+            main_note.visibility,
+            main_note.local_only
+        ),
+        cw: main_note.cw.clone(),
+        text: main_note.text.clone().unwrap_or("".to_owned()),
+        files: main_note
+            .files
+            .iter()
+            .map(|x| FileProps {
+                kind: FileKind::from_mime(&x.type_),
+                url: x.url.clone(),
+                thumbnail_url: x.thumbnail_url.as_deref().map(local_url),
+                placeholder: x.blur_hash.as_deref().and_then(placeholder),
+                aspect_ratio: x
+                    .properties
+                    .width
+                    .zip(x.properties.height)
+                    .map(|(w, h)| format!("{w} / {h}")),
+                sensitive: media_visibility.hides(x),
+                name: x.name.clone(),
+                size: x.size,
+                comment: x.comment.clone(),
+            })
+            .collect(),
+        reactions: x.reactions.clone(),
+        // 作者が別のサーバーにいれば、このアカウントからはリモートとしてリアクションする。
+        reaction_limit: main_note
+            .reaction_acceptance
+            .and_then(|r| r.limit(main_note.user.host.is_some())),
+        branch_fragments: Vec::new(),
+        renote: renote_header.map(|x| RenoteInfo {
+            avatar_url: local_url(&x.user.avatar_url),
+            avatar_placeholder: x.user.avatar_blurhash.as_deref().and_then(placeholder),
+            user_name: x.user.name.clone().unwrap_or(x.user.username.clone()),
+        }),
+        debug: cfg!(debug_assertions)
+            .then_some(serde_json::to_string_pretty(&x).unwrap_or("parse error".to_string())),
+    }
+}
+
+// 列に並んでいる行。`uri` から行を引けるようにしておく。
+#[derive(Default)]
+struct Rows {
+    order: Vec<String>,
+    rows: HashMap<String, Row>,
+}
+
+struct Row {
+    branches: HashSet<BranchKey>,
+
+    // 行ごとのシグナル。書き換えた行だけが描き直される。
+    props: Signal<NoteProps>,
+}
+
+impl Rows {
+    fn signals(&self) -> Vec<Signal<NoteProps>> {
+        self.order.iter().map(|x| self.rows[x].props).collect()
+    }
+
+    // 線の描き方は上にある行に依存するので、`from` から下を描き直す。
+    // `changed` は増えたか減ったブランチ。それを持つ行を過ぎれば、その下は変わらない。
+    // `None` なら最後まで描き直す。`changed_row` なら `from` の行自身がブランチの変わった行。
+    fn refresh_branch_fragments(
+        &self,
+        lanes: &[(BranchKey, String)],
+        from: usize,
+        changed: Option<HashSet<BranchKey>>,
+        changed_row: bool,
+    ) {
+        let mut branch_trace: HashSet<&BranchKey> = self.order[..from]
+            .iter()
+            .flat_map(|x| &self.rows[x].branches)
+            .collect();
+        let mut pending: Option<HashSet<&BranchKey>> = changed.as_ref().map(|changed| {
+            lanes
+                .iter()
+                .map(|(x, _)| x)
+                .filter(|x| changed.contains(x) && !branch_trace.contains(x))
+                .collect()
+        });
+
+        for (j, uri) in self.order.iter().enumerate().skip(from) {
+            if j > from && pending.as_ref().is_some_and(HashSet::is_empty) {
+                break;
+            }
+
+            let row = &self.rows[uri];
+            let fragments: Vec<_> = lanes
+                .iter()
+                .map(|(y, color)| BranchFragment {
+                    color: color.clone(),
+                    view: if branch_trace.contains(y) {
+                        if row.branches.contains(y) {
+                            BranchFragmentView::Full
+                        } else {
+                            BranchFragmentView::Skip
+                        }
+                    } else if row.branches.contains(y) {
+                        BranchFragmentView::Top
+                    } else {
+                        BranchFragmentView::None
+                    },
+                })
+                .collect();
+
+            let mut props = row.props;
+            if props.peek().branch_fragments != fragments {
+                props.write().branch_fragments = fragments;
+            }
+            branch_trace.extend(&row.branches);
+            if let Some(pending) = pending.as_mut().filter(|_| !(changed_row && j == from)) {
+                pending.retain(|x| !row.branches.contains(*x));
+            }
+        }
+    }
+}
+
+fn placeholder(blurhash: &str) -> Option<String> {
+    get_blurhash_decoder().data_uri(blurhash)
+}
+
+fn from_now(t: &DateTime<chrono::Utc>) -> String {
+    let dur = Utc::now() - t;
+    let neg = dur < chrono::TimeDelta::zero();
+    let dur = dur.abs();
+    let s = if dur.subsec_nanos() >= 500 {
+        dur.num_seconds() + 1
+    } else {
+        dur.num_seconds()
+    };
+
+    if s < 45 {
+        if neg {
+            format!("{:}秒後", s)
+        } else {
+            format!("{}秒前", s)
+        }
+    } else if s < 45 * 60 {
+        let m = (s as f64 / 60.0).round();
+        if neg {
+            format!("{m}分後")
+        } else {
+            format!("{m}分前")
+        }
+    } else if s < 22 * 60 * 60 {
+        let h = (s as f64 / (60.0 * 60.0)).round();
+        if neg {
+            format!("{h}時間後")
+        } else {
+            format!("{h}時間前")
+        }
+    } else {
+        let d = (s as f64 / (60.0 * 60.0 * 24.0)).round();
+        if neg {
+            format!("{d}日後")
+        } else {
+            format!("{d}日前")
+        }
+    }
+}
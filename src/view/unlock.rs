@@ -0,0 +1,65 @@
+use dioxus::prelude::*;
+use zeroize::Zeroizing;
+
+use crate::global_state::get_credential_store;
+
+#[derive(Clone, PartialEq, Props)]
+pub struct UnlockProps {
+    pub onunlock: EventHandler<()>,
+}
+
+#[component]
+pub fn Unlock(props: UnlockProps) -> Element {
+    let mut passphrase = use_signal(String::new);
+    let mut error = use_signal(|| None::<String>);
+
+    let mut unlocking = use_signal(|| false);
+
+    // Argon2 で鍵を導くのは重いので、画面を止めないように別のスレッドで開く。
+    let mut unlock = move || {
+        if unlocking() {
+            return;
+        }
+        unlocking.set(true);
+        let entered = Zeroizing::new(passphrase.replace(String::new()));
+
+        spawn(async move {
+            let result =
+                tokio::task::spawn_blocking(move || get_credential_store().unlock(&entered)).await;
+            unlocking.set(false);
+            match result {
+                Ok(Ok(())) => props.onunlock.call(()),
+                Ok(Err(e)) => error.set(Some(e.to_string())),
+                Err(e) => error.set(Some(e.to_string())),
+            }
+        });
+    };
+
+    rsx! {
+        div { class: "unlock",
+            h1 { "パスフレーズ" }
+            p {
+                "API キーを保存したファイルを開きます。"
+                br {}
+                code { "{get_credential_store().path().display()}" }
+            }
+            div { class: "unlock-form",
+                input {
+                    r#type: "password",
+                    autofocus: true,
+                    value: "{passphrase}",
+                    oninput: move |e| passphrase.set(e.value()),
+                    onkeydown: move |e| {
+                        if e.key() == Key::Enter {
+                            unlock();
+                        }
+                    }
+                }
+                button { disabled: unlocking(), onclick: move |_| unlock(), "開く" }
+            }
+            if let Some(e) = error() {
+                p { class: "unlock-error", "{e}" }
+            }
+        }
+    }
+}
@@ -18,6 +18,18 @@ impl WsMsgRouter {
             .extend(branches.into_iter());
     }
 
+    pub fn replace(&mut self, channel_id: String, branches: HashSet<BranchKey>) {
+        self.channel_id_to_branches.insert(channel_id, branches);
+    }
+
+    pub fn remove(&mut self, channel_id: &str) {
+        self.channel_id_to_branches.remove(channel_id);
+    }
+
+    pub fn contains(&self, channel_id: &str) -> bool {
+        self.channel_id_to_branches.contains_key(channel_id)
+    }
+
     pub fn solve_branches(&self, channel_id: &str) -> HashSet<BranchKey> {
         self.channel_id_to_branches
             .get(channel_id)
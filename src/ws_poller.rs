@@ -1,21 +1,26 @@
 use std::sync::Arc;
 
 use tokio::sync::{mpsc::error::TryRecvError, RwLock};
+use tracing::warn;
 
 use crate::{
     common_types::{Host, NoteModel},
+    connection_status::{ConnectionStatus, ConnectionStatuses},
+    global_state::get_emoji_service,
     mi_models::{NoteUpdatedBody, WsMsg, WsMsgChannelBody},
-    server_cxn::ServerCxn,
     server_note_repo::ServerNoteRepo,
+    server_source::ServerStream,
     ws_msg_router::WsMsgRouter,
 };
 
 #[derive(Debug)]
 pub struct WsPoller {
     pub repo: Arc<RwLock<ServerNoteRepo>>,
-    pub cxn: Arc<RwLock<ServerCxn>>,
-    pub router: WsMsgRouter,
+    pub cxn: Arc<RwLock<Box<dyn ServerStream>>>,
+    pub router: Arc<RwLock<WsMsgRouter>>,
     pub host: Host,
+    pub account: String,
+    pub statuses: ConnectionStatuses,
 }
 
 impl WsPoller {
@@ -34,14 +39,23 @@ impl WsPoller {
                 WsMsg::Channel(WsMsgChannelBody::Note { id: ch_id, body }) => {
                     let note_id = body.id.clone();
 
-                    self.repo
+                    // 切断したチャンネルから、行き違いで届いたノートは捨てる。
+                    let router = self.router.read().await;
+                    if !router.contains(&ch_id) {
+                        continue;
+                    }
+                    let branches = router.solve_branches(&ch_id);
+                    drop(router);
+
+                    let upserted = self
+                        .repo
                         .write()
                         .await
-                        .upsert(
-                            NoteModel::from_mi_model(body, self.host.clone()),
-                            self.router.solve_branches(&ch_id),
-                        )
-                        .expect("TODO: handle error");
+                        .upsert(NoteModel::from_mi_model(body, self.host.clone()), branches);
+                    if let Err(e) = upserted {
+                        self.statuses.degrade(&self.account, &e);
+                        continue;
+                    }
 
                     self.cxn.write().await.subscribe_note(&note_id);
                 }
@@ -49,6 +63,10 @@ impl WsPoller {
                     id: note_id,
                     body,
                 }) => {
+                    if let Some(emoji) = &body.emoji {
+                        get_emoji_service().insert_from_reaction(emoji, &self.host);
+                    }
+
                     self.repo
                         .write()
                         .await
@@ -56,5 +74,14 @@ impl WsPoller {
                 }
             }
         }
+
+        // 設定から消して閉じたときは、何もしない。
+        if !self.cxn.read().await.is_closed() {
+            warn!("{} closed the connection", self.host);
+            self.statuses.set(
+                &self.account,
+                ConnectionStatus::Failed("サーバーが接続を閉じました".to_owned()),
+            );
+        }
     }
 }